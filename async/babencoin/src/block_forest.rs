@@ -1,10 +1,11 @@
-use crate::data::{
-    BlockHash, TransactionHash, VerifiedBlock, VerifiedTransaction, WalletId, HASH_LEN,
+use crate::{
+    block_store::{BlockStore, StorageConfig},
+    data::{BlockHash, TransactionHash, VerifiedBlock, VerifiedTransaction, WalletId, HASH_LEN},
 };
 
 use anyhow::{bail, Context, Result};
 use chrono::Duration;
-use log::debug;
+use log::{debug, info, warn};
 use num_bigint::BigUint;
 
 use std::{
@@ -28,6 +29,7 @@ pub struct BlockForest {
     balance_snapshots: HashMap<BlockHash, HashMap<WalletId, u64>>,
     pending_transactions: HashMap<TransactionHash, VerifiedTransaction>,
     pending_snapshot: HashMap<WalletId, u64>,
+    store: Option<BlockStore>,
 }
 
 impl Default for BlockForest {
@@ -49,6 +51,7 @@ impl Default for BlockForest {
             balance_snapshots,
             pending_transactions: HashMap::new(),
             pending_snapshot: HashMap::new(),
+            store: None,
         }
     }
 }
@@ -58,6 +61,36 @@ impl BlockForest {
         Self::default()
     }
 
+    /// Load the blocks kept in the storage, replaying them through `add_block`.
+    /// The blocks added afterwards are appended to it.
+    pub fn open(config: &StorageConfig) -> Result<Self> {
+        let mut forest = Self::new();
+        let path = match &config.path {
+            Some(path) => path,
+            None => return Ok(forest),
+        };
+
+        let mut store = BlockStore::open(path)
+            .with_context(|| format!("failed to open block store at {}", path.display()))?;
+        let blocks = store.blocks().context("failed to read stored blocks")?;
+        let block_count = blocks.len();
+        for block in blocks {
+            let hash = block.compute_hash();
+            if let Err(err) = block.verified().and_then(|block| forest.add_block(block)) {
+                warn!("skipping stored block {}: {:#}", base64::encode(hash), err);
+            }
+        }
+        info!(
+            "loaded {} blocks from {}, head is at index {}",
+            block_count,
+            path.display(),
+            forest.head.index
+        );
+
+        forest.store = Some(store);
+        Ok(forest)
+    }
+
     pub fn head(&self) -> &Arc<VerifiedBlock> {
         &self.head
     }
@@ -74,6 +107,47 @@ impl BlockForest {
         self.blocks.get(hash)
     }
 
+    /// Find the block with the given index on the chain ending at the head.
+    pub fn find_block_by_index(&self, index: u64) -> Option<&Arc<VerifiedBlock>> {
+        if index > self.head.index {
+            return None;
+        }
+        let mut block = &self.head;
+        while block.index > index {
+            block = &self.blocks[&block.prev_hash];
+        }
+        Some(block)
+    }
+
+    /// Find a transaction on the chain ending at the head, along with its block,
+    /// or among the pending transactions.
+    pub fn find_transaction(
+        &self,
+        hash: &TransactionHash,
+    ) -> Option<(&VerifiedTransaction, Option<&Arc<VerifiedBlock>>)> {
+        if let Some(tx) = self.pending_transactions.get(hash) {
+            return Some((tx, None));
+        }
+        let mut block = &self.head;
+        loop {
+            if let Some(tx) = block.transactions().iter().find(|tx| tx.hash() == hash) {
+                return Some((tx, Some(block)));
+            }
+            if block.index == 0 {
+                return None;
+            }
+            block = &self.blocks[&block.prev_hash];
+        }
+    }
+
+    /// Balance of `wallet` as of the head block.
+    pub fn balance(&self, wallet: &WalletId) -> u64 {
+        self.balance_snapshots[self.head.hash()]
+            .get(wallet)
+            .copied()
+            .unwrap_or(0)
+    }
+
     pub fn next_max_hash(&self) -> BlockHash {
         let next_index = self.head.index + 1;
         if next_index % EPOCH_SIZE as u64 > 0 {
@@ -104,6 +178,14 @@ impl BlockForest {
             return Ok(());
         }
 
+        // Persist the block before touching the forest, so that they agree if it fails.
+        // Blocks failing the context checks are not kept, the others are checked again on load.
+        if self.store.is_some() && self.validate_block(&block).is_ok() {
+            if let Some(store) = &mut self.store {
+                store.append(&block).context("failed to persist block")?;
+            }
+        }
+
         self.unknown_block_hashes.remove(block.hash());
 
         let block_arc = Arc::new(block.clone());
@@ -0,0 +1,262 @@
+use crate::data::{Block, BlockHash, VerifiedBlock, HASH_LEN};
+
+use anyhow::{bail, Context, Result};
+use byteorder::{ByteOrder, LittleEndian, WriteBytesExt};
+use log::*;
+use serde::{Deserialize, Serialize};
+use sha3::{Digest, Sha3_256};
+
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    io::{self, BufReader, ErrorKind, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+////////////////////////////////////////////////////////////////////////////////
+
+const DATA_FILE_NAME: &str = "blocks.dat";
+const INDEX_FILE_NAME: &str = "blocks.idx";
+
+/// Payload length (u32) followed by the payload checksum (u64).
+const RECORD_HEADER_SIZE: usize = 12;
+/// Longer records are refused, so that a broken length is told apart from a valid one.
+const MAX_RECORD_SIZE: usize = 1 << 20;
+
+/// Block hash followed by the record offset (u64).
+const INDEX_ENTRY_SIZE: usize = HASH_LEN + 8;
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Default, Serialize, Deserialize)]
+pub struct StorageConfig {
+    /// Directory with the block files. Blocks are only kept in memory if not set.
+    pub path: Option<PathBuf>,
+}
+
+/// Append-only log of blocks as json records, with an index of hash → record offset.
+///
+/// Every record is synced to disk before its index entry is written, so after a crash
+/// only the tails of the files can be broken. They are cut off or rebuilt on open.
+pub struct BlockStore {
+    data: File,
+    index: File,
+    offsets: HashMap<BlockHash, u64>,
+    data_len: u64,
+}
+
+impl BlockStore {
+    pub fn open(path: &Path) -> Result<Self> {
+        fs::create_dir_all(path).with_context(|| format!("failed to create {}", path.display()))?;
+
+        let open_file = |name: &str| {
+            let file_path = path.join(name);
+            OpenOptions::new()
+                .read(true)
+                .append(true)
+                .create(true)
+                .open(&file_path)
+                .with_context(|| format!("failed to open {}", file_path.display()))
+        };
+        let mut data = open_file(DATA_FILE_NAME)?;
+        let mut index = open_file(INDEX_FILE_NAME)?;
+
+        let mut index_bytes = Vec::new();
+        index
+            .read_to_end(&mut index_bytes)
+            .context("failed to read block index")?;
+        let mut entries: Vec<_> = index_bytes
+            .chunks_exact(INDEX_ENTRY_SIZE)
+            .map(|entry| {
+                let mut hash = [0u8; HASH_LEN];
+                hash.copy_from_slice(&entry[..HASH_LEN]);
+                (hash, LittleEndian::read_u64(&entry[HASH_LEN..]))
+            })
+            .collect();
+        let indexed_count = entries.len();
+
+        // The index is written after the data, so only its tail may be missing or point
+        // past the end of the data. Start over from the last entry that checks out.
+        let data_file_len = data.metadata()?.len();
+        let mut scan_from = 0;
+        while let Some(&(hash, offset)) = entries.last() {
+            if offset < data_file_len {
+                data.seek(SeekFrom::Start(offset))?;
+                if let Some(block) = read_record(&mut data)? {
+                    if block.compute_hash() == hash {
+                        scan_from = data.stream_position()?;
+                        break;
+                    }
+                }
+            }
+            entries.pop();
+        }
+
+        let mut reader = BufReader::new(&mut data);
+        reader.seek(SeekFrom::Start(scan_from))?;
+        let mut data_len = scan_from;
+        while let Some(block) = read_record(&mut reader)? {
+            entries.push((block.compute_hash(), data_len));
+            data_len = reader.stream_position()?;
+        }
+
+        if data_len < data_file_len {
+            warn!(
+                "discarding {} bytes of a broken record at the end of the block store",
+                data_file_len - data_len
+            );
+            data.set_len(data_len)
+                .context("failed to truncate block data")?;
+            data.sync_data()?;
+        }
+
+        if entries.len() != indexed_count || index_bytes.len() % INDEX_ENTRY_SIZE > 0 {
+            debug!(
+                "rebuilding block index: {} entries, {} were indexed",
+                entries.len(),
+                indexed_count
+            );
+            index.set_len(0).context("failed to truncate block index")?;
+            let mut buffer = Vec::with_capacity(entries.len() * INDEX_ENTRY_SIZE);
+            for (hash, offset) in entries.iter() {
+                write_index_entry(&mut buffer, hash, *offset)?;
+            }
+            index
+                .write_all(&buffer)
+                .context("failed to write block index")?;
+        }
+
+        Ok(Self {
+            data,
+            index,
+            offsets: entries.into_iter().collect(),
+            data_len,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.offsets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.offsets.is_empty()
+    }
+
+    pub fn contains(&self, hash: &BlockHash) -> bool {
+        self.offsets.contains_key(hash)
+    }
+
+    /// Append `block` unless it is already stored.
+    pub fn append(&mut self, block: &VerifiedBlock) -> Result<()> {
+        if self.contains(block.hash()) {
+            return Ok(());
+        }
+
+        let payload = serde_json::to_vec(&block.to_block())?;
+        if payload.len() > MAX_RECORD_SIZE {
+            bail!(
+                "block record is too large: {} bytes, at most {} are allowed",
+                payload.len(),
+                MAX_RECORD_SIZE
+            );
+        }
+        let mut record = Vec::with_capacity(RECORD_HEADER_SIZE + payload.len());
+        record.write_u32::<LittleEndian>(payload.len() as u32)?;
+        record.write_u64::<LittleEndian>(checksum(&payload))?;
+        record.extend_from_slice(&payload);
+
+        let offset = self.data_len;
+        if let Err(err) = self
+            .data
+            .write_all(&record)
+            .and_then(|_| self.data.sync_data())
+        {
+            // Do not leave a partial record behind to append after.
+            self.data.set_len(offset).ok();
+            return Err(err).context("failed to write block record");
+        }
+        self.data_len += record.len() as u64;
+
+        let mut entry = Vec::with_capacity(INDEX_ENTRY_SIZE);
+        write_index_entry(&mut entry, block.hash(), offset)?;
+        self.index
+            .write_all(&entry)
+            .context("failed to write block index")?;
+
+        self.offsets.insert(*block.hash(), offset);
+        Ok(())
+    }
+
+    pub fn read(&mut self, hash: &BlockHash) -> Result<Option<Block>> {
+        let offset = match self.offsets.get(hash) {
+            Some(offset) => *offset,
+            None => return Ok(None),
+        };
+        self.data.seek(SeekFrom::Start(offset))?;
+        match read_record(&mut self.data)? {
+            Some(block) => Ok(Some(block)),
+            None => bail!("broken block record at offset {}", offset),
+        }
+    }
+
+    /// All the stored blocks, in the order they were appended.
+    pub fn blocks(&mut self) -> Result<Vec<Block>> {
+        self.data.seek(SeekFrom::Start(0))?;
+        let mut reader = BufReader::new(&mut self.data);
+        let mut blocks = Vec::with_capacity(self.offsets.len());
+        loop {
+            let offset = reader.stream_position()?;
+            if offset >= self.data_len {
+                break;
+            }
+            match read_record(&mut reader)? {
+                Some(block) => blocks.push(block),
+                None => bail!("broken block record at offset {}", offset),
+            }
+        }
+        Ok(blocks)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+fn checksum(payload: &[u8]) -> u64 {
+    LittleEndian::read_u64(&Sha3_256::digest(payload)[..8])
+}
+
+fn write_index_entry(writer: &mut impl Write, hash: &BlockHash, offset: u64) -> io::Result<()> {
+    writer.write_all(hash)?;
+    writer.write_u64::<LittleEndian>(offset)
+}
+
+/// Read the record at the current position. Returns `None` if it is incomplete or
+/// its checksum does not match, which is what a write interrupted by a crash leaves.
+fn read_record(reader: &mut impl Read) -> Result<Option<Block>> {
+    fn read_exact_or_eof(reader: &mut impl Read, buffer: &mut [u8]) -> io::Result<bool> {
+        match reader.read_exact(buffer) {
+            Ok(()) => Ok(true),
+            Err(err) if err.kind() == ErrorKind::UnexpectedEof => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+
+    let mut header = [0u8; RECORD_HEADER_SIZE];
+    if !read_exact_or_eof(reader, &mut header)? {
+        return Ok(None);
+    }
+    let len = LittleEndian::read_u32(&header[..4]) as usize;
+    if len > MAX_RECORD_SIZE {
+        return Ok(None);
+    }
+
+    let mut payload = vec![0u8; len];
+    if !read_exact_or_eof(reader, &mut payload)? {
+        return Ok(None);
+    }
+    if checksum(&payload) != LittleEndian::read_u64(&header[4..]) {
+        return Ok(None);
+    }
+
+    let block = serde_json::from_slice(&payload).context("failed to deserialize block")?;
+    Ok(Some(block))
+}
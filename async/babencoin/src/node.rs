@@ -1,16 +1,22 @@
 mod gossip_service;
 mod mining_service;
 mod peer_service;
+mod rpc_service;
 
+use crate::{block_forest::BlockForest, block_store::StorageConfig};
 use gossip_service::{GossipService, GossipServiceConfig};
 use mining_service::{MiningService, MiningServiceConfig};
 use peer_service::{PeerService, PeerServiceConfig};
+use rpc_service::{RpcService, RpcServiceConfig};
 
 use anyhow::{Context, Result};
 use crossbeam::channel;
 use serde::{Deserialize, Serialize};
 
-use std::thread;
+use std::{
+    sync::{Arc, RwLock},
+    thread,
+};
 
 ////////////////////////////////////////////////////////////////////////////////
 
@@ -19,6 +25,10 @@ pub struct Config {
     pub peer_service: PeerServiceConfig,
     pub gossip_service: GossipServiceConfig,
     pub mining_service: MiningServiceConfig,
+    #[serde(default)]
+    pub storage: StorageConfig,
+    #[serde(default)]
+    pub rpc_service: RpcServiceConfig,
 }
 
 pub fn run_forever(config: Config) -> Result<()> {
@@ -26,17 +36,38 @@ pub fn run_forever(config: Config) -> Result<()> {
     let (command_sender, command_receiver) = channel::bounded(1000);
     let (block_sender, block_receiver) = channel::bounded(1000);
     let (mining_info_sender, mining_info_receiver) = channel::bounded(1000);
+    let (transaction_sender, transaction_receiver) = channel::bounded(1000);
 
     let mut peer_service =
         PeerService::new(config.peer_service, peer_event_sender, command_receiver)
             .context("failed to create peer service")?;
 
+    let block_forest =
+        BlockForest::open(&config.storage).context("failed to load block storage")?;
+    let block_forest = Arc::new(RwLock::new(block_forest));
+
+    if config.rpc_service.listen_address.is_some() {
+        let mut rpc_service = RpcService::new(
+            config.rpc_service,
+            Arc::clone(&block_forest),
+            transaction_sender,
+        )
+        .context("failed to create rpc service")?;
+
+        thread::spawn(move || {
+            rpc_service.run();
+            panic!("rpc service terminated");
+        });
+    }
+
     let mut gossip_service = GossipService::new(
         config.gossip_service,
+        block_forest,
         peer_event_receiver,
         command_sender,
         block_receiver,
         mining_info_sender,
+        transaction_receiver,
     );
 
     let mut mining_service =
@@ -3,6 +3,7 @@ use crate::{
     data::{BlockHash, TransactionHash, VerifiedBlock, VerifiedPeerMessage, VerifiedTransaction},
     node::mining_service::MiningInfo,
     node::peer_service::{PeerCommand, PeerCommandKind, PeerEvent, PeerEventKind, SessionId},
+    util::ignore_poison,
 };
 
 use anyhow::{anyhow, Context, Result};
@@ -19,7 +20,7 @@ use crate::data::PeerMessage::Transaction;
 use crate::data::VerifiedPeerMessage::{Block, Request};
 use crossbeam::channel::RecvError;
 use std::ops::Deref;
-use std::sync::{Arc, LockResult, RwLock, RwLockReadGuard};
+use std::sync::{Arc, RwLock, RwLockReadGuard};
 use std::{
     collections::{HashMap, HashSet},
     thread,
@@ -40,6 +41,7 @@ pub struct GossipService {
     command_sender: Sender<PeerCommand>,
     block_receiver: Receiver<VerifiedBlock>,
     mining_info_sender: Sender<MiningInfo>,
+    transaction_receiver: Receiver<VerifiedTransaction>,
     block_forest: Arc<RwLock<BlockForest>>,
     session_storage: Arc<RwLock<SessionStorage>>,
 }
@@ -61,10 +63,12 @@ impl Default for SessionStorage {
 impl GossipService {
     pub fn new(
         config: GossipServiceConfig,
+        block_forest: Arc<RwLock<BlockForest>>,
         event_receiver: Receiver<PeerEvent>,
         command_sender: Sender<PeerCommand>,
         block_receiver: Receiver<VerifiedBlock>,
         mining_info_sender: Sender<MiningInfo>,
+        transaction_receiver: Receiver<VerifiedTransaction>,
     ) -> Self {
         Self {
             config,
@@ -72,7 +76,8 @@ impl GossipService {
             command_sender,
             block_receiver,
             mining_info_sender,
-            block_forest: Arc::new(RwLock::new(BlockForest::new())),
+            transaction_receiver,
+            block_forest,
             session_storage: Arc::new(RwLock::new(SessionStorage::default())),
         }
     }
@@ -80,6 +85,7 @@ impl GossipService {
     pub fn run(&mut self) {
         let event_receiver = self.event_receiver.clone();
         let block_receiver = self.block_receiver.clone();
+        let mut transaction_receiver = self.transaction_receiver.clone();
 
         let mining_sender = self.mining_info_sender.clone();
         let command_sender = self.command_sender.clone();
@@ -94,6 +100,14 @@ impl GossipService {
                     msg,
                     Arc::clone(&block_forest),
                     Arc::clone(&session_storage)).unwrap(),
+                recv(transaction_receiver) -> msg => match msg {
+                    Ok(tx) => Self::handle_submitted_transaction(
+                        command_sender.clone(),
+                        tx,
+                        Arc::clone(&session_storage)).unwrap(),
+                    // Nothing is submitted without the rpc service.
+                    Err(RecvError) => transaction_receiver = channel::never(),
+                },
                 //todo: it is breaking test_simple
                 /*recv(block_receiver) -> msg => Self::handle_new_block_event_message(
                     command_sender.clone(),
@@ -114,7 +128,7 @@ impl GossipService {
         let mut block_forest = block_forest.write().expect("Failed to capture write lock");
         let validation_res = block_forest.add_block(verified_block.clone());
         if validation_res.is_ok() {
-            let mut session_storage = Self::ignore_poison(session_storage.write());
+            let mut session_storage = ignore_poison(session_storage.write());
             for (session, mut set) in session_storage.session_to_blocks.iter_mut() {
                 let peer_command = PeerCommand {
                     session_id: *session,
@@ -131,6 +145,28 @@ impl GossipService {
         validation_res
     }
 
+    // Transactions submitted over rpc are already added to the forest,
+    // so they only need to be forwarded to the peers.
+    fn handle_submitted_transaction(
+        peer_command_sender: Sender<PeerCommand>,
+        tx: VerifiedTransaction,
+        session_storage: Arc<RwLock<SessionStorage>>,
+    ) -> Result<()> {
+        let mut session_storage = ignore_poison(session_storage.write());
+        for (session_id, transaction_set) in session_storage.session_to_transactions.iter_mut() {
+            if transaction_set.insert(*tx.hash()) {
+                let peer_command = PeerCommand {
+                    session_id: *session_id,
+                    command_kind: PeerCommandKind::SendMessage(VerifiedPeerMessage::Transaction(
+                        Box::new(tx.clone()),
+                    )),
+                };
+                peer_command_sender.send(peer_command)?;
+            }
+        }
+        Ok(())
+    }
+
     fn handle_peer_event_message(
         peer_command_sender: Sender<PeerCommand>,
         peer_event_message: Result<PeerEvent, RecvError>,
@@ -186,7 +222,7 @@ impl GossipService {
         session_storage: Arc<RwLock<SessionStorage>>,
     ) -> Result<()> {
         {
-            let mut session_storage = Self::ignore_poison(session_storage.write());
+            let mut session_storage = ignore_poison(session_storage.write());
             session_storage
                 .session_to_blocks
                 .insert(session_id, HashSet::new());
@@ -195,7 +231,7 @@ impl GossipService {
                 .insert(session_id, HashSet::new());
         }
 
-        let block_forest = Self::ignore_poison(block_forest.read());
+        let block_forest = ignore_poison(block_forest.read());
         let head = block_forest.head();
         let pending = block_forest.pending_transactions();
         let head = PeerCommand {
@@ -225,7 +261,7 @@ impl GossipService {
         peer_command_sender: Sender<PeerCommand>,
     ) -> Result<()> {
         debug!("Gossip is sending disconnect!");
-        let mut session_storage = Self::ignore_poison(session_storage.write());
+        let mut session_storage = ignore_poison(session_storage.write());
         session_storage.session_to_blocks.remove(&session_id);
         session_storage.session_to_transactions.remove(&session_id);
         let peer_command = PeerCommand {
@@ -265,7 +301,7 @@ impl GossipService {
                     .send(peer_command)
                     .map_err(anyhow::Error::msg);
                 if send_res.is_ok() {
-                    let mut session_storage = Self::ignore_poison(session_storage.write());
+                    let mut session_storage = ignore_poison(session_storage.write());
                     let block_set = session_storage
                         .session_to_blocks
                         .get_mut(&session_id)
@@ -296,7 +332,7 @@ impl GossipService {
             .expect("Failed to capture write lock on forest!");
         let validation_res = block_forest.add_transaction(*tx.clone());
         if validation_res.is_ok() {
-            let mut session_storage = Self::ignore_poison(session_storage.write());
+            let mut session_storage = ignore_poison(session_storage.write());
             let transaction_set = session_storage
                 .session_to_transactions
                 .get_mut(&session_id)
@@ -348,7 +384,7 @@ impl GossipService {
         let mut block_forest = block_forest.write().expect("Failed to capture write lock");
         let validation_res = block_forest.add_block(*block.clone());
         if validation_res.is_ok() {
-            let mut session_storage = Self::ignore_poison(session_storage.write());
+            let mut session_storage = ignore_poison(session_storage.write());
             let block_set = session_storage
                 .session_to_blocks
                 .get_mut(&session_id)
@@ -368,12 +404,4 @@ impl GossipService {
         }
         validation_res
     }
-
-    // todo: what is lock poisoning and how to handle it?
-    fn ignore_poison<T>(lock_res: LockResult<T>) -> T {
-        match lock_res {
-            Ok(x) => x,
-            Err(e) => e.into_inner(),
-        }
-    }
 }
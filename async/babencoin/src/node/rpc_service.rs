@@ -0,0 +1,406 @@
+use crate::{
+    block_forest::BlockForest,
+    data::{
+        Block, BlockHash, Transaction, TransactionHash, VerifiedBlock, VerifiedTransaction,
+        WalletId, HASH_LEN,
+    },
+    util::{deserialize_base64_fixed, deserialize_wallet_id, ignore_poison},
+};
+
+use anyhow::{bail, Context, Result};
+use crossbeam::channel::{self, Sender};
+use log::*;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    sync::{Arc, RwLock},
+    thread,
+    time::Duration,
+};
+
+////////////////////////////////////////////////////////////////////////////////
+
+const MAX_HEADER_SIZE: u64 = 8192;
+const MAX_BODY_SIZE: usize = 65536;
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+const WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const TRANSACTION_REJECTED: i64 = -32000;
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub struct RpcServiceConfig {
+    /// Address of the JSON-RPC endpoint, which must be a loopback one.
+    /// The service is disabled if not set.
+    pub listen_address: Option<String>,
+    /// Number of connections served at once, the others wait until one is closed.
+    pub max_connections: usize,
+}
+
+impl Default for RpcServiceConfig {
+    fn default() -> Self {
+        Self {
+            listen_address: None,
+            max_connections: 16,
+        }
+    }
+}
+
+/// Answers JSON-RPC 2.0 requests, POSTed over HTTP to `/`, about the state of the chain.
+///
+/// Methods:
+/// * `get_head` - the head block;
+/// * `get_block` - the block by `hash`, or by `index` on the chain ending at the head;
+/// * `get_transaction` - the transaction by `hash`, with the hash of its block if any;
+/// * `get_balance` - the balance of `wallet` as of the head block;
+/// * `get_pending_transactions` - the transactions not in the chain yet;
+/// * `submit_transaction` - validate a transaction and forward it to the peers.
+pub struct RpcService {
+    listener: TcpListener,
+    max_connections: usize,
+    block_forest: Arc<RwLock<BlockForest>>,
+    transaction_sender: Sender<VerifiedTransaction>,
+}
+
+impl RpcService {
+    pub fn new(
+        config: RpcServiceConfig,
+        block_forest: Arc<RwLock<BlockForest>>,
+        transaction_sender: Sender<VerifiedTransaction>,
+    ) -> Result<Self> {
+        let address = config
+            .listen_address
+            .context("rpc service listen address is not set")?;
+        let addrs = address
+            .to_socket_addrs()
+            .with_context(|| format!("failed to resolve {}", address))?
+            .collect::<Vec<SocketAddr>>();
+        // There is no authentication, anyone reaching the endpoint may submit transactions.
+        if let Some(addr) = addrs.iter().find(|addr| !addr.ip().is_loopback()) {
+            bail!(
+                "rpc service must listen on a loopback address, not {}",
+                addr
+            );
+        }
+        let listener = TcpListener::bind(addrs.as_slice())
+            .with_context(|| format!("failed to bind {}", address))?;
+        if config.max_connections == 0 {
+            bail!("rpc service max_connections must be positive");
+        }
+        Ok(Self {
+            listener,
+            max_connections: config.max_connections,
+            block_forest,
+            transaction_sender,
+        })
+    }
+
+    pub fn run(&mut self) {
+        // Accepting waits for an idle worker, the connections beyond that queue in the backlog.
+        let (stream_sender, stream_receiver) = channel::bounded::<TcpStream>(0);
+        for _ in 0..self.max_connections {
+            let handler = RequestHandler {
+                block_forest: Arc::clone(&self.block_forest),
+                transaction_sender: self.transaction_sender.clone(),
+            };
+            let stream_receiver = stream_receiver.clone();
+            thread::spawn(move || {
+                for stream in stream_receiver {
+                    if let Err(err) = handler.serve(stream) {
+                        debug!("rpc connection failed: {:#}", err);
+                    }
+                }
+            });
+        }
+
+        for stream in self.listener.incoming() {
+            match stream {
+                Ok(stream) => stream_sender.send(stream).expect("rpc workers are gone"),
+                Err(err) => warn!("failed to accept rpc connection: {}", err),
+            }
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Deserialize)]
+struct RpcRequest {
+    jsonrpc: String,
+    #[serde(default)]
+    id: Value,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+#[derive(Serialize)]
+struct RpcResponse {
+    jsonrpc: &'static str,
+    id: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<RpcError>,
+}
+
+#[derive(Serialize)]
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl ToString) -> Self {
+        Self {
+            code,
+            message: message.to_string(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct BlockInfo {
+    hash: String,
+    #[serde(flatten)]
+    block: Block,
+}
+
+impl From<&VerifiedBlock> for BlockInfo {
+    fn from(block: &VerifiedBlock) -> Self {
+        Self {
+            hash: base64::encode(block.hash()),
+            block: block.to_block(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct TransactionInfo {
+    hash: String,
+    #[serde(flatten)]
+    transaction: Transaction,
+    /// The block containing the transaction, `null` while it is pending.
+    block_hash: Option<String>,
+}
+
+impl TransactionInfo {
+    fn new(tx: &VerifiedTransaction, block: Option<&VerifiedBlock>) -> Self {
+        Self {
+            hash: base64::encode(tx.hash()),
+            transaction: tx.clone().into(),
+            block_hash: block.map(|block| base64::encode(block.hash())),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum BlockParams {
+    Hash {
+        #[serde(deserialize_with = "deserialize_base64_fixed::<'_, _, HASH_LEN>")]
+        hash: BlockHash,
+    },
+    Index {
+        index: u64,
+    },
+}
+
+#[derive(Deserialize)]
+struct TransactionParams {
+    #[serde(deserialize_with = "deserialize_base64_fixed::<'_, _, HASH_LEN>")]
+    hash: TransactionHash,
+}
+
+#[derive(Deserialize)]
+struct BalanceParams {
+    #[serde(deserialize_with = "deserialize_wallet_id")]
+    wallet: WalletId,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+struct RequestHandler {
+    block_forest: Arc<RwLock<BlockForest>>,
+    transaction_sender: Sender<VerifiedTransaction>,
+}
+
+impl RequestHandler {
+    fn serve(&self, stream: TcpStream) -> Result<()> {
+        stream.set_read_timeout(Some(READ_TIMEOUT))?;
+        stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+        let mut reader = BufReader::new(&stream);
+        let (status, body) = match read_http_request(&mut reader)? {
+            Ok(body) => ("200 OK", self.handle(&body)),
+            Err(status) => (status, Vec::new()),
+        };
+        write_http_response(&stream, status, &body)
+    }
+
+    fn handle(&self, body: &[u8]) -> Vec<u8> {
+        let response = match serde_json::from_slice::<Value>(body) {
+            Err(err) => error_response(Value::Null, RpcError::new(PARSE_ERROR, err)),
+            Ok(value) => match serde_json::from_value::<RpcRequest>(value) {
+                Err(err) => error_response(Value::Null, RpcError::new(INVALID_REQUEST, err)),
+                Ok(request) if request.jsonrpc != "2.0" => error_response(
+                    request.id,
+                    RpcError::new(INVALID_REQUEST, "unsupported jsonrpc version"),
+                ),
+                Ok(request) => {
+                    debug!("rpc request: {}", request.method);
+                    match self.call(&request.method, request.params) {
+                        Ok(result) => RpcResponse {
+                            jsonrpc: "2.0",
+                            id: request.id,
+                            result: Some(result),
+                            error: None,
+                        },
+                        Err(err) => error_response(request.id, err),
+                    }
+                }
+            },
+        };
+        serde_json::to_vec(&response).expect("failed to serialize rpc response")
+    }
+
+    fn call(&self, method: &str, params: Value) -> Result<Value, RpcError> {
+        let forest = ignore_poison(self.block_forest.read());
+        let result = match method {
+            "get_head" => to_value(BlockInfo::from(forest.head().as_ref())),
+            "get_block" => {
+                let block = match parse_params(params)? {
+                    BlockParams::Hash { hash } => forest.find_block(&hash),
+                    BlockParams::Index { index } => forest.find_block_by_index(index),
+                };
+                to_value(block.map(|block| BlockInfo::from(block.as_ref())))
+            }
+            "get_transaction" => {
+                let TransactionParams { hash } = parse_params(params)?;
+                let tx = forest.find_transaction(&hash);
+                to_value(tx.map(|(tx, block)| TransactionInfo::new(tx, block.map(Arc::as_ref))))
+            }
+            "get_balance" => {
+                let BalanceParams { wallet } = parse_params(params)?;
+                to_value(forest.balance(&wallet))
+            }
+            "get_pending_transactions" => to_value(
+                forest
+                    .pending_transactions()
+                    .values()
+                    .map(|tx| TransactionInfo::new(tx, None))
+                    .collect::<Vec<_>>(),
+            ),
+            "submit_transaction" => {
+                drop(forest);
+                let tx: Transaction = parse_params(params)?;
+                let hash = self
+                    .submit_transaction(tx)
+                    .map_err(|err| RpcError::new(TRANSACTION_REJECTED, format!("{:#}", err)))?;
+                to_value(base64::encode(hash))
+            }
+            _ => {
+                return Err(RpcError::new(
+                    METHOD_NOT_FOUND,
+                    format!("unknown method {}", method),
+                ))
+            }
+        };
+        Ok(result)
+    }
+
+    fn submit_transaction(&self, tx: Transaction) -> Result<TransactionHash> {
+        let tx = tx.verified().context("transaction verification failed")?;
+        ignore_poison(self.block_forest.write()).add_transaction(tx.clone())?;
+        let hash = *tx.hash();
+        self.transaction_sender
+            .send(tx)
+            .context("gossip service is gone")?;
+        Ok(hash)
+    }
+}
+
+fn error_response(id: Value, error: RpcError) -> RpcResponse {
+    RpcResponse {
+        jsonrpc: "2.0",
+        id,
+        result: None,
+        error: Some(error),
+    }
+}
+
+fn parse_params<T: DeserializeOwned>(params: Value) -> Result<T, RpcError> {
+    serde_json::from_value(params).map_err(|err| RpcError::new(INVALID_PARAMS, err))
+}
+
+fn to_value<T: Serialize>(value: T) -> Value {
+    serde_json::to_value(value).expect("failed to serialize rpc result")
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Read a request, returning its body, or the status to reply with if it is not
+/// a JSON-RPC call.
+fn read_http_request(reader: &mut impl BufRead) -> Result<Result<Vec<u8>, &'static str>> {
+    let mut head = reader.by_ref().take(MAX_HEADER_SIZE);
+    let mut request_line = String::new();
+    head.read_line(&mut request_line)?;
+    let mut parts = request_line.split_whitespace();
+    let (method, path) = match (parts.next(), parts.next(), parts.next()) {
+        (Some(method), Some(path), Some(_version)) => (method.to_owned(), path.to_owned()),
+        _ => bail!("invalid request line: {:?}", request_line),
+    };
+
+    let mut content_length = None;
+    loop {
+        let mut line = String::new();
+        if head.read_line(&mut line)? == 0 {
+            bail!("unexpected end of headers");
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+
+    if path != "/" {
+        return Ok(Err("404 Not Found"));
+    }
+    if method != "POST" {
+        return Ok(Err("405 Method Not Allowed"));
+    }
+    let content_length = match content_length {
+        Some(len) if len > MAX_BODY_SIZE => return Ok(Err("413 Payload Too Large")),
+        Some(len) => len,
+        None => return Ok(Err("411 Length Required")),
+    };
+
+    let mut body = vec![0u8; content_length];
+    reader.read_exact(&mut body)?;
+    Ok(Ok(body))
+}
+
+fn write_http_response(mut stream: &TcpStream, status: &str, body: &[u8]) -> Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        body.len()
+    )?;
+    stream.write_all(body)?;
+    stream.flush()?;
+    Ok(())
+}
@@ -9,6 +9,8 @@ use serde::{
     Deserialize,
 };
 
+use std::sync::LockResult;
+
 ////////////////////////////////////////////////////////////////////////////////
 
 fn decode_pkcs8_plaintext(raw: &str) -> Result<Vec<u8>> {
@@ -115,3 +117,14 @@ where
         LocalResult::Ambiguous(_, _) => Err(de::Error::custom("ambiguous timestamp")),
     }
 }
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Take the guard even if another thread panicked while holding the lock. The services
+/// only change the shared state through methods that keep it consistent, so it stays usable.
+pub fn ignore_poison<T>(lock_res: LockResult<T>) -> T {
+    match lock_res {
+        Ok(x) => x,
+        Err(e) => e.into_inner(),
+    }
+}
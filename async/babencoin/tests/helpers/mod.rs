@@ -1,7 +1,10 @@
 #![allow(dead_code)]
 
 use babencoin::{
-    data::{Block, BlockHash, PeerMessage, HASH_LEN},
+    data::{
+        Block, BlockHash, PeerMessage, VerifiedBlock, VerifiedTransaction, WalletId, HASH_LEN,
+        MAX_REWARD,
+    },
     node,
 };
 
@@ -257,6 +260,22 @@ pub fn random_block(index: u64) -> Block {
     block
 }
 
+/// A block following `prev` ten minutes later, with `transactions` and the full reward to `issuer`.
+pub fn next_block(
+    prev: &VerifiedBlock,
+    issuer: &WalletId,
+    transactions: Vec<VerifiedTransaction>,
+) -> Block {
+    let mut block = Block::genesis();
+    block.index = prev.index + 1;
+    block.prev_hash = *prev.hash();
+    block.timestamp = prev.timestamp + chrono::Duration::minutes(10);
+    block.reward = MAX_REWARD;
+    block.issuer = issuer.clone();
+    block.transactions = transactions.into_iter().map(Into::into).collect();
+    block
+}
+
 pub fn generate_private_key() -> RSAPrivateKey {
     generate_multi_prime_key(&mut thread_rng(), 32, 1024).unwrap()
 }
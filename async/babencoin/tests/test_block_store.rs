@@ -0,0 +1,279 @@
+#[allow(unused_macros)]
+mod helpers;
+
+use helpers::{generate_private_key, next_block, random_block};
+
+use babencoin::{
+    block_forest::BlockForest,
+    block_store::{BlockStore, StorageConfig},
+    data::{Block, VerifiedBlock, VerifiedTransaction, WalletId},
+};
+
+use std::{
+    fs::{self, OpenOptions},
+    path::Path,
+};
+
+////////////////////////////////////////////////////////////////////////////////
+
+fn chain(len: usize) -> Vec<VerifiedBlock> {
+    let mut blocks = vec![VerifiedBlock::genesis()];
+    for _ in 0..len {
+        let prev = blocks.last().unwrap();
+        blocks.push(next_block(prev, &prev.issuer, vec![]).verified().unwrap());
+    }
+    blocks.split_off(1)
+}
+
+fn storage_config(path: &Path) -> StorageConfig {
+    StorageConfig {
+        path: Some(path.to_owned()),
+    }
+}
+
+fn truncate(path: &Path, len: u64) {
+    OpenOptions::new()
+        .write(true)
+        .open(path)
+        .unwrap()
+        .set_len(len)
+        .unwrap();
+}
+
+fn stored_blocks(path: &Path) -> Vec<Block> {
+    BlockStore::open(path).unwrap().blocks().unwrap()
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn test_store_reopen() {
+    let dir = tempfile::tempdir().unwrap();
+    let blocks: Vec<_> = (1..=3)
+        .map(|i| random_block(10 + i).verified().unwrap())
+        .collect();
+
+    let mut store = BlockStore::open(dir.path()).unwrap();
+    assert!(store.is_empty());
+    for block in blocks.iter() {
+        store.append(block).unwrap();
+    }
+    store.append(&blocks[0]).unwrap();
+    assert_eq!(store.len(), 3);
+    drop(store);
+
+    let mut store = BlockStore::open(dir.path()).unwrap();
+    assert_eq!(store.len(), 3);
+    for block in blocks.iter() {
+        assert!(store.contains(block.hash()));
+        assert_eq!(store.read(block.hash()).unwrap(), Some(block.to_block()));
+    }
+    assert_eq!(store.read(&[0u8; 64]).unwrap(), None);
+
+    let expected: Vec<_> = blocks.iter().map(|block| block.to_block()).collect();
+    assert_eq!(store.blocks().unwrap(), expected);
+}
+
+#[test]
+fn test_store_truncated_record() {
+    let dir = tempfile::tempdir().unwrap();
+    let data_path = dir.path().join("blocks.dat");
+    let blocks: Vec<_> = (1..=3)
+        .map(|i| random_block(10 + i).verified().unwrap())
+        .collect();
+
+    let mut store = BlockStore::open(dir.path()).unwrap();
+    store.append(&blocks[0]).unwrap();
+    store.append(&blocks[1]).unwrap();
+    drop(store);
+    let complete_len = fs::metadata(&data_path).unwrap().len();
+    let complete = fs::read(&data_path).unwrap();
+
+    let mut store = BlockStore::open(dir.path()).unwrap();
+    store.append(&blocks[2]).unwrap();
+    drop(store);
+    let full = fs::read(&data_path).unwrap();
+
+    // A crash in the middle of writing the third record, before its index entry.
+    let index_path = dir.path().join("blocks.idx");
+    let index = fs::read(&index_path).unwrap();
+    for len in (complete_len..full.len() as u64).step_by(37) {
+        fs::write(&data_path, &full).unwrap();
+        fs::write(&index_path, &index[..index.len() - 72]).unwrap();
+        truncate(&data_path, len);
+
+        let mut store = BlockStore::open(dir.path()).unwrap();
+        assert_eq!(store.len(), 2);
+        assert!(!store.contains(blocks[2].hash()));
+        assert_eq!(fs::read(&data_path).unwrap(), complete);
+
+        store.append(&blocks[2]).unwrap();
+        drop(store);
+        assert_eq!(fs::read(&data_path).unwrap(), full);
+        assert_eq!(stored_blocks(dir.path()).len(), 3);
+    }
+}
+
+#[test]
+fn test_store_corrupted_record() {
+    let dir = tempfile::tempdir().unwrap();
+    let data_path = dir.path().join("blocks.dat");
+    let mut store = BlockStore::open(dir.path()).unwrap();
+    store.append(&random_block(11).verified().unwrap()).unwrap();
+    store.append(&random_block(12).verified().unwrap()).unwrap();
+    drop(store);
+
+    // Garbage at the end of the last record, as left by a torn write.
+    let mut data = fs::read(&data_path).unwrap();
+    let len = data.len();
+    data[len - 10..].fill(0);
+    fs::write(&data_path, &data).unwrap();
+
+    let store = BlockStore::open(dir.path()).unwrap();
+    assert_eq!(store.len(), 1);
+    assert_eq!(stored_blocks(dir.path()).len(), 1);
+}
+
+#[test]
+fn test_store_large_block() {
+    let dir = tempfile::tempdir().unwrap();
+    let key = generate_private_key();
+    let comment = "x".repeat(2 << 20);
+    let tx = VerifiedTransaction::sign(&key, key.to_public_key().into(), 1, 0, comment).unwrap();
+    let mut large_block = random_block(11);
+    large_block.transactions = vec![tx.into()];
+    let blocks: Vec<_> = (2..=3)
+        .map(|i| random_block(10 + i).verified().unwrap())
+        .collect();
+
+    // A record too large to be told apart from a broken one is refused.
+    let mut store = BlockStore::open(dir.path()).unwrap();
+    store.append(&blocks[0]).unwrap();
+    assert!(store.append(&large_block.verified().unwrap()).is_err());
+    store.append(&blocks[1]).unwrap();
+    assert_eq!(store.len(), 2);
+    drop(store);
+
+    let store = BlockStore::open(dir.path()).unwrap();
+    assert_eq!(store.len(), 2);
+    for block in blocks.iter() {
+        assert!(store.contains(block.hash()));
+    }
+    let expected: Vec<_> = blocks.iter().map(|block| block.to_block()).collect();
+    assert_eq!(stored_blocks(dir.path()), expected);
+}
+
+#[test]
+fn test_store_index_recovery() {
+    let dir = tempfile::tempdir().unwrap();
+    let data_path = dir.path().join("blocks.dat");
+    let index_path = dir.path().join("blocks.idx");
+    let blocks: Vec<_> = (1..=4)
+        .map(|i| random_block(10 + i).verified().unwrap())
+        .collect();
+
+    let mut store = BlockStore::open(dir.path()).unwrap();
+    for block in blocks.iter() {
+        store.append(block).unwrap();
+    }
+    drop(store);
+    let index = fs::read(&index_path).unwrap();
+
+    // The index entry of the last block is written partially, or not at all.
+    for len in [index.len() - 72, index.len() - 30, 72 + 5, 0] {
+        truncate(&index_path, len as u64);
+        let store = BlockStore::open(dir.path()).unwrap();
+        assert_eq!(store.len(), 4);
+        drop(store);
+        assert_eq!(fs::read(&index_path).unwrap(), index);
+    }
+
+    fs::remove_file(&index_path).unwrap();
+    let mut store = BlockStore::open(dir.path()).unwrap();
+    assert_eq!(
+        store.read(blocks[2].hash()).unwrap(),
+        Some(blocks[2].to_block())
+    );
+    drop(store);
+
+    // The index points past the end of the data.
+    let data = fs::read(&data_path).unwrap();
+    fs::write(&data_path, &data[..data.len() / 2]).unwrap();
+    let store = BlockStore::open(dir.path()).unwrap();
+    assert!(store.len() < 4);
+    assert!(!store.contains(blocks[3].hash()));
+}
+
+#[test]
+fn test_forest_restart() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = storage_config(dir.path());
+    let blocks = chain(10);
+
+    let mut forest = BlockForest::open(&config).unwrap();
+    for block in blocks.iter().rev() {
+        forest.add_block(block.clone()).unwrap();
+    }
+    // Invalid blocks are not stored.
+    let mut bad_block = next_block(&blocks[3], &blocks[3].issuer, vec![]);
+    bad_block.timestamp = blocks[3].timestamp;
+    assert!(forest.add_block(bad_block.verified().unwrap()).is_err());
+    assert_eq!(forest.head().hash(), blocks[9].hash());
+    drop(forest);
+
+    assert_eq!(stored_blocks(dir.path()).len(), 10);
+    let forest = BlockForest::open(&config).unwrap();
+    assert_eq!(forest.head().hash(), blocks[9].hash());
+    assert!(forest.unknown_block_hashes().is_empty());
+    for block in blocks.iter() {
+        assert_eq!(forest.find_block(block.hash()).unwrap().as_ref(), block);
+    }
+    drop(forest);
+
+    // The first block was added last, so without it the chain is not connected.
+    let data_path = dir.path().join("blocks.dat");
+    let len = fs::metadata(&data_path).unwrap().len();
+    truncate(&data_path, len - 1);
+    let mut forest = BlockForest::open(&config).unwrap();
+    assert_eq!(forest.head().hash(), VerifiedBlock::genesis().hash());
+    assert!(forest.unknown_block_hashes().contains(blocks[0].hash()));
+
+    forest.add_block(blocks[0].clone()).unwrap();
+    assert_eq!(forest.head().hash(), blocks[9].hash());
+    drop(forest);
+    let forest = BlockForest::open(&config).unwrap();
+    assert_eq!(forest.head().hash(), blocks[9].hash());
+}
+
+#[test]
+fn test_forest_persist_failure() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut forest = BlockForest::open(&storage_config(dir.path())).unwrap();
+
+    // A valid block, but too large for the store.
+    let key = generate_private_key();
+    let wallet: WalletId = key.to_public_key().into();
+    let comment = "x".repeat(2 << 20);
+    let tx = VerifiedTransaction::sign(&key, wallet.clone(), 1, 0, comment).unwrap();
+    let block = next_block(&VerifiedBlock::genesis(), &wallet, vec![tx]);
+    let block = block.verified().unwrap();
+
+    assert!(forest.add_block(block.clone()).is_err());
+    assert_eq!(forest.head().hash(), VerifiedBlock::genesis().hash());
+    assert!(forest.find_block(block.hash()).is_none());
+    assert!(forest.unknown_block_hashes().is_empty());
+
+    let blocks = chain(2);
+    for block in blocks.iter() {
+        forest.add_block(block.clone()).unwrap();
+    }
+    assert_eq!(forest.head().hash(), blocks[1].hash());
+    assert_eq!(stored_blocks(dir.path()).len(), 2);
+}
+
+#[test]
+fn test_no_storage() {
+    let mut forest = BlockForest::open(&StorageConfig::default()).unwrap();
+    forest.add_block(chain(1).remove(0)).unwrap();
+    assert_eq!(forest.head().index, 1);
+}
@@ -0,0 +1,256 @@
+#[macro_use]
+mod helpers;
+
+use helpers::{generate_private_key, next_block, wait_for_message};
+
+use babencoin::{
+    block_store::BlockStore,
+    data::{PeerMessage, Transaction, VerifiedBlock, VerifiedTransaction, WalletId},
+    node,
+};
+
+use rand::{thread_rng, Rng};
+use rsa::{PublicKeyEncoding, RSAPrivateKey};
+use serde_json::{json, Value};
+
+use std::{
+    io::{Read, Write},
+    net::TcpStream,
+    sync::mpsc,
+    thread,
+    time::Duration,
+};
+
+////////////////////////////////////////////////////////////////////////////////
+
+fn rpc_config() -> (node::Config, String) {
+    let port = thread_rng().gen_range(49152..65536);
+    let addr = format!("127.0.0.1:{}", port);
+    let mut config = node::Config::default();
+    config.rpc_service.listen_address = Some(addr.clone());
+    (config, addr)
+}
+
+fn http_request(addr: &str, request_line: &str, body: &str) -> (String, String) {
+    let mut conn = TcpStream::connect(addr).unwrap();
+    write!(
+        conn,
+        "{}\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+        request_line,
+        addr,
+        body.len(),
+        body
+    )
+    .unwrap();
+
+    let mut response = String::new();
+    conn.read_to_string(&mut response).unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    let status = head.lines().next().unwrap().to_owned();
+    (status, body.to_owned())
+}
+
+fn rpc_call(addr: &str, method: &str, params: Value) -> Value {
+    let request = json!({"jsonrpc": "2.0", "id": 7, "method": method, "params": params});
+    let (status, body) = http_request(addr, "POST / HTTP/1.1", &request.to_string());
+    assert_eq!(status, "HTTP/1.1 200 OK");
+    let response: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(response["id"], 7);
+    response
+}
+
+fn rpc_result(addr: &str, method: &str, params: Value) -> Value {
+    let response = rpc_call(addr, method, params);
+    assert!(response.get("error").is_none(), "{}", response);
+    response["result"].clone()
+}
+
+fn rpc_error_code(addr: &str, method: &str, params: Value) -> i64 {
+    rpc_call(addr, method, params)["error"]["code"]
+        .as_i64()
+        .unwrap()
+}
+
+fn wallet(key: &RSAPrivateKey) -> WalletId {
+    key.to_public_key().into()
+}
+
+fn encode_wallet(wallet: &WalletId) -> String {
+    base64::encode(wallet.public_key.to_pkcs8().unwrap())
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn test_rpc_chain_state() {
+    let dir = tempfile::tempdir().unwrap();
+    let (mut config, addr) = rpc_config();
+    config.storage.path = Some(dir.path().to_owned());
+
+    // The chain is loaded from the storage on startup.
+    let key = generate_private_key();
+    let mut blocks = vec![VerifiedBlock::genesis()];
+    let mut store = BlockStore::open(dir.path()).unwrap();
+    for _ in 0..3 {
+        let block = next_block(blocks.last().unwrap(), &wallet(&key), vec![]);
+        let block = block.verified().unwrap();
+        store.append(&block).unwrap();
+        blocks.push(block);
+    }
+    drop(store);
+
+    let env = test_env!("test_rpc_chain_state", config);
+    let head = rpc_result(&addr, "get_head", Value::Null);
+    assert_eq!(head["index"], 3);
+    assert_eq!(head["hash"], base64::encode(blocks[3].hash()));
+    let block = rpc_result(&addr, "get_block", json!({"index": 2}));
+    assert_eq!(block["hash"], base64::encode(blocks[2].hash()));
+    assert_eq!(block["issuer"], encode_wallet(&wallet(&key)));
+    let hash = base64::encode(blocks[1].hash());
+    assert_eq!(
+        rpc_result(&addr, "get_block", json!({ "hash": hash }))["index"],
+        1
+    );
+    let genesis = rpc_result(&addr, "get_block", json!({"index": 0}));
+    assert_eq!(genesis["hash"], base64::encode(blocks[0].hash()));
+    assert_eq!(
+        rpc_result(&addr, "get_block", json!({"index": 4})),
+        Value::Null
+    );
+
+    let wallet_params = json!({ "wallet": encode_wallet(&wallet(&key)) });
+    assert_eq!(
+        rpc_result(&addr, "get_balance", wallet_params.clone()),
+        3000
+    );
+
+    let mut conn = env.connect_to_node().unwrap();
+    wait_for_message(&mut conn, 10, |msg| match msg {
+        PeerMessage::Block(block) => block.compute_hash() == *blocks[3].hash(),
+        _ => false,
+    })
+    .unwrap();
+
+    let receiver = wallet(&generate_private_key());
+    let tx = VerifiedTransaction::sign(&key, receiver.clone(), 500, 10, "rpc".into()).unwrap();
+    let tx_hash = base64::encode(tx.hash());
+    let params = serde_json::to_value(Transaction::from(tx.clone())).unwrap();
+    assert_eq!(rpc_result(&addr, "submit_transaction", params), tx_hash);
+
+    // The transaction is forwarded to the peers.
+    wait_for_message(&mut conn, 5, |msg| match msg {
+        PeerMessage::Transaction(recv_tx) => **recv_tx == *tx,
+        _ => false,
+    })
+    .unwrap();
+
+    let pending = rpc_result(&addr, "get_pending_transactions", Value::Null);
+    assert_eq!(pending.as_array().unwrap().len(), 1);
+    assert_eq!(pending[0]["hash"], tx_hash);
+    let found = rpc_result(&addr, "get_transaction", json!({ "hash": tx_hash }));
+    assert_eq!(found["comment"], "rpc");
+    assert_eq!(found["block_hash"], Value::Null);
+    assert_eq!(
+        rpc_result(&addr, "get_balance", wallet_params.clone()),
+        3000
+    );
+
+    // The sender cannot spend the pending transaction twice.
+    let overdraft = VerifiedTransaction::sign(&key, receiver.clone(), 2500, 0, "".into()).unwrap();
+    let params = serde_json::to_value(Transaction::from(overdraft)).unwrap();
+    assert_eq!(rpc_error_code(&addr, "submit_transaction", params), -32000);
+    drop(conn);
+    drop(env);
+
+    let block = next_block(&blocks[3], &wallet(&key), vec![tx]);
+    let block = block.verified().unwrap();
+    BlockStore::open(dir.path())
+        .unwrap()
+        .append(&block)
+        .unwrap();
+
+    let (mut config, addr) = rpc_config();
+    config.storage.path = Some(dir.path().to_owned());
+    let _env = test_env!("test_rpc_chain_state_restart", config);
+    let found = rpc_result(&addr, "get_transaction", json!({ "hash": tx_hash }));
+    assert_eq!(found["block_hash"], base64::encode(block.hash()));
+    let pending = rpc_result(&addr, "get_pending_transactions", Value::Null);
+    assert!(pending.as_array().unwrap().is_empty());
+    assert_eq!(rpc_result(&addr, "get_balance", wallet_params), 3500);
+    let receiver_params = json!({ "wallet": encode_wallet(&receiver) });
+    assert_eq!(rpc_result(&addr, "get_balance", receiver_params), 500);
+}
+
+#[test]
+fn test_rpc_errors() {
+    let (config, addr) = rpc_config();
+    let _env = test_env!("test_rpc_errors", config);
+
+    assert_eq!(rpc_error_code(&addr, "get_everything", Value::Null), -32601);
+    assert_eq!(
+        rpc_error_code(&addr, "get_block", json!({"hash": "AAAA"})),
+        -32602
+    );
+    assert_eq!(rpc_error_code(&addr, "get_balance", Value::Null), -32602);
+    assert_eq!(
+        rpc_result(
+            &addr,
+            "get_transaction",
+            json!({"hash": base64::encode([0u8; 64])})
+        ),
+        Value::Null
+    );
+
+    let (status, body) = http_request(&addr, "POST / HTTP/1.1", "{");
+    assert_eq!(status, "HTTP/1.1 200 OK");
+    let response: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(response["error"]["code"], -32700);
+
+    let request = json!({"jsonrpc": "1.0", "id": 1, "method": "get_head"}).to_string();
+    let (_, body) = http_request(&addr, "POST / HTTP/1.1", &request);
+    let response: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(response["error"]["code"], -32600);
+
+    let (status, _) = http_request(&addr, "GET / HTTP/1.1", "");
+    assert_eq!(status, "HTTP/1.1 405 Method Not Allowed");
+    let (status, _) = http_request(&addr, "POST /rpc HTTP/1.1", &request);
+    assert_eq!(status, "HTTP/1.1 404 Not Found");
+}
+
+#[test]
+fn test_rpc_public_address() {
+    let mut config = node::Config::default();
+    config.rpc_service.listen_address = Some("0.0.0.0:0".to_owned());
+
+    // The node must refuse to start rather than serve rpc on every interface.
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || sender.send(node::run_forever(config)).unwrap());
+    let err = receiver
+        .recv_timeout(Duration::from_secs(5))
+        .unwrap()
+        .unwrap_err();
+    assert!(format!("{:#}", err).contains("loopback"), "{:#}", err);
+}
+
+#[test]
+fn test_rpc_connection_limit() {
+    let (mut config, addr) = rpc_config();
+    config.rpc_service.max_connections = 2;
+    let _env = test_env!("test_rpc_connection_limit", config);
+
+    // Idle connections hold their workers, so the others wait for them to be closed.
+    let idle: Vec<_> = (0..2).map(|_| TcpStream::connect(&addr).unwrap()).collect();
+    let (sender, receiver) = mpsc::channel();
+    let request_addr = addr.clone();
+    thread::spawn(move || {
+        sender
+            .send(rpc_result(&request_addr, "get_head", Value::Null))
+            .unwrap();
+    });
+    assert!(receiver.recv_timeout(Duration::from_millis(500)).is_err());
+
+    drop(idle);
+    let head = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!(head["index"], 0);
+    assert_eq!(rpc_result(&addr, "get_head", Value::Null), head);
+}
@@ -0,0 +1,112 @@
+use std::io::{self, Read, Write};
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+
+use ripgzip::{BgzfWriter, Crc32, GzDecoder, GzEncoder, Level};
+
+const INPUTS: [(&str, &[u8]); 3] = [
+    (
+        "war-and-peace",
+        include_bytes!("../data/ok/06-war-and-peace.txt.gz"),
+    ),
+    ("doc.pdf", include_bytes!("../data/ok/02-doc.pdf.gz")),
+    ("app", include_bytes!("../data/ok/05-app.gz")),
+];
+
+fn decompressed_len(data: &[u8]) -> u64 {
+    let mut output = Vec::new();
+    GzDecoder::new(data).read_to_end(&mut output).unwrap();
+    output.len() as u64
+}
+
+fn decompress(c: &mut Criterion) {
+    let mut group = c.benchmark_group("decompress");
+    for (name, data) in INPUTS {
+        group.throughput(Throughput::Bytes(decompressed_len(data)));
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                let mut input = black_box(data);
+                ripgzip::decompress(&mut input, io::sink()).unwrap();
+            })
+        });
+    }
+    group.finish();
+}
+
+fn gz_decoder(c: &mut Criterion) {
+    let mut group = c.benchmark_group("gz_decoder");
+    let (name, data) = INPUTS[0];
+    group.throughput(Throughput::Bytes(decompressed_len(data)));
+    group.bench_function(name, |b| {
+        b.iter(|| io::copy(&mut GzDecoder::new(black_box(data)), &mut io::sink()).unwrap())
+    });
+    group.finish();
+}
+
+fn stored(c: &mut Criterion) {
+    let mut data = Vec::new();
+    GzDecoder::new(INPUTS[0].1).read_to_end(&mut data).unwrap();
+    let mut encoder = GzEncoder::new(Vec::new(), Level::none());
+    encoder.write_all(&data).unwrap();
+    let compressed = encoder.finish().unwrap();
+
+    let mut group = c.benchmark_group("stored");
+    group.throughput(Throughput::Bytes(data.len() as u64));
+    group.bench_function("war-and-peace", |b| {
+        b.iter(|| {
+            let mut input = black_box(compressed.as_slice());
+            ripgzip::decompress(&mut input, io::sink()).unwrap();
+        })
+    });
+    group.finish();
+}
+
+fn crc32(c: &mut Criterion) {
+    let mut data = Vec::new();
+    GzDecoder::new(INPUTS[0].1).read_to_end(&mut data).unwrap();
+
+    let mut group = c.benchmark_group("crc32");
+    group.throughput(Throughput::Bytes(data.len() as u64));
+    group.bench_function("war-and-peace", |b| {
+        b.iter(|| {
+            let mut crc = Crc32::new();
+            crc.write(black_box(&data));
+            crc.sum32()
+        })
+    });
+    group.finish();
+}
+
+fn decompress_parallel(c: &mut Criterion) {
+    let mut data = Vec::new();
+    GzDecoder::new(INPUTS[0].1).read_to_end(&mut data).unwrap();
+    let data = data.repeat(8);
+    let mut writer = BgzfWriter::new(Vec::new(), Level::default());
+    writer.write_all(&data).unwrap();
+    let compressed = writer.finish().unwrap();
+
+    let mut group = c.benchmark_group("decompress_parallel");
+    group.throughput(Throughput::Bytes(data.len() as u64));
+    group.bench_function("serial", |b| {
+        b.iter(|| {
+            let mut input = black_box(compressed.as_slice());
+            ripgzip::decompress(&mut input, io::sink()).unwrap();
+        })
+    });
+    for processes in [1, 2, 4, 8] {
+        group.bench_function(format!("{}-threads", processes), |b| {
+            b.iter(|| {
+                let input = black_box(compressed.as_slice());
+                ripgzip::decompress_parallel(input, io::sink(), processes).unwrap();
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().sample_size(10);
+    targets = decompress, gz_decoder, stored, crc32, decompress_parallel
+}
+criterion_main!(benches);
@@ -0,0 +1,65 @@
+////////////////////////////////////////////////////////////////////////////////
+
+const MOD_ADLER: u32 = 65521;
+
+/// The largest number of bytes that can be summed before `b` overflows `u32`.
+const NMAX: usize = 5552;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Adler32 {
+    a: u32,
+    b: u32,
+}
+
+impl Adler32 {
+    pub fn new() -> Self {
+        Self { a: 1, b: 0 }
+    }
+
+    pub fn write(&mut self, data: &[u8]) {
+        for chunk in data.chunks(NMAX) {
+            for &byte in chunk {
+                self.a += byte as u32;
+                self.b += self.a;
+            }
+            self.a %= MOD_ADLER;
+            self.b %= MOD_ADLER;
+        }
+    }
+
+    pub fn sum32(&self) -> u32 {
+        self.b << 16 | self.a
+    }
+}
+
+/// The Adler-32 of `data`, which identifies preset dictionaries in zlib headers.
+pub fn checksum(data: &[u8]) -> u32 {
+    let mut adler = Adler32::new();
+    adler.write(data);
+    adler.sum32()
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sum32() {
+        let mut adler = Adler32::new();
+        assert_eq!(adler.sum32(), 1);
+        adler.write(b"Wikipedia");
+        assert_eq!(adler.sum32(), 0x11e60398);
+
+        let data = vec![0xffu8; 100_000];
+        let mut whole = Adler32::new();
+        whole.write(&data);
+        let mut parts = Adler32::new();
+        for chunk in data.chunks(777) {
+            parts.write(chunk);
+        }
+        assert_eq!(whole.sum32(), parts.sum32());
+        assert_eq!(whole.sum32(), 0x149a302c);
+    }
+}
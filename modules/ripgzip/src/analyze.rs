@@ -0,0 +1,342 @@
+use std::fmt;
+use std::io::{self, BufRead, Write};
+
+use crate::bit_reader::BitReader;
+use crate::deflate::{CompressionType, DeflateReader};
+use crate::error::{Error, Location, Result};
+use crate::gzip::{GzipReader, MemberReader};
+use crate::huffman_coding::{
+    distance_code, fixed_litlen_distance_trees, length_code, read_code_lengths, CodeLengths,
+    DistanceToken, HuffmanCoding, LitLenToken, DISTANCE_BASES, DISTANCE_CODES, LENGTH_BASES,
+    LENGTH_CODES,
+};
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Maximum distance of a match.
+const WINDOW_SIZE: u64 = 32768;
+
+/// Size of the chunks in which stored blocks are skipped.
+const SKIP_CHUNK_SIZE: usize = 32768;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BlockType {
+    Stored,
+    Fixed,
+    Dynamic,
+}
+
+impl fmt::Display for BlockType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            BlockType::Stored => "stored",
+            BlockType::Fixed => "fixed",
+            BlockType::Dynamic => "dynamic",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Statistics of a single DEFLATE block.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BlockInfo {
+    pub member: usize,
+    /// Offset of the block header from the start of the stream, in bits.
+    pub bit_offset: u64,
+    /// Size of the block including its header, in bits.
+    pub compressed_bits: u64,
+    pub uncompressed_size: u64,
+    pub is_final: bool,
+    pub block_type: BlockType,
+    /// The code lengths transmitted in the header of a dynamic block.
+    pub code_lengths: Option<CodeLengths>,
+    pub literals: u64,
+    pub matches: u64,
+    /// Number of matches per length symbol, from 257 to 285.
+    pub length_histogram: [u64; LENGTH_CODES],
+    /// Number of matches per distance symbol.
+    pub distance_histogram: [u64; DISTANCE_CODES],
+}
+
+impl BlockInfo {
+    fn new(member: usize, bit_offset: u64, is_final: bool, block_type: BlockType) -> Self {
+        Self {
+            member,
+            bit_offset,
+            compressed_bits: 0,
+            uncompressed_size: 0,
+            is_final,
+            block_type,
+            code_lengths: None,
+            literals: 0,
+            matches: 0,
+            length_histogram: [0; LENGTH_CODES],
+            distance_histogram: [0; DISTANCE_CODES],
+        }
+    }
+}
+
+/// The block structure of a gzip stream, as printed by `--analyze`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Analysis {
+    pub blocks: Vec<BlockInfo>,
+}
+
+impl Analysis {
+    /// Walk all the blocks of a gzip stream, decoding their tokens without producing any output.
+    ///
+    /// As the data is not reconstructed, the CRC32 of the members is not checked.
+    pub fn new<R: BufRead>(input: R) -> Result<Self> {
+        let mut reader = DeflateReader::new(BitReader::new(input));
+        let mut blocks = Vec::new();
+        let mut member = 0;
+        loop {
+            let position = reader.bit_reader().position().next_multiple_of(8);
+            let stream = reader.bit_reader_mut().borrow_reader_from_boundary();
+            match GzipReader::new(stream).next_member() {
+                None => break,
+                Some(header) => header.map_err(|err| err.locate(member, position))?,
+            };
+
+            let mut member_size = 0;
+            loop {
+                let position = reader.bit_reader().position();
+                let block = analyze_block(&mut reader, member, member_size)
+                    .map_err(|err| err.locate(member, position))?;
+                member_size += block.uncompressed_size;
+                let is_final = block.is_final;
+                blocks.push(block);
+                if is_final {
+                    break;
+                }
+            }
+
+            let position = reader.bit_reader().position().next_multiple_of(8);
+            let stream = reader.bit_reader_mut().borrow_reader_from_boundary();
+            let (footer, _) = MemberReader::new(stream)
+                .read_footer()
+                .map_err(|err| err.locate(member, position))?;
+            if footer.data_size != member_size as u32 {
+                return Err(Error::LengthMismatch {
+                    stored: footer.data_size,
+                    computed: member_size as u32,
+                    location: Location {
+                        member,
+                        bit_offset: position,
+                    },
+                });
+            }
+            member += 1;
+        }
+        Ok(Self { blocks })
+    }
+
+    pub fn write_text<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        for (index, block) in self.blocks.iter().enumerate() {
+            let location = Location {
+                member: block.member,
+                bit_offset: block.bit_offset,
+            };
+            writeln!(
+                writer,
+                "block {} at {}: {}{}",
+                index,
+                location,
+                block.block_type,
+                if block.is_final { ", final" } else { "" }
+            )?;
+            writeln!(
+                writer,
+                "  compressed {} bits, uncompressed {} bytes, {} literals, {} matches",
+                block.compressed_bits, block.uncompressed_size, block.literals, block.matches
+            )?;
+            if let Some(lengths) = &block.code_lengths {
+                writeln!(
+                    writer,
+                    "  code length code lengths: {}",
+                    join(&lengths.code_length_lengths)
+                )?;
+                writeln!(
+                    writer,
+                    "  literal/length code lengths: {}",
+                    join(&lengths.litlen_lengths)
+                )?;
+                writeln!(
+                    writer,
+                    "  distance code lengths: {}",
+                    join(&lengths.distance_lengths)
+                )?;
+            }
+            if block.matches > 0 {
+                writeln!(writer, "  match lengths:")?;
+                write_histogram(&mut writer, &block.length_histogram, &LENGTH_BASES, 258)?;
+                writeln!(writer, "  match distances:")?;
+                write_histogram(
+                    &mut writer,
+                    &block.distance_histogram,
+                    &DISTANCE_BASES,
+                    WINDOW_SIZE as u16,
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the analysis as a JSON object with a `blocks` array, one block per line.
+    pub fn write_json<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writeln!(writer, "{{\"blocks\": [")?;
+        for (index, block) in self.blocks.iter().enumerate() {
+            let code_lengths = match &block.code_lengths {
+                Some(lengths) => format!(
+                    "{{\"code_length\": [{}], \"literal_length\": [{}], \"distance\": [{}]}}",
+                    join_with(&lengths.code_length_lengths, ", "),
+                    join_with(&lengths.litlen_lengths, ", "),
+                    join_with(&lengths.distance_lengths, ", ")
+                ),
+                None => "null".to_owned(),
+            };
+            writeln!(
+                writer,
+                "  {{\"member\": {}, \"bit_offset\": {}, \"type\": \"{}\", \"final\": {}, \
+                 \"compressed_bits\": {}, \"uncompressed_size\": {}, \"literals\": {}, \
+                 \"matches\": {}, \"code_lengths\": {}, \"length_histogram\": [{}], \
+                 \"distance_histogram\": [{}]}}{}",
+                block.member,
+                block.bit_offset,
+                block.block_type,
+                block.is_final,
+                block.compressed_bits,
+                block.uncompressed_size,
+                block.literals,
+                block.matches,
+                code_lengths,
+                join_with(&block.length_histogram, ", "),
+                join_with(&block.distance_histogram, ", "),
+                if index + 1 < self.blocks.len() {
+                    ","
+                } else {
+                    ""
+                }
+            )?;
+        }
+        writeln!(writer, "]}}")
+    }
+}
+
+fn analyze_block<T: BufRead>(
+    reader: &mut DeflateReader<T>,
+    member: usize,
+    member_size: u64,
+) -> Result<BlockInfo> {
+    let start = reader.bit_reader().position();
+    let (header, r) = reader.next_block().unwrap()?;
+    let block_type = match header.compression_type {
+        CompressionType::Uncompressed => BlockType::Stored,
+        CompressionType::FixedTree => BlockType::Fixed,
+        CompressionType::DynamicTree => BlockType::Dynamic,
+        CompressionType::Reserved => {
+            return Err(Error::UnsupportedBlockType {
+                location: Location::default(),
+            })
+        }
+    };
+    let mut block = BlockInfo::new(member, start, header.is_final, block_type);
+
+    match block_type {
+        BlockType::Stored => {
+            let _ = r.borrow_reader_from_boundary();
+            let len = r.read_bits(16)?.bits();
+            let nlen = r.read_bits(16)?.bits();
+            if len != !nlen {
+                return Err(Error::StoredLengthMismatch {
+                    location: Location::default(),
+                });
+            }
+            let mut buf = vec![0; SKIP_CHUNK_SIZE];
+            let mut remaining = len as usize;
+            while remaining > 0 {
+                let chunk = remaining.min(SKIP_CHUNK_SIZE);
+                r.read_aligned_bytes(&mut buf[..chunk])?;
+                remaining -= chunk;
+            }
+            block.uncompressed_size = len as u64;
+        }
+        BlockType::Fixed | BlockType::Dynamic => {
+            let (litlen_coding, distance_coding) = if block_type == BlockType::Fixed {
+                fixed_litlen_distance_trees()?
+            } else {
+                let lengths = read_code_lengths(r)?;
+                let codings = (
+                    HuffmanCoding::<LitLenToken>::from_lengths(&lengths.litlen_lengths)?,
+                    HuffmanCoding::<DistanceToken>::from_lengths(&lengths.distance_lengths)?,
+                );
+                block.code_lengths = Some(lengths);
+                codings
+            };
+            loop {
+                match litlen_coding.read_symbol(r)? {
+                    LitLenToken::Literal(_) => {
+                        block.literals += 1;
+                        block.uncompressed_size += 1;
+                    }
+                    LitLenToken::EndOfBlock => break,
+                    LitLenToken::Length { base, extra_bits } => {
+                        let len = base + r.read_bits(extra_bits)?.bits();
+                        let distance_token = distance_coding.read_symbol(r)?;
+                        let distance =
+                            distance_token.base + r.read_bits(distance_token.extra_bits)?.bits();
+                        let available = (member_size + block.uncompressed_size).min(WINDOW_SIZE);
+                        if distance as u64 > available {
+                            return Err(Error::DistanceTooFarBack {
+                                distance: distance as usize,
+                                available: available as usize,
+                                location: Location::at(r.position()),
+                            });
+                        }
+                        block.matches += 1;
+                        block.uncompressed_size += len as u64;
+                        block.length_histogram[length_code(len).0 - 257] += 1;
+                        block.distance_histogram[distance_code(distance).0] += 1;
+                    }
+                }
+            }
+        }
+    }
+    block.compressed_bits = r.position() - start;
+    Ok(block)
+}
+
+/// Print the non-empty buckets of a histogram along with the range of values they cover.
+fn write_histogram<W: Write>(
+    writer: &mut W,
+    histogram: &[u64],
+    bases: &[u16],
+    max: u16,
+) -> io::Result<()> {
+    for (symbol, &count) in histogram.iter().enumerate() {
+        if count == 0 {
+            continue;
+        }
+        let low = bases[symbol];
+        let high = bases.get(symbol + 1).map_or(max, |next| next - 1);
+        let range = if low == high {
+            low.to_string()
+        } else {
+            format!("{}-{}", low, high)
+        };
+        writeln!(writer, "    {:>11}: {}", range, count)?;
+    }
+    Ok(())
+}
+
+fn join<T: ToString>(values: &[T]) -> String {
+    join_with(values, " ")
+}
+
+fn join_with<T: ToString>(values: &[T], separator: &str) -> String {
+    values
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(separator)
+}
@@ -0,0 +1,345 @@
+use std::io::{self, BufRead, Read};
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+
+use tokio::io::{AsyncBufRead, AsyncRead, AsyncWrite, ReadBuf};
+
+use crate::decoder::{into_io_error, Decoder, DecompressOptions};
+use crate::encoder::Encoder;
+use crate::gzip::MemberHeader;
+use crate::lz77::Level;
+use crate::Format;
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Consumed input is dropped once there is at least this much of it.
+const COMPACT_THRESHOLD: usize = 65536;
+
+/// The compressed input received so far, which the decoder reads as a regular `BufRead`.
+#[derive(Default)]
+struct InputBuffer {
+    data: Vec<u8>,
+    pos: usize,
+    /// Total number of bytes consumed by the decoder.
+    consumed: u64,
+}
+
+impl InputBuffer {
+    fn extend(&mut self, data: &[u8]) {
+        if self.pos >= COMPACT_THRESHOLD && self.pos * 2 >= self.data.len() {
+            self.data.drain(..self.pos);
+            self.pos = 0;
+        }
+        self.data.extend_from_slice(data);
+    }
+}
+
+impl Read for InputBuffer {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = (&self.data[self.pos..]).read(buf)?;
+        self.consume(len);
+        Ok(len)
+    }
+}
+
+impl BufRead for InputBuffer {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        Ok(&self.data[self.pos..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos += amt;
+        self.consumed += amt as u64;
+    }
+}
+
+/// Drives the sync decoder, only stepping it once it cannot run out of buffered input
+/// in the middle of a step. Otherwise more input is read first, so that decoding is
+/// suspended whenever the inner reader is pending and resumed at the same state.
+struct AsyncDecoder<R> {
+    input: R,
+    decoder: Decoder<InputBuffer>,
+    is_input_over: bool,
+}
+
+impl<R: AsyncBufRead + Unpin> AsyncDecoder<R> {
+    fn new(input: R, format: Format, options: DecompressOptions) -> Self {
+        Self {
+            input,
+            decoder: Decoder::with_options(InputBuffer::default(), format, options),
+            is_input_over: false,
+        }
+    }
+
+    /// Whether the decoder can step without running out of the buffered input.
+    fn can_step(&self) -> bool {
+        if self.is_input_over {
+            return true;
+        }
+        // The bit reader may hold bytes before the current position unconsumed.
+        let position = self.decoder.input_position().div_ceil(8);
+        let buffer = self.decoder.input();
+        let skip = position.saturating_sub(buffer.consumed) as usize;
+        let data = &buffer.data[buffer.pos..];
+        self.decoder.has_step_input(&data[skip.min(data.len())..])
+    }
+
+    fn poll_fill_buf(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        while self.decoder.output().is_empty() && !self.decoder.is_done() {
+            if !self.can_step() {
+                let data = ready!(Pin::new(&mut self.input).poll_fill_buf(cx))?;
+                let len = data.len();
+                if len == 0 {
+                    self.is_input_over = true;
+                } else {
+                    self.decoder.input_mut().extend(data);
+                    Pin::new(&mut self.input).consume(len);
+                }
+                continue;
+            }
+            self.decoder.step().map_err(into_io_error)?;
+        }
+        Poll::Ready(Ok(self.decoder.output()))
+    }
+
+    fn poll_read(&mut self, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let data = ready!(self.poll_fill_buf(cx))?;
+        let len = data.len().min(buf.remaining());
+        buf.put_slice(&data[..len]);
+        self.decoder.consume(len);
+        Poll::Ready(Ok(()))
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Drives the sync encoder over an in-memory buffer, writing its output to the inner
+/// writer before accepting more input.
+struct AsyncEncoder<W> {
+    output: W,
+    encoder: Option<Encoder<Vec<u8>>>,
+    /// Compressed data not written to `output` yet.
+    pending: Vec<u8>,
+    written: usize,
+}
+
+impl<W: AsyncWrite + Unpin> AsyncEncoder<W> {
+    fn new(output: W, level: Level, format: Format) -> Self {
+        Self {
+            output,
+            encoder: Some(Encoder::new(Vec::new(), level, format)),
+            pending: Vec::new(),
+            written: 0,
+        }
+    }
+
+    /// Move the data produced by the encoder to `pending` and write all of it.
+    fn poll_drain(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if let Some(encoder) = &mut self.encoder {
+            self.pending.append(encoder.get_mut());
+        }
+        while self.written < self.pending.len() {
+            let data = &self.pending[self.written..];
+            let len = ready!(Pin::new(&mut self.output).poll_write(cx, data))?;
+            if len == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.written += len;
+        }
+        self.pending.clear();
+        self.written = 0;
+        Poll::Ready(Ok(()))
+    }
+
+    fn encoder(&mut self) -> io::Result<&mut Encoder<Vec<u8>>> {
+        self.encoder
+            .as_mut()
+            .ok_or_else(|| io::Error::other("write after shutdown"))
+    }
+
+    fn poll_write(&mut self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        ready!(self.poll_drain(cx))?;
+        Poll::Ready(self.encoder()?.write(buf))
+    }
+
+    fn poll_flush(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        ready!(self.poll_drain(cx))?;
+        if let Some(encoder) = &mut self.encoder {
+            encoder.flush()?;
+            ready!(self.poll_drain(cx))?;
+        }
+        Pin::new(&mut self.output).poll_flush(cx)
+    }
+
+    fn poll_shutdown(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        ready!(self.poll_drain(cx))?;
+        if let Some(encoder) = self.encoder.take() {
+            self.pending = encoder.finish()?;
+            ready!(self.poll_drain(cx))?;
+        }
+        Pin::new(&mut self.output).poll_shutdown(cx)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// An asynchronous decoder of (possibly multi-member) gzip streams.
+pub struct AsyncGzDecoder<R> {
+    inner: AsyncDecoder<R>,
+}
+
+impl<R: AsyncBufRead + Unpin> AsyncGzDecoder<R> {
+    pub fn new(input: R) -> Self {
+        Self::with_options(input, DecompressOptions::default())
+    }
+
+    pub fn with_options(input: R, options: DecompressOptions) -> Self {
+        Self {
+            inner: AsyncDecoder::new(input, Format::Gzip, options),
+        }
+    }
+
+    /// Header of the member being decoded, available once the first read returns.
+    pub fn header(&self) -> Option<&MemberHeader> {
+        self.inner.decoder.header()
+    }
+}
+
+impl<R: AsyncBufRead + Unpin> AsyncRead for AsyncGzDecoder<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        self.get_mut().inner.poll_read(cx, buf)
+    }
+}
+
+impl<R: AsyncBufRead + Unpin> AsyncBufRead for AsyncGzDecoder<R> {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        self.get_mut().inner.poll_fill_buf(cx)
+    }
+
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        self.get_mut().inner.decoder.consume(amt);
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// An asynchronous zlib (RFC 1950) decoder.
+pub struct AsyncZlibDecoder<R> {
+    inner: AsyncDecoder<R>,
+}
+
+impl<R: AsyncBufRead + Unpin> AsyncZlibDecoder<R> {
+    pub fn new(input: R) -> Self {
+        Self {
+            inner: AsyncDecoder::new(input, Format::Zlib, DecompressOptions::default()),
+        }
+    }
+}
+
+impl<R: AsyncBufRead + Unpin> AsyncRead for AsyncZlibDecoder<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        self.get_mut().inner.poll_read(cx, buf)
+    }
+}
+
+impl<R: AsyncBufRead + Unpin> AsyncBufRead for AsyncZlibDecoder<R> {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        self.get_mut().inner.poll_fill_buf(cx)
+    }
+
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        self.get_mut().inner.decoder.consume(amt);
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// An asynchronous gzip writer producing a single member.
+///
+/// The member is completed by `shutdown`.
+pub struct AsyncGzEncoder<W> {
+    inner: AsyncEncoder<W>,
+}
+
+impl<W: AsyncWrite + Unpin> AsyncGzEncoder<W> {
+    pub fn new(output: W, level: Level) -> Self {
+        Self {
+            inner: AsyncEncoder::new(output, level, Format::Gzip),
+        }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner.output
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner.output
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for AsyncGzEncoder<W> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.get_mut().inner.poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().inner.poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().inner.poll_shutdown(cx)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// An asynchronous zlib (RFC 1950) writer, completed by `shutdown`.
+pub struct AsyncZlibEncoder<W> {
+    inner: AsyncEncoder<W>,
+}
+
+impl<W: AsyncWrite + Unpin> AsyncZlibEncoder<W> {
+    pub fn new(output: W, level: Level) -> Self {
+        Self {
+            inner: AsyncEncoder::new(output, level, Format::Zlib),
+        }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner.output
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner.output
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for AsyncZlibEncoder<W> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.get_mut().inner.poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().inner.poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().inner.poll_shutdown(cx)
+    }
+}
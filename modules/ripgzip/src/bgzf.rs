@@ -0,0 +1,382 @@
+use std::io::{self, BufRead, Read, Seek, SeekFrom, Write};
+
+use crate::crc32;
+use crate::decoder::{into_io_error, Decoder, DecompressOptions};
+use crate::encoder::{member_header, Encoder};
+use crate::error::{Error, Location, Result};
+use crate::gzip::{GzipWriter, MemberFooter, MemberHeader, MemberWriter};
+use crate::lz77::Level;
+use crate::Format;
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Largest size of a whole BGZF block, as its `BSIZE` field holds the size minus one.
+const MAX_BLOCK_SIZE: usize = 65536;
+
+/// Amount of data per block, leaving room for the header, the footer and incompressible data.
+const MAX_BLOCK_DATA: usize = 0xff00;
+
+/// Size of the fixed part of the header preceding the extra field.
+const FIXED_HEADER_SIZE: usize = 12;
+
+const HEADER_SIZE: usize = FIXED_HEADER_SIZE + 6;
+const FOOTER_SIZE: usize = 8;
+
+const FEXTRA: u8 = 1 << 2;
+
+const BC_SUBFIELD: [u8; 2] = *b"BC";
+
+/// An empty block terminating every BGZF file.
+const EOF_MARKER: [u8; 28] = [
+    0x1f, 0x8b, 0x08, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0x06, 0x00, 0x42, 0x43, 0x02, 0x00,
+    0x1b, 0x00, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// A position in a BGZF file: the offset of a block in the file shifted by 16 bits,
+/// combined with an offset in the decompressed data of that block.
+///
+/// Virtual offsets increase along with the decompressed data, so they can be compared.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct VirtualOffset(pub u64);
+
+impl VirtualOffset {
+    pub fn new(block_offset: u64, in_block_offset: u16) -> Self {
+        Self(block_offset << 16 | in_block_offset as u64)
+    }
+
+    /// Offset of the block in the compressed file.
+    pub fn block_offset(self) -> u64 {
+        self.0 >> 16
+    }
+
+    /// Offset in the decompressed data of the block.
+    pub fn in_block_offset(self) -> u16 {
+        self.0 as u16
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// A writer of BGZF (blocked gzip) files, as used by SAM/BAM, VCF and tabix.
+///
+/// The data is split into gzip members of at most 64 KiB each, with the size of the
+/// member recorded in the `BC` extra subfield. The file is complete only after `finish`
+/// is called, which writes the empty block marking its end.
+pub struct BgzfWriter<W: Write> {
+    inner: W,
+    level: Level,
+    /// Data of the block being filled.
+    buffer: Vec<u8>,
+    /// Number of bytes written to `inner` so far.
+    offset: u64,
+}
+
+impl<W: Write> BgzfWriter<W> {
+    pub fn new(inner: W, level: Level) -> Self {
+        Self {
+            inner,
+            level,
+            buffer: Vec::with_capacity(MAX_BLOCK_DATA),
+            offset: 0,
+        }
+    }
+
+    /// Virtual offset of the next byte written, e.g. to be recorded in an index.
+    pub fn virtual_offset(&self) -> VirtualOffset {
+        VirtualOffset::new(self.offset, self.buffer.len() as u16)
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Write the last block and the end-of-file marker, and return the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.write_block()?;
+        self.inner.write_all(&EOF_MARKER)?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+
+    fn write_block(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let block = compress_block(&self.buffer, self.level)?;
+        self.inner.write_all(&block)?;
+        self.offset += block.len() as u64;
+        self.buffer.clear();
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for BgzfWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = buf.len().min(MAX_BLOCK_DATA - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..len]);
+        if self.buffer.len() == MAX_BLOCK_DATA {
+            self.write_block()?;
+        }
+        Ok(len)
+    }
+
+    /// End the current block, so that everything written so far can be read back.
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_block()?;
+        self.inner.flush()
+    }
+}
+
+/// Compress `data` into a whole BGZF block, falling back to stored blocks
+/// if the compressed data does not fit.
+fn compress_block(data: &[u8], level: Level) -> io::Result<Vec<u8>> {
+    let mut deflated = deflate(data, level)?;
+    if HEADER_SIZE + deflated.len() + FOOTER_SIZE > MAX_BLOCK_SIZE {
+        deflated = deflate(data, Level::none())?;
+    }
+    let block_size = HEADER_SIZE + deflated.len() + FOOTER_SIZE;
+    let mut extra = BC_SUBFIELD.to_vec();
+    extra.extend_from_slice(&2u16.to_le_bytes());
+    extra.extend_from_slice(&((block_size - 1) as u16).to_le_bytes());
+    let header = MemberHeader {
+        extra: Some(extra),
+        ..MemberHeader::default()
+    };
+
+    let mut block = Vec::with_capacity(block_size);
+    GzipWriter::new(&mut block).write_header(&member_header(header, level))?;
+    block.extend_from_slice(&deflated);
+    let footer = MemberFooter {
+        data_crc32: crc32::checksum(data),
+        data_size: data.len() as u32,
+    };
+    MemberWriter::new(&mut block).write_footer(&footer)?;
+    Ok(block)
+}
+
+fn deflate(mut data: &[u8], level: Level) -> io::Result<Vec<u8>> {
+    let mut encoder = Encoder::new(Vec::new(), level, Format::Raw);
+    while !data.is_empty() {
+        let len = encoder.write(data)?;
+        data = &data[len..];
+    }
+    encoder.finish()
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// A reader of BGZF files supporting seeks to virtual offsets.
+///
+/// Blocks are decompressed and verified one at a time. Errors are located by
+/// their offset in the whole file, while their member index is always 0.
+pub struct BgzfReader<R> {
+    inner: R,
+    /// Decompressed data of the current block.
+    block: Vec<u8>,
+    pos: usize,
+    /// Offset of the current block in the file.
+    block_offset: u64,
+    /// Offset of the block following the current one.
+    next_block_offset: u64,
+}
+
+impl<R: Read> BgzfReader<R> {
+    /// `inner` must be positioned at the start of the file.
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            block: Vec::with_capacity(MAX_BLOCK_SIZE),
+            pos: 0,
+            block_offset: 0,
+            next_block_offset: 0,
+        }
+    }
+
+    /// Virtual offset of the next byte read.
+    pub fn virtual_offset(&self) -> VirtualOffset {
+        if self.pos == self.block.len() {
+            VirtualOffset::new(self.next_block_offset, 0)
+        } else {
+            VirtualOffset::new(self.block_offset, self.pos as u16)
+        }
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    /// Read and decompress the next block, returning `false` at the end of the file.
+    fn read_block(&mut self) -> Result<bool> {
+        let offset = self.next_block_offset;
+        let data = match self.read_compressed_block() {
+            Ok(Some(data)) => data,
+            Ok(None) => return Ok(false),
+            Err(err) => return Err(err.locate(0, offset * 8)),
+        };
+
+        self.block.clear();
+        self.pos = 0;
+        let options = DecompressOptions {
+            max_output_size: Some(MAX_BLOCK_SIZE as u64),
+            ..DecompressOptions::default()
+        };
+        let mut decoder = Decoder::with_options(data.as_slice(), Format::Gzip, options);
+        while !decoder.is_done() {
+            decoder.step().map_err(|err| err.shift(0, offset * 8))?;
+            decoder.drain_output(&mut self.block);
+        }
+        self.block_offset = offset;
+        self.next_block_offset = offset + data.len() as u64;
+        Ok(true)
+    }
+
+    /// Read the gzip member making up the next block, with its size taken from the `BC` subfield.
+    fn read_compressed_block(&mut self) -> Result<Option<Vec<u8>>> {
+        let mut data = vec![0; FIXED_HEADER_SIZE];
+        let len = read_full(&mut self.inner, &mut data)?;
+        if len == 0 {
+            return Ok(None);
+        }
+        if len < data.len() {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+        if data[3] & FEXTRA == 0 {
+            return Err(invalid_block("no extra field"));
+        }
+        let extra_len = u16::from_le_bytes([data[10], data[11]]) as usize;
+        data.resize(FIXED_HEADER_SIZE + extra_len, 0);
+        self.inner.read_exact(&mut data[FIXED_HEADER_SIZE..])?;
+
+        let header = MemberHeader {
+            extra: Some(data[FIXED_HEADER_SIZE..].to_vec()),
+            ..MemberHeader::default()
+        };
+        let block_size = match header.subfield(BC_SUBFIELD) {
+            Some(&[low, high]) => u16::from_le_bytes([low, high]) as usize + 1,
+            _ => return Err(invalid_block("no BC subfield")),
+        };
+        if block_size < data.len() + FOOTER_SIZE {
+            return Err(invalid_block("block size is too small"));
+        }
+        let header_size = data.len();
+        data.resize(block_size, 0);
+        self.inner.read_exact(&mut data[header_size..])?;
+        Ok(Some(data))
+    }
+}
+
+impl<R: Read + Seek> BgzfReader<R> {
+    /// Continue reading at `offset`, which must have been obtained from `virtual_offset`
+    /// of a reader or a writer of the same file.
+    pub fn seek_virtual(&mut self, offset: VirtualOffset) -> Result<()> {
+        self.inner.seek(SeekFrom::Start(offset.block_offset()))?;
+        self.block.clear();
+        self.pos = 0;
+        self.next_block_offset = offset.block_offset();
+        let in_block_offset = offset.in_block_offset() as usize;
+        if in_block_offset > 0 {
+            if !self.read_block()? || in_block_offset > self.block.len() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "virtual offset is past the end of its block",
+                )
+                .into());
+            }
+            self.pos = in_block_offset;
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for BgzfReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let data = self.fill_buf()?;
+        let len = data.len().min(buf.len());
+        buf[..len].copy_from_slice(&data[..len]);
+        self.consume(len);
+        Ok(len)
+    }
+}
+
+impl<R: Read> BufRead for BgzfReader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        // Empty blocks, such as the end-of-file marker, are skipped.
+        while self.pos == self.block.len() {
+            if !self.read_block().map_err(into_io_error)? {
+                break;
+            }
+        }
+        Ok(&self.block[self.pos..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos += amt;
+    }
+}
+
+fn invalid_block(reason: &'static str) -> Error {
+    Error::InvalidHeader {
+        reason,
+        location: Location::default(),
+    }
+}
+
+/// Fill `buf` unless the input ends first, returning the number of bytes read.
+fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut len = 0;
+    while len < buf.len() {
+        match reader.read(&mut buf[len..]) {
+            Ok(0) => break,
+            Ok(n) => len += n,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(len)
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn eof_marker() -> io::Result<()> {
+        assert_eq!(compress_block(&[], Level::default())?, EOF_MARKER);
+        Ok(())
+    }
+
+    #[test]
+    fn incompressible_block() -> io::Result<()> {
+        let mut state = 1u32;
+        let data: Vec<u8> = (0..MAX_BLOCK_DATA)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                (state >> 16) as u8
+            })
+            .collect();
+        let block = compress_block(&data, Level::best())?;
+        assert!(block.len() <= MAX_BLOCK_SIZE);
+        let mut reader = BgzfReader::new(block.as_slice());
+        let mut output = Vec::new();
+        reader.read_to_end(&mut output)?;
+        assert!(output == data);
+        Ok(())
+    }
+
+    #[test]
+    fn virtual_offset() {
+        let offset = VirtualOffset::new(123_456, 789);
+        assert_eq!(offset.0, 123_456 << 16 | 789);
+        assert_eq!(offset.block_offset(), 123_456);
+        assert_eq!(offset.in_block_offset(), 789);
+        assert!(offset < VirtualOffset::new(123_457, 0));
+    }
+}
@@ -1,15 +1,10 @@
-use byteorder::ReadBytesExt;
-
 use std::cmp::min;
-use std::{
-    io::{self, BufRead},
-};
-
-#[cfg(test)]
-use std::println as debug;
+use std::io::{self, BufRead, Read, Write};
 
 ////////////////////////////////////////////////////////////////////////////////
-const MASK: u8 = 0b11111111;
+
+/// The buffer is refilled a byte at a time, so up to 7 bits may be left in it.
+const MAX_PEEK_BITS: u8 = 56;
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub struct BitSequence {
@@ -29,86 +24,216 @@ impl BitSequence {
     pub fn len(&self) -> u8 {
         self.len
     }
-
-    pub fn concat(self, other: Self) -> Self {
-        assert!(
-            self.len + other.len() <= 16,
-            "Concatenation result of two bit sequences is larger than 16"
-        );
-        let bits;
-        let len;
-        if self.len == 0 {
-            bits = other.bits;
-            len = other.len;
-        } else if other.len == 0 {
-            bits = self.bits;
-            len = self.len;
-        } else {
-            bits = self.bits << other.len | other.bits();
-            len = self.len + other.len;
-        }
-        Self { bits, len }
-    }
 }
 
 ////////////////////////////////////////////////////////////////////////////////
 
-struct BitReaderBuffer {
-    buffer: u8,
-    len: u8,
-}
-
+/// Reads bits LSB-first through a 64-bit buffer refilled from the underlying stream.
+///
+/// Bytes are loaded into the buffer from `fill_buf` and consumed from the stream only
+/// when all of their bits have been read, so the stream is not advanced past the data
+/// actually used. The only exception is a chunk of the stream ending in the middle of
+/// a read: then the bytes left in the buffer are "detached" from the stream.
+/// The reader implements `BufRead` itself and returns the detached bytes first.
 pub struct BitReader<T> {
     stream: T,
-    bit_reader_buffer: BitReaderBuffer,
+    buffer: u64,
+    len: u8,
+    /// Bytes loaded into the buffer but not consumed from the stream yet.
+    unconsumed: usize,
+    /// Bytes loaded into the buffer and already consumed from the stream.
+    detached: usize,
+    /// Total number of bytes ever loaded from the stream.
+    loaded: u64,
+    bytes: [u8; 8],
 }
 
 impl<T: BufRead> BitReader<T> {
     pub fn new(stream: T) -> Self {
         Self {
             stream,
-            bit_reader_buffer: BitReaderBuffer { buffer: 0, len: 0 },
+            buffer: 0,
+            len: 0,
+            unconsumed: 0,
+            detached: 0,
+            loaded: 0,
+            bytes: [0; 8],
+        }
+    }
+
+    /// Consume from the stream the bytes that were completely read from the buffer.
+    fn sync(&mut self) {
+        let in_buffer = (self.len as usize).div_ceil(8);
+        let dropped = self.detached + self.unconsumed - in_buffer;
+        let from_detached = min(dropped, self.detached);
+        self.detached -= from_detached;
+        let from_stream = dropped - from_detached;
+        self.stream.consume(from_stream);
+        self.unconsumed -= from_stream;
+    }
+
+    /// Load whole bytes into the buffer until it is full or has at least `need` bits
+    /// and the current chunk of the stream is over.
+    fn refill(&mut self, need: u8) -> io::Result<()> {
+        self.sync();
+        while self.len <= MAX_PEEK_BITS {
+            let data = self.stream.fill_buf()?;
+            let fresh = &data[self.unconsumed..];
+            if fresh.is_empty() {
+                if self.unconsumed == 0 || self.len >= need {
+                    break;
+                }
+                self.stream.consume(self.unconsumed);
+                self.detached += self.unconsumed;
+                self.unconsumed = 0;
+                continue;
+            }
+            let count = min(fresh.len(), ((64 - self.len) / 8) as usize);
+            for &byte in &fresh[..count] {
+                self.buffer |= (byte as u64) << self.len;
+                self.len += 8;
+            }
+            self.unconsumed += count;
+            self.loaded += count as u64;
         }
+        Ok(())
+    }
+
+    /// Return the next `len` bits without consuming them.
+    ///
+    /// Bits past the end of the stream read as zeroes; `consume` reports the EOF.
+    pub fn peek_bits(&mut self, len: u8) -> io::Result<u64> {
+        assert!(
+            len <= MAX_PEEK_BITS,
+            "You can only peek up to 56 bits at a time"
+        );
+        if self.len < len {
+            self.refill(len)?;
+        }
+        Ok(self.buffer & mask(len))
+    }
+
+    pub fn consume(&mut self, len: u8) -> io::Result<()> {
+        if self.len < len {
+            self.refill(len)?;
+            if self.len < len {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+        }
+        self.buffer = self.buffer.checked_shr(len as u32).unwrap_or(0);
+        self.len -= len;
+        Ok(())
     }
 
     pub fn read_bits(&mut self, len: u8) -> io::Result<BitSequence> {
         assert!(len <= 16, "You can only read up to 16 bits at a time");
-        if len == 0 {
-            return Ok(BitSequence::new(0, 0));
-        }
-        let mut seq: Option<BitSequence> = None;
-        let mut size = len;
-        while size > 0 {
-            let mut shift = if size >= 8 { 8 } else { size % 8 };
-            let save: u8;
-            if self.bit_reader_buffer.len == 0 {
-                let read = self.stream.read_u8()?;
-                save = read & MASK >> (8 - shift);
-
-                let new_buffer = read.checked_shr(shift as u32).unwrap_or(0);
-                self.bit_reader_buffer.buffer = new_buffer;
-                self.bit_reader_buffer.len = 8 - shift;
-            } else {
-                shift = min(self.bit_reader_buffer.len, shift);
-                save = self.bit_reader_buffer.buffer & MASK >> (8 - shift);
-
-                let new_buffer = self.bit_reader_buffer.buffer >> shift;
-                self.bit_reader_buffer.buffer = new_buffer;
-                self.bit_reader_buffer.len -= shift;
+        let bits = self.peek_bits(len)?;
+        self.consume(len)?;
+        Ok(BitSequence::new(bits as u16, len))
+    }
+
+    /// Discard all the unread bits in the current byte and return the reader
+    /// positioned at the next byte boundary.
+    pub fn borrow_reader_from_boundary(&mut self) -> &mut Self {
+        let skip = self.len % 8;
+        self.buffer >>= skip;
+        self.len -= skip;
+        self.sync();
+        self
+    }
+
+    /// Fill `buf` with the bytes following the current position, which must be
+    /// at a byte boundary. Bytes are copied in bulk, bypassing the bit buffer when possible.
+    pub fn read_aligned_bytes(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        assert_eq!(self.len % 8, 0, "the reader is not at a byte boundary");
+        self.read_exact(buf)
+    }
+
+    /// Like `read_aligned_bytes`, but write `len` bytes to `writer` straight from the buffer
+    /// of the stream.
+    pub fn copy_aligned_bytes<W: Write>(
+        &mut self,
+        mut len: usize,
+        writer: &mut W,
+    ) -> io::Result<()> {
+        assert_eq!(self.len % 8, 0, "the reader is not at a byte boundary");
+        while len > 0 {
+            let data = self.fill_buf()?;
+            if data.is_empty() {
+                return Err(io::ErrorKind::UnexpectedEof.into());
             }
-            let new_seq = BitSequence::new(save as u16, shift);
-            seq = seq.map(|s| new_seq.concat(s)).or(Some(new_seq));
-            size -= shift;
+            let chunk = min(data.len(), len);
+            writer.write_all(&data[..chunk])?;
+            self.consume_bytes(chunk);
+            len -= chunk;
         }
-        Ok(seq.unwrap())
+        Ok(())
     }
 
-    /// Discard all the unread bits in the current byte and return a mutable reference
-    /// to the underlying reader.
-    pub fn borrow_reader_from_boundary(&mut self) -> &mut T {
-        self.bit_reader_buffer.len = 0;
+    /// Number of bits read since the reader was created.
+    pub fn position(&self) -> u64 {
+        self.loaded * 8 - self.len as u64
+    }
+
+    #[cfg(feature = "tokio")]
+    pub fn get_ref(&self) -> &T {
+        &self.stream
+    }
+
+    /// The underlying stream, which may be extended but must not be consumed from.
+    #[cfg(feature = "tokio")]
+    pub fn get_mut(&mut self) -> &mut T {
         &mut self.stream
     }
+
+    /// Return the underlying stream, which may be positioned up to 8 bytes past `position`.
+    pub fn into_inner(self) -> T {
+        self.stream
+    }
+
+    fn consume_bytes(&mut self, amt: usize) {
+        if self.len == 0 {
+            self.stream.consume(amt);
+            self.loaded += amt as u64;
+            return;
+        }
+        let bits = (amt * 8) as u8;
+        assert!(bits <= self.len, "consumed more bytes than available");
+        self.buffer = self.buffer.checked_shr(bits as u32).unwrap_or(0);
+        self.len -= bits;
+        self.sync();
+    }
+}
+
+fn mask(len: u8) -> u64 {
+    (1u64 << len) - 1
+}
+
+impl<T: BufRead> Read for BitReader<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let data = self.fill_buf()?;
+        let len = min(data.len(), buf.len());
+        buf[..len].copy_from_slice(&data[..len]);
+        self.consume_bytes(len);
+        Ok(len)
+    }
+}
+
+impl<T: BufRead> BufRead for BitReader<T> {
+    /// Must only be called at a byte boundary.
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        debug_assert_eq!(self.len % 8, 0);
+        self.sync();
+        if self.len == 0 {
+            return self.stream.fill_buf();
+        }
+        self.bytes = self.buffer.to_le_bytes();
+        Ok(&self.bytes[..(self.len / 8) as usize])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.consume_bytes(amt)
+    }
 }
 
 ////////////////////////////////////////////////////////////////////////////////
@@ -116,6 +241,7 @@ impl<T: BufRead> BitReader<T> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use byteorder::ReadBytesExt;
 
     #[test]
     fn read_bits() -> io::Result<()> {
@@ -134,6 +260,82 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn peek_consume() -> io::Result<()> {
+        let data: &[u8] = &[0b01100011, 0b11011011, 0b10101111];
+        let mut reader = BitReader::new(data);
+        assert_eq!(reader.peek_bits(12)?, 0xb63);
+        assert_eq!(reader.peek_bits(3)?, 0b011);
+        reader.consume(3)?;
+        assert_eq!(reader.peek_bits(20)?, 0x05fb6c);
+        assert_eq!(reader.peek_bits(30)?, 0x15fb6c);
+        reader.consume(21)?;
+        assert_eq!(reader.peek_bits(8)?, 0);
+        assert_eq!(
+            reader.consume(1).unwrap_err().kind(),
+            io::ErrorKind::UnexpectedEof
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn stream_position() -> io::Result<()> {
+        let data: Vec<u8> = (0..=255).collect();
+        for capacity in [1, 3, 8, 1000] {
+            let mut stream = io::BufReader::with_capacity(capacity, data.as_slice());
+            {
+                let mut reader = BitReader::new(&mut stream);
+                assert_eq!(reader.read_bits(12)?.bits(), 0x100);
+                assert_eq!(reader.position(), 12);
+                assert_eq!(reader.borrow_reader_from_boundary().read_u8()?, 2);
+                assert_eq!(reader.position(), 24);
+                assert_eq!(reader.read_bits(16)?.bits(), 0x0403);
+                reader.borrow_reader_from_boundary();
+                assert_eq!(reader.position(), 40);
+            }
+            if capacity >= 8 {
+                assert_eq!(stream.read_u8()?, 5);
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn read_aligned_bytes() -> io::Result<()> {
+        let data: Vec<u8> = (0..100).collect();
+        let mut reader = BitReader::new(io::BufReader::with_capacity(7, data.as_slice()));
+        assert_eq!(reader.read_bits(16)?.bits(), 0x0100);
+        let mut buf = [0u8; 90];
+        reader.read_aligned_bytes(&mut buf)?;
+        assert_eq!(buf.as_slice(), &data[2..92]);
+        assert_eq!(reader.read_bits(8)?.bits(), 92);
+        let mut buf = [0u8; 8];
+        assert_eq!(
+            reader.read_aligned_bytes(&mut buf).unwrap_err().kind(),
+            io::ErrorKind::UnexpectedEof
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn copy_aligned_bytes() -> io::Result<()> {
+        let data: Vec<u8> = (0..100).collect();
+        let mut reader = BitReader::new(io::BufReader::with_capacity(7, data.as_slice()));
+        assert_eq!(reader.read_bits(16)?.bits(), 0x0100);
+        let mut output = Vec::new();
+        reader.copy_aligned_bytes(90, &mut output)?;
+        assert_eq!(output, &data[2..92]);
+        assert_eq!(reader.read_bits(8)?.bits(), 92);
+        assert_eq!(
+            reader
+                .copy_aligned_bytes(8, &mut output)
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::UnexpectedEof
+        );
+        Ok(())
+    }
+
     #[test]
     fn borrow_reader_from_boundary() -> io::Result<()> {
         let data: &[u8] = &[0b01100011, 0b11011011, 0b10101111];
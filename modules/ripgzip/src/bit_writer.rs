@@ -0,0 +1,99 @@
+use std::io::{self, Write};
+
+use crate::bit_reader::BitSequence;
+
+////////////////////////////////////////////////////////////////////////////////
+
+pub struct BitWriter<T> {
+    stream: T,
+    buffer: u32,
+    len: u8,
+}
+
+impl<T: Write> BitWriter<T> {
+    pub fn new(stream: T) -> Self {
+        Self {
+            stream,
+            buffer: 0,
+            len: 0,
+        }
+    }
+
+    /// Write the bits of `seq` starting from the least significant one.
+    pub fn write_bits(&mut self, seq: BitSequence) -> io::Result<()> {
+        self.buffer |= (seq.bits() as u32) << self.len;
+        self.len += seq.len();
+        while self.len >= 8 {
+            self.stream.write_all(&[self.buffer as u8])?;
+            self.buffer >>= 8;
+            self.len -= 8;
+        }
+        Ok(())
+    }
+
+    /// Number of bits written into the current (incomplete) byte.
+    pub fn pending_bits(&self) -> u8 {
+        self.len
+    }
+
+    pub fn get_ref(&self) -> &T {
+        &self.stream
+    }
+
+    #[cfg(feature = "tokio")]
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.stream
+    }
+
+    /// Return the underlying writer, dropping the bits of an incomplete byte.
+    pub fn into_inner(self) -> T {
+        self.stream
+    }
+
+    /// Pad the current byte with zeroes and return a mutable reference
+    /// to the underlying writer.
+    pub fn borrow_writer_from_boundary(&mut self) -> io::Result<&mut T> {
+        if self.len > 0 {
+            self.stream.write_all(&[self.buffer as u8])?;
+            self.buffer = 0;
+            self.len = 0;
+        }
+        Ok(&mut self.stream)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_bits() -> io::Result<()> {
+        let mut data = Vec::new();
+        let mut writer = BitWriter::new(&mut data);
+        writer.write_bits(BitSequence::new(0b1, 1))?;
+        writer.write_bits(BitSequence::new(0b01, 2))?;
+        writer.write_bits(BitSequence::new(0b100, 3))?;
+        writer.write_bits(BitSequence::new(0b1101, 4))?;
+        writer.write_bits(BitSequence::new(0b10110, 5))?;
+        writer.write_bits(BitSequence::new(0b01011111, 8))?;
+        writer.write_bits(BitSequence::new(0b1, 1))?;
+        assert_eq!(writer.pending_bits(), 0);
+        assert_eq!(data, [0b01100011, 0b11011011, 0b10101111]);
+        Ok(())
+    }
+
+    #[test]
+    fn borrow_writer_from_boundary() -> io::Result<()> {
+        let mut data = Vec::new();
+        let mut writer = BitWriter::new(&mut data);
+        writer.write_bits(BitSequence::new(0b011, 3))?;
+        writer
+            .borrow_writer_from_boundary()?
+            .write_all(&[0b11011011])?;
+        writer.write_bits(BitSequence::new(0b10101111, 8))?;
+        assert_eq!(data, [0b00000011, 0b11011011, 0b10101111]);
+        Ok(())
+    }
+}
@@ -0,0 +1,108 @@
+////////////////////////////////////////////////////////////////////////////////
+
+/// No chunk is cut before it has that many bytes.
+const MIN_CHUNK_SIZE: usize = 4096;
+
+/// A chunk ends where the top bits of the hash are zero, 8 KiB after the minimum on average.
+const HASH_BITS: u32 = 13;
+
+/// Random values of the bytes, from the SplitMix64 generator.
+static GEAR: [u64; 256] = gear();
+
+const fn gear() -> [u64; 256] {
+    let mut table = [0; 256];
+    let mut state = 0u64;
+    let mut i = 0;
+    while i < 256 {
+        state = state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+}
+
+/// Cuts the input into content-defined chunks with a gear rolling hash, as in FastCDC.
+///
+/// The hash only depends on the last 64 bytes, so after an edit the chunk boundaries
+/// fall back into place as soon as the input is the same again.
+pub struct Chunker {
+    hash: u64,
+    /// Number of bytes since the last boundary.
+    chunk_size: usize,
+}
+
+impl Chunker {
+    pub fn new() -> Self {
+        Self {
+            hash: 0,
+            chunk_size: 0,
+        }
+    }
+
+    /// Feed `data` until the end of the current chunk, returning the number of bytes
+    /// consumed if a boundary was found. Otherwise all of `data` belongs to the chunk.
+    pub fn find_boundary(&mut self, data: &[u8]) -> Option<usize> {
+        for (i, &byte) in data.iter().enumerate() {
+            self.hash = (self.hash << 1).wrapping_add(GEAR[byte as usize]);
+            self.chunk_size += 1;
+            if self.chunk_size >= MIN_CHUNK_SIZE && self.hash >> (64 - HASH_BITS) == 0 {
+                self.chunk_size = 0;
+                return Some(i + 1);
+            }
+        }
+        None
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+// The generator of the integration tests, for the same data in both.
+#[cfg(test)]
+#[path = "../tests/common/random.rs"]
+mod random;
+
+#[cfg(test)]
+mod tests {
+    use super::random::random_bytes;
+    use super::*;
+
+    fn boundaries(data: &[u8], piece_size: usize) -> Vec<usize> {
+        let mut chunker = Chunker::new();
+        let mut boundaries = Vec::new();
+        let mut offset = 0;
+        for mut piece in data.chunks(piece_size) {
+            while let Some(len) = chunker.find_boundary(piece) {
+                offset += len;
+                boundaries.push(offset);
+                piece = &piece[len..];
+            }
+            offset += piece.len();
+        }
+        boundaries
+    }
+
+    #[test]
+    fn content_defined() {
+        let data = random_bytes(1 << 21, 1);
+        let expected = boundaries(&data, data.len());
+        assert_eq!(boundaries(&data, 1000), expected);
+        assert_eq!(boundaries(&data, 1), expected);
+        // About 12 KiB per chunk.
+        assert!((120..220).contains(&expected.len()), "{}", expected.len());
+        let sizes = expected.windows(2).map(|pair| pair[1] - pair[0]);
+        assert!(sizes.min().unwrap() >= MIN_CHUNK_SIZE);
+
+        // The boundaries after an insertion are shifted along with the data.
+        let mut edited = data.clone();
+        edited.splice(100_000..100_000, random_bytes(777, 2));
+        let shifted: Vec<_> = boundaries(&edited, 4096)
+            .into_iter()
+            .filter(|&pos| pos > 300_000)
+            .map(|pos| pos - 777)
+            .collect();
+        assert!(expected.ends_with(&shifted));
+    }
+}
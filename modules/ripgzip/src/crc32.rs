@@ -0,0 +1,309 @@
+////////////////////////////////////////////////////////////////////////////////
+
+/// The reversed IEEE polynomial used by gzip.
+const POLYNOMIAL: u32 = 0xedb88320;
+
+/// Lookup tables for slicing-by-8: `TABLES[k][n]` is the CRC of byte `n` followed by `k` zeros.
+static TABLES: [[u32; 256]; 8] = tables();
+
+const fn tables() -> [[u32; 256]; 8] {
+    let mut tables = [[0; 256]; 8];
+    let mut n = 0;
+    while n < 256 {
+        let mut crc = n as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                POLYNOMIAL ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        tables[0][n] = crc;
+        n += 1;
+    }
+    let mut k = 1;
+    while k < 8 {
+        let mut n = 0;
+        while n < 256 {
+            let prev = tables[k - 1][n];
+            tables[k][n] = (prev >> 8) ^ tables[0][(prev & 0xff) as usize];
+            n += 1;
+        }
+        k += 1;
+    }
+    tables
+}
+
+/// CRC32 of a byte stream, as stored in the gzip member footer.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Crc32 {
+    crc: u32,
+}
+
+impl Crc32 {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn write(&mut self, data: &[u8]) {
+        self.crc = update(self.crc, data);
+    }
+
+    pub fn sum32(&self) -> u32 {
+        self.crc
+    }
+}
+
+/// The CRC32 of `data`.
+pub fn checksum(data: &[u8]) -> u32 {
+    update(0, data)
+}
+
+/// Extend the CRC32 of a byte sequence to the one of the sequence followed by `data`.
+fn update(crc: u32, data: &[u8]) -> u32 {
+    #[cfg(target_arch = "x86_64")]
+    if data.len() >= pclmul::MIN_LEN {
+        if let Some(crc) = pclmul::update(crc, data) {
+            return crc;
+        }
+    }
+    update_slicing(crc, data)
+}
+
+/// Table-driven CRC32 processing 8 bytes per step.
+fn update_slicing(crc: u32, data: &[u8]) -> u32 {
+    let mut crc = !crc;
+    let mut chunks = data.chunks_exact(8);
+    for chunk in &mut chunks {
+        let low = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]) ^ crc;
+        let high = u32::from_le_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]);
+        crc = TABLES[7][(low & 0xff) as usize]
+            ^ TABLES[6][(low >> 8 & 0xff) as usize]
+            ^ TABLES[5][(low >> 16 & 0xff) as usize]
+            ^ TABLES[4][(low >> 24) as usize]
+            ^ TABLES[3][(high & 0xff) as usize]
+            ^ TABLES[2][(high >> 8 & 0xff) as usize]
+            ^ TABLES[1][(high >> 16 & 0xff) as usize]
+            ^ TABLES[0][(high >> 24) as usize];
+    }
+    for &byte in chunks.remainder() {
+        crc = TABLES[0][((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+
+/// Carry-less multiplication folding, after Intel's "Fast CRC Computation for Generic
+/// Polynomials Using PCLMULQDQ Instruction", used when the CPU supports it.
+#[cfg(target_arch = "x86_64")]
+#[allow(unsafe_code)]
+mod pclmul {
+    use std::arch::x86_64::*;
+
+    use super::update_slicing;
+
+    /// Inputs shorter than this are not worth the setup of the folding.
+    pub const MIN_LEN: usize = 128;
+
+    // Powers of x modulo the polynomial, bit-reflected: x^(4*128+32), x^(4*128-32),
+    // x^(128+32), x^(128-32) and x^64 for folding, then P(x) and floor(x^64 / P(x)).
+    const K1: i64 = 0x1_5444_2bd4;
+    const K2: i64 = 0x1_c6e4_1596;
+    const K3: i64 = 0x1_7519_97d0;
+    const K4: i64 = 0x0_ccaa_009e;
+    const K5: i64 = 0x1_63cd_6124;
+    const P_X: i64 = 0x1_db71_0641;
+    const U_PRIME: i64 = 0x1_f701_1641;
+
+    /// `None` if the CPU lacks the required instructions.
+    pub fn update(crc: u32, data: &[u8]) -> Option<u32> {
+        if !is_x86_feature_detected!("pclmulqdq") || !is_x86_feature_detected!("sse4.1") {
+            return None;
+        }
+        // SAFETY: the target features were just detected.
+        Some(unsafe { fold(crc, data) })
+    }
+
+    #[target_feature(enable = "pclmulqdq", enable = "sse4.1")]
+    fn fold(crc: u32, mut data: &[u8]) -> u32 {
+        debug_assert!(data.len() >= MIN_LEN);
+
+        // Fold four 128-bit lanes by 512 bits at a time.
+        let mut x3 = load(&mut data);
+        let mut x2 = load(&mut data);
+        let mut x1 = load(&mut data);
+        let mut x0 = load(&mut data);
+        x3 = _mm_xor_si128(x3, _mm_cvtsi32_si128(!crc as i32));
+        let k1k2 = _mm_set_epi64x(K2, K1);
+        while data.len() >= 64 {
+            x3 = reduce128(x3, load(&mut data), k1k2);
+            x2 = reduce128(x2, load(&mut data), k1k2);
+            x1 = reduce128(x1, load(&mut data), k1k2);
+            x0 = reduce128(x0, load(&mut data), k1k2);
+        }
+
+        // Fold the lanes into one, then the rest of the input 128 bits at a time.
+        let k3k4 = _mm_set_epi64x(K4, K3);
+        let mut x = reduce128(x3, x2, k3k4);
+        x = reduce128(x, x1, k3k4);
+        x = reduce128(x, x0, k3k4);
+        while data.len() >= 16 {
+            x = reduce128(x, load(&mut data), k3k4);
+        }
+
+        // Reduce 128 bits to 96, then to 64.
+        let low32 = _mm_set_epi32(0, 0, 0, !0);
+        let x = _mm_xor_si128(_mm_clmulepi64_si128(x, k3k4, 0x10), _mm_srli_si128(x, 8));
+        let x = _mm_xor_si128(
+            _mm_clmulepi64_si128(_mm_and_si128(x, low32), _mm_set_epi64x(0, K5), 0x00),
+            _mm_srli_si128(x, 4),
+        );
+
+        // Barrett reduction to 32 bits.
+        let pu = _mm_set_epi64x(U_PRIME, P_X);
+        let t1 = _mm_clmulepi64_si128(_mm_and_si128(x, low32), pu, 0x10);
+        let t2 = _mm_clmulepi64_si128(_mm_and_si128(t1, low32), pu, 0x00);
+        let crc = !(_mm_extract_epi32(_mm_xor_si128(x, t2), 1) as u32);
+
+        update_slicing(crc, data)
+    }
+
+    #[target_feature(enable = "pclmulqdq", enable = "sse4.1")]
+    fn reduce128(a: __m128i, b: __m128i, keys: __m128i) -> __m128i {
+        let t1 = _mm_clmulepi64_si128(a, keys, 0x00);
+        let t2 = _mm_clmulepi64_si128(a, keys, 0x11);
+        _mm_xor_si128(_mm_xor_si128(b, t1), t2)
+    }
+
+    #[target_feature(enable = "sse4.1")]
+    fn load(data: &mut &[u8]) -> __m128i {
+        let (chunk, rest) = data.split_at(16);
+        *data = rest;
+        // SAFETY: `chunk` has 16 bytes and the load is unaligned.
+        unsafe { _mm_loadu_si128(chunk.as_ptr() as *const __m128i) }
+    }
+}
+
+type Gf2Matrix = [u32; 32];
+
+fn gf2_matrix_times(matrix: &Gf2Matrix, mut vector: u32) -> u32 {
+    let mut sum = 0;
+    for row in matrix {
+        if vector == 0 {
+            break;
+        }
+        if vector & 1 != 0 {
+            sum ^= row;
+        }
+        vector >>= 1;
+    }
+    sum
+}
+
+fn gf2_matrix_square(matrix: &Gf2Matrix) -> Gf2Matrix {
+    let mut square = [0; 32];
+    for (row, &value) in square.iter_mut().zip(matrix) {
+        *row = gf2_matrix_times(matrix, value);
+    }
+    square
+}
+
+/// Compute the CRC32 of the concatenation of two byte sequences
+/// given their CRC32s and the length of the second one, as zlib's `crc32_combine`.
+pub fn crc32_combine(mut crc1: u32, crc2: u32, mut len2: u64) -> u32 {
+    if len2 == 0 {
+        return crc1;
+    }
+
+    // The operator appending a single zero bit to the message.
+    let mut odd = [0; 32];
+    odd[0] = POLYNOMIAL;
+    for (n, row) in odd.iter_mut().enumerate().skip(1) {
+        *row = 1 << (n - 1);
+    }
+    // Two and four zero bits.
+    let mut even = gf2_matrix_square(&odd);
+    odd = gf2_matrix_square(&even);
+
+    // Append `len2` zero bytes to crc1, squaring the operator for every bit of `len2`.
+    loop {
+        even = gf2_matrix_square(&odd);
+        if len2 & 1 != 0 {
+            crc1 = gf2_matrix_times(&even, crc1);
+        }
+        len2 >>= 1;
+        if len2 == 0 {
+            break;
+        }
+        odd = gf2_matrix_square(&even);
+        if len2 & 1 != 0 {
+            crc1 = gf2_matrix_times(&odd, crc1);
+        }
+        len2 >>= 1;
+        if len2 == 0 {
+            break;
+        }
+    }
+    crc1 ^ crc2
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crc::crc32;
+
+    fn sample(len: usize) -> Vec<u8> {
+        (0..len as u32).map(|i| (i * 7 + i / 13) as u8).collect()
+    }
+
+    #[test]
+    fn slicing() {
+        let data = sample(1000);
+        for start in 0..8 {
+            for end in (start..data.len()).step_by(37) {
+                let part = &data[start..end];
+                assert_eq!(update_slicing(0, part), crc32::checksum_ieee(part));
+            }
+        }
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn pclmul() {
+        let data = sample(10_000);
+        for len in (pclmul::MIN_LEN..1000).chain([4096, 9990]) {
+            for start in [0, 1, 3] {
+                let part = &data[start..start + len];
+                if let Some(crc) = pclmul::update(0, part) {
+                    assert_eq!(crc, crc32::checksum_ieee(part), "length {}", len);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn incremental() {
+        let data = sample(100_000);
+        let mut crc = Crc32::new();
+        assert_eq!(crc.sum32(), 0);
+        for chunk in data.chunks(1021) {
+            crc.write(chunk);
+        }
+        assert_eq!(crc.sum32(), checksum(&data));
+        assert_eq!(checksum(b"123456789"), 0xcbf43926);
+    }
+
+    #[test]
+    fn combine() {
+        let data = sample(100_000);
+        let whole = checksum(&data);
+        for split in [0, 1, 1000, 65536, 99_999, 100_000] {
+            let (first, second) = data.split_at(split);
+            let combined = crc32_combine(checksum(first), checksum(second), second.len() as u64);
+            assert_eq!(combined, whole, "split at {}", split);
+        }
+    }
+}
@@ -0,0 +1,705 @@
+use std::cmp::min;
+use std::collections::VecDeque;
+use std::io::{self, BufRead, Read, Write};
+
+use log::debug;
+
+use crate::adler32;
+use crate::bit_reader::BitReader;
+use crate::deflate::{CompressionType, DeflateReader};
+use crate::error::{Error, Limit, Location, Result};
+use crate::gzip::{GzipReader, MemberHeader, MemberReader};
+use crate::huffman_coding::{
+    decode_litlen_distance_trees, fixed_litlen_distance_trees, DistanceToken, HuffmanCoding,
+    LitLenToken,
+};
+use crate::tracking_writer::{Checksum, TrackingWriter};
+use crate::zlib::ZlibReader;
+use crate::Format;
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Amount of decoded data after which the decoder stops and hands it out.
+const OUTPUT_CHUNK_SIZE: usize = 32768;
+
+/// Input enough for any step but reading a gzip member header: a chunk of output
+/// takes at most 15 bits per byte, and block headers and footers are much smaller.
+#[cfg(feature = "tokio")]
+const MAX_STEP_INPUT: usize = 2 * OUTPUT_CHUNK_SIZE + 1024;
+
+enum State {
+    MemberHeader,
+    BlockHeader,
+    Stored {
+        remaining: usize,
+    },
+    Huffman {
+        litlen_coding: HuffmanCoding<LitLenToken>,
+        distance_coding: HuffmanCoding<DistanceToken>,
+    },
+    MemberFooter,
+    Done,
+}
+
+/// Resource caps for decompressing untrusted input. All of them are disabled by default.
+///
+/// Decoding stops with `Error::LimitExceeded` as soon as a cap is crossed.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DecompressOptions {
+    /// Maximum total size of the decompressed data.
+    pub max_output_size: Option<u64>,
+    /// Maximum ratio of the decompressed size to the compressed bytes consumed so far.
+    pub max_ratio: Option<u64>,
+    /// Maximum number of gzip members.
+    pub max_members: Option<usize>,
+    /// Maximum size of each of the FEXTRA, FNAME and FCOMMENT header fields.
+    pub max_header_field_size: Option<usize>,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// The decoding state machine shared by all the container formats.
+pub(crate) struct Decoder<R> {
+    format: Format,
+    reader: DeflateReader<R>,
+    writer: TrackingWriter<VecDeque<u8>>,
+    state: State,
+    is_final_block: bool,
+    header: Option<MemberHeader>,
+    member_count: usize,
+    /// Whether the current member was decoded from its start, so that its footer can be checked.
+    is_member_complete: bool,
+    options: DecompressOptions,
+    /// Total size of the output produced so far.
+    output_size: u64,
+    /// The preset dictionary of a zlib stream, used only if the header asks for it.
+    dictionary: Option<Vec<u8>>,
+}
+
+impl<R: BufRead> Decoder<R> {
+    pub(crate) fn new(input: R, format: Format) -> Self {
+        Self {
+            format,
+            reader: DeflateReader::new(BitReader::new(input)),
+            writer: TrackingWriter::new(
+                VecDeque::with_capacity(2 * OUTPUT_CHUNK_SIZE),
+                match format {
+                    Format::Gzip => Checksum::crc32(),
+                    Format::Zlib => Checksum::adler32(),
+                    Format::Raw => Checksum::None,
+                },
+            ),
+            state: State::MemberHeader,
+            is_final_block: false,
+            header: None,
+            member_count: 0,
+            is_member_complete: true,
+            options: DecompressOptions::default(),
+            output_size: 0,
+            dictionary: None,
+        }
+    }
+
+    /// Make `dictionary` available to the matches at the start of the stream.
+    /// Zlib streams use it only if their header refers to it.
+    pub(crate) fn with_dictionary(input: R, format: Format, dictionary: &[u8]) -> Self {
+        let mut decoder = Self::new(input, format);
+        match format {
+            Format::Zlib => decoder.dictionary = Some(dictionary.to_vec()),
+            _ => decoder.writer.seed_history(dictionary),
+        }
+        decoder
+    }
+
+    pub(crate) fn with_options(input: R, format: Format, options: DecompressOptions) -> Self {
+        Self {
+            options,
+            ..Self::new(input, format)
+        }
+    }
+
+    /// Continue decoding a gzip stream from a block boundary in the middle of a member.
+    ///
+    /// `input` must be positioned at the byte containing the first bit of the block,
+    /// `skip_bits` is the offset of that bit and `window` is the output preceding the block.
+    /// The footer of the member is not checked, as only a part of it is decoded.
+    pub(crate) fn resume(input: R, skip_bits: u8, window: &[u8]) -> Result<Self> {
+        let mut decoder = Self::new(input, Format::Gzip);
+        decoder.reader.bit_reader_mut().consume(skip_bits)?;
+        decoder.writer.seed_history(window);
+        decoder.state = State::BlockHeader;
+        decoder.is_member_complete = false;
+        Ok(decoder)
+    }
+
+    pub(crate) fn into_inner(self) -> R {
+        self.reader.into_inner().into_inner()
+    }
+
+    #[cfg(feature = "tokio")]
+    pub(crate) fn input(&self) -> &R {
+        self.reader.bit_reader().get_ref()
+    }
+
+    /// The input stream, which may only be extended or have its consumed part dropped.
+    #[cfg(feature = "tokio")]
+    pub(crate) fn input_mut(&mut self) -> &mut R {
+        self.reader.bit_reader_mut().get_mut()
+    }
+
+    /// Header of the last gzip member started.
+    pub(crate) fn header(&self) -> Option<&MemberHeader> {
+        self.header.as_ref()
+    }
+
+    /// The decoded output not consumed yet, without decoding any further.
+    pub(crate) fn output(&self) -> &[u8] {
+        self.writer.get_ref().as_slices().0
+    }
+
+    /// Whether the next step cannot run out of data given the `input` following
+    /// the current byte position, in case more of the stream is yet to come.
+    #[cfg(feature = "tokio")]
+    pub(crate) fn has_step_input(&self, input: &[u8]) -> bool {
+        match (&self.state, self.format) {
+            (State::Done, _) => true,
+            (State::MemberHeader, Format::Gzip) => {
+                match GzipReader::new(input)
+                    .with_max_field_size(self.options.max_header_field_size)
+                    .next_member()
+                {
+                    None => false,
+                    Some(Ok(_)) => true,
+                    Some(Err(err)) => io::Error::from(err).kind() != io::ErrorKind::UnexpectedEof,
+                }
+            }
+            _ => input.len() >= MAX_STEP_INPUT,
+        }
+    }
+
+    /// Decode the stream until some output is available and return it.
+    /// An empty slice means that the stream is over.
+    pub(crate) fn decode(&mut self) -> Result<&[u8]> {
+        while self.writer.get_ref().is_empty() && !self.is_done() {
+            self.step()?;
+        }
+        Ok(self.output())
+    }
+
+    pub(crate) fn consume(&mut self, amt: usize) {
+        self.writer.get_mut().drain(..amt);
+    }
+
+    /// Drop all the decoded output, returning its length.
+    pub(crate) fn discard_output(&mut self) -> usize {
+        let len = self.writer.get_ref().len();
+        self.writer.get_mut().clear();
+        len
+    }
+
+    /// Move all the decoded output to the end of `output`.
+    pub(crate) fn drain_output(&mut self, output: &mut Vec<u8>) {
+        output.extend(self.writer.get_mut().drain(..));
+    }
+
+    pub(crate) fn is_done(&self) -> bool {
+        matches!(self.state, State::Done)
+    }
+
+    /// Number of members whose decoding has started.
+    pub(crate) fn member_count(&self) -> usize {
+        self.member_count
+    }
+
+    /// Whether the next step reads a member header or the stream is over.
+    pub(crate) fn is_at_member_boundary(&self) -> bool {
+        matches!(self.state, State::MemberHeader | State::Done)
+    }
+
+    /// Whether the next step starts a new DEFLATE block.
+    pub(crate) fn is_at_block_boundary(&self) -> bool {
+        matches!(self.state, State::BlockHeader)
+    }
+
+    /// Number of input bits read so far.
+    pub(crate) fn input_position(&self) -> u64 {
+        self.reader.bit_reader().position()
+    }
+
+    /// The output preceding the current position in the member, up to 32 KiB.
+    pub(crate) fn window(&self) -> Vec<u8> {
+        self.writer.history()
+    }
+
+    /// Index of the member being decoded.
+    fn member_index(&self) -> usize {
+        match self.state {
+            State::MemberHeader => self.member_count,
+            _ => self.member_count.saturating_sub(1),
+        }
+    }
+
+    pub(crate) fn step(&mut self) -> Result<()> {
+        let member = self.member_index();
+        let position = match self.state {
+            // Headers and footers start at the next byte boundary.
+            State::MemberHeader | State::MemberFooter => self.input_position().next_multiple_of(8),
+            _ => self.input_position(),
+        };
+        let buffered = self.writer.get_ref().len();
+        self.step_inner()
+            .and_then(|_| {
+                self.output_size += (self.writer.get_ref().len() - buffered) as u64;
+                self.check_output_size()
+            })
+            .map_err(|err| err.locate(member, position))
+    }
+
+    fn check_output_size(&self) -> Result<()> {
+        let input_size = self.input_position().div_ceil(8).max(1);
+        let limit = match self.options {
+            DecompressOptions {
+                max_output_size: Some(max),
+                ..
+            } if self.output_size > max => Limit::OutputSize(max),
+            DecompressOptions {
+                max_ratio: Some(max),
+                ..
+            } if self.output_size > max.saturating_mul(input_size) => Limit::Ratio(max),
+            _ => return Ok(()),
+        };
+        Err(Error::LimitExceeded {
+            limit,
+            location: Location::default(),
+        })
+    }
+
+    fn step_inner(&mut self) -> Result<()> {
+        let next_state = match &self.state {
+            State::MemberHeader => {
+                let stream = self.reader.bit_reader_mut().borrow_reader_from_boundary();
+                match self.format {
+                    Format::Gzip => match GzipReader::new(stream)
+                        .with_max_field_size(self.options.max_header_field_size)
+                        .next_member()
+                    {
+                        None => State::Done,
+                        Some(member) => {
+                            if let Some(max) = self.options.max_members {
+                                if self.member_count >= max {
+                                    return Err(Error::LimitExceeded {
+                                        limit: Limit::MemberCount(max),
+                                        location: Location::default(),
+                                    });
+                                }
+                            }
+                            let (header, _) = member?;
+                            debug!(
+                                "Member {}: name {:?}, comment {:?}, mtime {}, os {}",
+                                self.member_count,
+                                header.name,
+                                header.comment,
+                                header.modification_time,
+                                header.os_name()
+                            );
+                            self.header = Some(header);
+                            self.member_count += 1;
+                            State::BlockHeader
+                        }
+                    },
+                    Format::Zlib => {
+                        let header = ZlibReader::new(stream).read_header()?;
+                        if let Some(id) = header.dictionary_id {
+                            match &self.dictionary {
+                                Some(dictionary) if adler32::checksum(dictionary) == id => {
+                                    self.writer.seed_history(dictionary)
+                                }
+                                _ => {
+                                    return Err(Error::DictionaryRequired {
+                                        id,
+                                        location: Location::default(),
+                                    })
+                                }
+                            }
+                        }
+                        State::BlockHeader
+                    }
+                    Format::Raw => State::BlockHeader,
+                }
+            }
+            State::BlockHeader => {
+                let (header, r) = self.reader.next_block().unwrap()?;
+                self.is_final_block = header.is_final;
+                match header.compression_type {
+                    CompressionType::Uncompressed => {
+                        let _ = r.borrow_reader_from_boundary();
+                        let len = r.read_bits(16)?.bits();
+                        let nlen = r.read_bits(16)?.bits();
+                        if len != !nlen {
+                            return Err(Error::StoredLengthMismatch {
+                                location: Location::default(),
+                            });
+                        }
+                        State::Stored {
+                            remaining: len as usize,
+                        }
+                    }
+                    CompressionType::FixedTree | CompressionType::DynamicTree => {
+                        let (litlen_coding, distance_coding) = match header.compression_type {
+                            CompressionType::FixedTree => fixed_litlen_distance_trees()?,
+                            _ => decode_litlen_distance_trees(r)?,
+                        };
+                        State::Huffman {
+                            litlen_coding,
+                            distance_coding,
+                        }
+                    }
+                    CompressionType::Reserved => {
+                        return Err(Error::UnsupportedBlockType {
+                            location: Location::default(),
+                        });
+                    }
+                }
+            }
+            State::Stored { remaining } => {
+                let len = min(*remaining, OUTPUT_CHUNK_SIZE);
+                self.reader
+                    .bit_reader_mut()
+                    .copy_aligned_bytes(len, &mut self.writer)?;
+                match *remaining - len {
+                    0 => self.end_of_block(),
+                    remaining => State::Stored { remaining },
+                }
+            }
+            State::Huffman {
+                litlen_coding,
+                distance_coding,
+            } => {
+                let r = self.reader.bit_reader_mut();
+                let mut end_of_block = false;
+                while self.writer.get_ref().len() < OUTPUT_CHUNK_SIZE {
+                    match litlen_coding.read_symbol(r)? {
+                        LitLenToken::Literal(lit) => {
+                            self.writer.write_all(&[lit])?;
+                        }
+                        LitLenToken::EndOfBlock => {
+                            end_of_block = true;
+                            break;
+                        }
+                        LitLenToken::Length { base, extra_bits } => {
+                            let len = (base + r.read_bits(extra_bits)?.bits()) as usize;
+                            let distance_token = distance_coding.read_symbol(r)?;
+                            let distance = (distance_token.base
+                                + r.read_bits(distance_token.extra_bits)?.bits())
+                                as usize;
+                            self.writer
+                                .write_previous(distance, len)
+                                .map_err(|err| err.locate(0, r.position()))?;
+                        }
+                    }
+                }
+                if !end_of_block {
+                    return Ok(());
+                }
+                self.end_of_block()
+            }
+            State::MemberFooter => {
+                let stream = self.reader.bit_reader_mut().borrow_reader_from_boundary();
+                match self.format {
+                    Format::Gzip => {
+                        let (footer, _) = MemberReader::new(stream).read_footer()?;
+                        if self.is_member_complete {
+                            let size = self.writer.byte_count() as u32;
+                            if footer.data_size != size {
+                                return Err(Error::LengthMismatch {
+                                    stored: footer.data_size,
+                                    computed: size,
+                                    location: Location::default(),
+                                });
+                            }
+                            let crc32 = self.writer.checksum();
+                            if footer.data_crc32 != crc32 {
+                                return Err(Error::CrcMismatch {
+                                    stored: footer.data_crc32,
+                                    computed: crc32,
+                                    location: Location::default(),
+                                });
+                            }
+                        }
+                        self.is_member_complete = true;
+                        self.writer.flush()?;
+                        State::MemberHeader
+                    }
+                    Format::Zlib => {
+                        let footer = ZlibReader::new(stream).read_footer()?;
+                        let adler32 = self.writer.checksum();
+                        if footer.data_adler32 != adler32 {
+                            return Err(Error::Adler32Mismatch {
+                                stored: footer.data_adler32,
+                                computed: adler32,
+                                location: Location::default(),
+                            });
+                        }
+                        State::Done
+                    }
+                    Format::Raw => State::Done,
+                }
+            }
+            State::Done => State::Done,
+        };
+        self.state = next_state;
+        Ok(())
+    }
+
+    fn end_of_block(&self) -> State {
+        if self.is_final_block {
+            State::MemberFooter
+        } else {
+            State::BlockHeader
+        }
+    }
+}
+
+pub(crate) fn into_io_error(err: Error) -> io::Error {
+    err.into()
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// A pull-based decoder of (possibly multi-member) gzip streams.
+pub struct GzDecoder<R> {
+    inner: Decoder<R>,
+}
+
+impl<R: BufRead> GzDecoder<R> {
+    pub fn new(input: R) -> Self {
+        Self {
+            inner: Decoder::new(input, Format::Gzip),
+        }
+    }
+
+    pub fn with_options(input: R, options: DecompressOptions) -> Self {
+        Self {
+            inner: Decoder::with_options(input, Format::Gzip, options),
+        }
+    }
+
+    /// Like `new`, for streams whose first member was compressed with the preset `dictionary`,
+    /// as written by `GzEncoder::with_dictionary`.
+    pub fn with_dictionary(input: R, dictionary: &[u8]) -> Self {
+        Self {
+            inner: Decoder::with_dictionary(input, Format::Gzip, dictionary),
+        }
+    }
+
+    /// Header of the member being decoded, available once the first read returns.
+    pub fn header(&self) -> Option<&MemberHeader> {
+        self.inner.header()
+    }
+
+    pub(crate) fn decode(&mut self) -> Result<&[u8]> {
+        self.inner.decode()
+    }
+}
+
+impl<R: BufRead> Read for GzDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        read_buffered(self, buf)
+    }
+}
+
+impl<R: BufRead> BufRead for GzDecoder<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.decode().map_err(into_io_error)
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.inner.consume(amt);
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// An iterator over the headers of all the members of a gzip stream.
+///
+/// Every member is fully decoded and verified before the next header is returned,
+/// but the decompressed data is discarded.
+pub struct MemberHeaders<R> {
+    inner: Decoder<R>,
+    failed: bool,
+}
+
+impl<R: BufRead> MemberHeaders<R> {
+    pub fn new(input: R) -> Self {
+        Self {
+            inner: Decoder::new(input, Format::Gzip),
+            failed: false,
+        }
+    }
+
+    fn next_header(&mut self) -> Result<Option<MemberHeader>> {
+        let member_count = self.inner.member_count;
+        while self.inner.member_count == member_count {
+            if self.inner.is_done() {
+                return Ok(None);
+            }
+            self.inner.step()?;
+            self.inner.discard_output();
+        }
+        Ok(self.inner.header.clone())
+    }
+}
+
+impl<R: BufRead> Iterator for MemberHeaders<R> {
+    type Item = Result<MemberHeader>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        let res = self.next_header();
+        self.failed = res.is_err();
+        res.transpose()
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// A gzip member along with its sizes, as listed by `gzip -l`.
+#[derive(Clone, Debug)]
+pub struct MemberInfo {
+    pub header: MemberHeader,
+    /// Size of the member in the stream, including its header and footer.
+    pub compressed_size: u64,
+    pub uncompressed_size: u64,
+}
+
+/// An iterator over the members of a gzip stream, verifying each one entirely before returning it.
+pub struct Members<R> {
+    inner: Decoder<R>,
+    failed: bool,
+}
+
+impl<R: BufRead> Members<R> {
+    pub fn new(input: R) -> Self {
+        Self {
+            inner: Decoder::new(input, Format::Gzip),
+            failed: false,
+        }
+    }
+
+    fn next_member(&mut self) -> Result<Option<MemberInfo>> {
+        let start = self.inner.input_position();
+        self.inner.step()?;
+        if self.inner.is_done() {
+            return Ok(None);
+        }
+        let mut uncompressed_size = 0;
+        while !self.inner.is_at_member_boundary() {
+            self.inner.step()?;
+            uncompressed_size += self.inner.discard_output() as u64;
+        }
+        Ok(Some(MemberInfo {
+            header: self.inner.header.clone().expect("member header is read"),
+            compressed_size: (self.inner.input_position() - start) / 8,
+            uncompressed_size,
+        }))
+    }
+}
+
+impl<R: BufRead> Iterator for Members<R> {
+    type Item = Result<MemberInfo>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        let res = self.next_member();
+        self.failed = res.is_err();
+        res.transpose()
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// A pull-based decoder of zlib (RFC 1950) streams.
+pub struct ZlibDecoder<R> {
+    inner: Decoder<R>,
+}
+
+impl<R: BufRead> ZlibDecoder<R> {
+    pub fn new(input: R) -> Self {
+        Self {
+            inner: Decoder::new(input, Format::Zlib),
+        }
+    }
+
+    /// Like `new`, but accepting streams compressed with the preset `dictionary`.
+    pub fn with_dictionary(input: R, dictionary: &[u8]) -> Self {
+        Self {
+            inner: Decoder::with_dictionary(input, Format::Zlib, dictionary),
+        }
+    }
+}
+
+impl<R: BufRead> Read for ZlibDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        read_buffered(self, buf)
+    }
+}
+
+impl<R: BufRead> BufRead for ZlibDecoder<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.decode().map_err(into_io_error)
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.inner.consume(amt);
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// A pull-based decoder of raw DEFLATE (RFC 1951) streams.
+pub struct DeflateDecoder<R> {
+    inner: Decoder<R>,
+}
+
+impl<R: BufRead> DeflateDecoder<R> {
+    pub fn new(input: R) -> Self {
+        Self {
+            inner: Decoder::new(input, Format::Raw),
+        }
+    }
+
+    /// Like `new`, for streams whose matches may refer to the last 32 KiB of `dictionary`.
+    pub fn with_dictionary(input: R, dictionary: &[u8]) -> Self {
+        Self {
+            inner: Decoder::with_dictionary(input, Format::Raw, dictionary),
+        }
+    }
+}
+
+impl<R: BufRead> Read for DeflateDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        read_buffered(self, buf)
+    }
+}
+
+impl<R: BufRead> BufRead for DeflateDecoder<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.decode().map_err(into_io_error)
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.inner.consume(amt);
+    }
+}
+
+fn read_buffered<T: BufRead>(reader: &mut T, buf: &mut [u8]) -> io::Result<usize> {
+    let data = reader.fill_buf()?;
+    let len = min(data.len(), buf.len());
+    buf[..len].copy_from_slice(&data[..len]);
+    reader.consume(len);
+    Ok(len)
+}
@@ -1,9 +1,14 @@
-use std::io::BufRead;
+use std::io::{self, BufRead, Write};
 
-use anyhow::{Result};
-
-use crate::bit_reader::{BitReader};
-use crate::CompressionType::{DynamicTree, FixedTree, Reserved, Uncompressed};
+use crate::bit_reader::{BitReader, BitSequence};
+use crate::bit_writer::BitWriter;
+use crate::deflate::CompressionType::{DynamicTree, FixedTree, Reserved, Uncompressed};
+use crate::error::Result;
+use crate::huffman_coding::{
+    build_codes, distance_code, length_code, DynamicTrees, DISTANCE_CODES, END_OF_BLOCK,
+    FIXED_DISTANCE_LENGTHS, FIXED_LITLEN_LENGTHS, LITLEN_CODES,
+};
+use crate::lz77::Token;
 
 ////////////////////////////////////////////////////////////////////////////////
 
@@ -32,6 +37,18 @@ impl<T: BufRead> DeflateReader<T> {
         Self { bit_reader }
     }
 
+    pub fn bit_reader(&self) -> &BitReader<T> {
+        &self.bit_reader
+    }
+
+    pub fn bit_reader_mut(&mut self) -> &mut BitReader<T> {
+        &mut self.bit_reader
+    }
+
+    pub fn into_inner(self) -> BitReader<T> {
+        self.bit_reader
+    }
+
     pub fn next_block(&mut self) -> Option<Result<(BlockHeader, &mut BitReader<T>)>> {
         Some(self.inner())
     }
@@ -55,3 +72,154 @@ impl<T: BufRead> DeflateReader<T> {
         ))
     }
 }
+
+////////////////////////////////////////////////////////////////////////////////
+
+const MAX_STORED_BLOCK_SIZE: usize = 65535;
+
+pub struct DeflateWriter<T> {
+    bit_writer: BitWriter<T>,
+}
+
+impl<T: Write> DeflateWriter<T> {
+    pub fn new(bit_writer: BitWriter<T>) -> Self {
+        Self { bit_writer }
+    }
+
+    /// Write `tokens` as a single block of whichever type is the smallest.
+    /// `data` is the uncompressed content of the block.
+    pub fn write_block(&mut self, tokens: &[Token], data: &[u8], is_final: bool) -> io::Result<()> {
+        let mut litlen_frequencies = [0u32; LITLEN_CODES];
+        let mut distance_frequencies = [0u32; DISTANCE_CODES];
+        let mut extra_bits = 0u64;
+        for token in tokens {
+            match *token {
+                Token::Literal(byte) => litlen_frequencies[byte as usize] += 1,
+                Token::Match { length, distance } => {
+                    let (length_symbol, length_extra) = length_code(length);
+                    let (distance_symbol, distance_extra) = distance_code(distance);
+                    litlen_frequencies[length_symbol] += 1;
+                    distance_frequencies[distance_symbol] += 1;
+                    extra_bits += (length_extra.len() + distance_extra.len()) as u64;
+                }
+            }
+        }
+        litlen_frequencies[END_OF_BLOCK] += 1;
+
+        let trees = DynamicTrees::new(&litlen_frequencies, &distance_frequencies);
+        let data_cost = |litlen_lengths: &[u8], distance_lengths: &[u8]| -> u64 {
+            let litlen: u64 = litlen_frequencies
+                .iter()
+                .zip(litlen_lengths)
+                .map(|(&freq, &len)| freq as u64 * len as u64)
+                .sum();
+            let distance: u64 = distance_frequencies
+                .iter()
+                .zip(distance_lengths)
+                .map(|(&freq, &len)| freq as u64 * len as u64)
+                .sum();
+            litlen + distance + extra_bits
+        };
+        let fixed_cost = data_cost(&FIXED_LITLEN_LENGTHS, &FIXED_DISTANCE_LENGTHS);
+        let dynamic_cost = trees.cost() + data_cost(&trees.litlen_lengths, &trees.distance_lengths);
+        let stored_cost = self.stored_cost(data.len());
+
+        if stored_cost <= fixed_cost.min(dynamic_cost) {
+            self.write_stored(data, is_final)
+        } else if fixed_cost <= dynamic_cost {
+            self.write_header(is_final, FixedTree)?;
+            self.write_tokens(
+                tokens,
+                &build_codes(&FIXED_LITLEN_LENGTHS),
+                &build_codes(&FIXED_DISTANCE_LENGTHS),
+            )
+        } else {
+            self.write_header(is_final, DynamicTree)?;
+            trees.write(&mut self.bit_writer)?;
+            self.write_tokens(
+                tokens,
+                &build_codes(&trees.litlen_lengths),
+                &build_codes(&trees.distance_lengths),
+            )
+        }
+    }
+
+    /// Pad the last byte of the stream and return the underlying writer.
+    pub fn finish(&mut self) -> io::Result<&mut T> {
+        self.bit_writer.borrow_writer_from_boundary()
+    }
+
+    pub fn get_ref(&self) -> &T {
+        self.bit_writer.get_ref()
+    }
+
+    #[cfg(feature = "tokio")]
+    pub fn get_mut(&mut self) -> &mut T {
+        self.bit_writer.get_mut()
+    }
+
+    pub fn into_inner(self) -> T {
+        self.bit_writer.into_inner()
+    }
+
+    fn stored_cost(&self, len: usize) -> u64 {
+        // Every stored block after the first one starts on a byte boundary.
+        let blocks = len.max(1).div_ceil(MAX_STORED_BLOCK_SIZE) as u64;
+        let padding = (8 - (self.bit_writer.pending_bits() + 3) % 8) % 8;
+        3 + padding as u64 + 32 + (blocks - 1) * (8 + 32) + 8 * len as u64
+    }
+
+    fn write_header(
+        &mut self,
+        is_final: bool,
+        compression_type: CompressionType,
+    ) -> io::Result<()> {
+        self.bit_writer
+            .write_bits(BitSequence::new(is_final as u16, 1))?;
+        self.bit_writer
+            .write_bits(BitSequence::new(compression_type as u16, 2))
+    }
+
+    /// Write `data` as stored blocks; an empty `data` makes a single empty block.
+    pub fn write_stored(&mut self, data: &[u8], is_final: bool) -> io::Result<()> {
+        let mut chunks = data.chunks(MAX_STORED_BLOCK_SIZE).peekable();
+        if chunks.peek().is_none() {
+            self.write_header(is_final, Uncompressed)?;
+            self.bit_writer
+                .borrow_writer_from_boundary()?
+                .write_all(&[0x00, 0x00, 0xff, 0xff])?;
+        }
+        while let Some(chunk) = chunks.next() {
+            self.write_header(is_final && chunks.peek().is_none(), Uncompressed)?;
+            let len = chunk.len() as u16;
+            let writer = self.bit_writer.borrow_writer_from_boundary()?;
+            writer.write_all(&len.to_le_bytes())?;
+            writer.write_all(&(!len).to_le_bytes())?;
+            writer.write_all(chunk)?;
+        }
+        Ok(())
+    }
+
+    fn write_tokens(
+        &mut self,
+        tokens: &[Token],
+        litlen_codes: &[BitSequence],
+        distance_codes: &[BitSequence],
+    ) -> io::Result<()> {
+        for token in tokens {
+            match *token {
+                Token::Literal(byte) => self.bit_writer.write_bits(litlen_codes[byte as usize])?,
+                Token::Match { length, distance } => {
+                    let (length_symbol, length_extra) = length_code(length);
+                    let (distance_symbol, distance_extra) = distance_code(distance);
+                    self.bit_writer.write_bits(litlen_codes[length_symbol])?;
+                    self.bit_writer.write_bits(length_extra)?;
+                    self.bit_writer
+                        .write_bits(distance_codes[distance_symbol])?;
+                    self.bit_writer.write_bits(distance_extra)?;
+                }
+            }
+        }
+        self.bit_writer.write_bits(litlen_codes[END_OF_BLOCK])
+    }
+}
@@ -0,0 +1,340 @@
+use std::cmp::min;
+use std::io::{self, Write};
+
+use crate::adler32::{self, Adler32};
+use crate::bit_writer::BitWriter;
+use crate::chunker::Chunker;
+use crate::crc32::Crc32;
+use crate::deflate::DeflateWriter;
+use crate::gzip::{GzipWriter, MemberFooter, MemberHeader, MemberWriter};
+use crate::lz77::{Flush, Level, Lz77Encoder, WINDOW_SIZE};
+use crate::zlib::{StreamFooter, StreamHeader, ZlibWriter};
+use crate::Format;
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Maximum amount of input accepted by a single `write` call.
+const INPUT_CHUNK_SIZE: usize = 65536;
+
+const XFL_BEST: u8 = 2;
+const XFL_FAST: u8 = 4;
+
+/// The compression pipeline shared by all the container formats.
+pub(crate) struct Encoder<W: Write> {
+    format: Format,
+    writer: DeflateWriter<W>,
+    encoder: Lz77Encoder,
+    level: Level,
+    /// The member header, only written in the gzip format.
+    header: MemberHeader,
+    header_written: bool,
+    digest: Crc32,
+    adler: Adler32,
+    data_size: u32,
+    /// Adler-32 of the preset dictionary, only written in the zlib format.
+    dictionary_id: Option<u32>,
+    /// Cuts the input into chunks ending blocks in the rsyncable mode.
+    chunker: Option<Chunker>,
+}
+
+impl<W: Write> Encoder<W> {
+    pub(crate) fn new(inner: W, level: Level, format: Format) -> Self {
+        Self {
+            format,
+            writer: DeflateWriter::new(BitWriter::new(inner)),
+            encoder: Lz77Encoder::new(level),
+            level,
+            header: member_header(MemberHeader::default(), level),
+            header_written: false,
+            digest: Crc32::new(),
+            adler: Adler32::new(),
+            data_size: 0,
+            dictionary_id: None,
+            chunker: None,
+        }
+    }
+
+    fn with_dictionary(inner: W, level: Level, format: Format, dictionary: &[u8]) -> Self {
+        let mut encoder = Self::new(inner, level, format);
+        encoder.encoder.set_dictionary(dictionary);
+        encoder.dictionary_id = Some(adler32::checksum(dictionary));
+        encoder
+    }
+
+    pub(crate) fn get_ref(&self) -> &W {
+        self.writer.get_ref()
+    }
+
+    /// The underlying writer, which only ever receives whole bytes.
+    #[cfg(feature = "tokio")]
+    pub(crate) fn get_mut(&mut self) -> &mut W {
+        self.writer.get_mut()
+    }
+
+    pub(crate) fn finish(mut self) -> io::Result<W> {
+        self.write_header()?;
+        self.write_blocks(Flush::Finish)?;
+        self.writer.finish()?;
+        let mut inner = self.writer.into_inner();
+        match self.format {
+            Format::Gzip => {
+                let footer = MemberFooter {
+                    data_crc32: self.digest.sum32(),
+                    data_size: self.data_size,
+                };
+                inner = MemberWriter::new(inner).write_footer(&footer)?.into_inner();
+            }
+            Format::Zlib => {
+                let footer = StreamFooter {
+                    data_adler32: self.adler.sum32(),
+                };
+                ZlibWriter::new(&mut inner).write_footer(&footer)?;
+            }
+            Format::Raw => {}
+        }
+        Ok(inner)
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        if self.header_written {
+            return Ok(());
+        }
+        self.header_written = true;
+        let level = self.level.level();
+        let stream = self.writer.finish()?;
+        match self.format {
+            Format::Gzip => {
+                GzipWriter::new(stream).write_header(&self.header)?;
+            }
+            Format::Zlib => {
+                let level = match level {
+                    0..=1 => 0,
+                    2..=5 => 1,
+                    6 => 2,
+                    _ => 3,
+                };
+                let header = StreamHeader {
+                    window_size: WINDOW_SIZE,
+                    level,
+                    dictionary_id: self.dictionary_id,
+                };
+                ZlibWriter::new(stream).write_header(&header)?;
+            }
+            Format::Raw => {}
+        }
+        Ok(())
+    }
+
+    fn write_blocks(&mut self, flush: Flush) -> io::Result<()> {
+        while let Some(block) = self.encoder.next_block(flush) {
+            if self.level == Level::none() {
+                self.writer.write_stored(block.data, block.is_final)?;
+            } else {
+                self.writer
+                    .write_block(block.tokens, block.data, block.is_final)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.write_header()?;
+        let mut buf = &buf[..min(buf.len(), INPUT_CHUNK_SIZE)];
+        let boundary = self
+            .chunker
+            .as_mut()
+            .and_then(|chunker| chunker.find_boundary(buf));
+        if let Some(len) = boundary {
+            buf = &buf[..len];
+        }
+        match self.format {
+            Format::Gzip => {
+                self.digest.write(buf);
+                self.data_size = self.data_size.wrapping_add(buf.len() as u32);
+            }
+            Format::Zlib => self.adler.write(buf),
+            Format::Raw => {}
+        }
+        self.encoder.push(buf);
+        self.write_blocks(Flush::None)?;
+        if boundary.is_some() {
+            // The next chunk starts a new block at a byte boundary.
+            self.write_blocks(Flush::Sync)?;
+            self.writer.write_stored(&[], false)?;
+        }
+        Ok(buf.len())
+    }
+
+    /// Compress all the buffered input and align the output with an empty stored block,
+    /// so that everything written so far can be decompressed.
+    pub(crate) fn flush(&mut self) -> io::Result<()> {
+        self.write_header()?;
+        self.write_blocks(Flush::Sync)?;
+        self.writer.write_stored(&[], false)?;
+        self.writer.finish()?.flush()
+    }
+}
+
+/// `header` with the extra flags describing the given level.
+pub(crate) fn member_header(header: MemberHeader, level: Level) -> MemberHeader {
+    let extra_flags = match level.level() {
+        1 => XFL_FAST,
+        9.. => XFL_BEST,
+        _ => 0,
+    };
+    MemberHeader {
+        extra_flags,
+        ..header
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// A gzip writer compressing everything written into it as a single member.
+///
+/// The member is complete only after `finish` is called.
+pub struct GzEncoder<W: Write> {
+    inner: Encoder<W>,
+}
+
+impl<W: Write> GzEncoder<W> {
+    pub fn new(inner: W, level: Level) -> Self {
+        Self {
+            inner: Encoder::new(inner, level, Format::Gzip),
+        }
+    }
+
+    /// Like `new`, but writing the given header, e.g. with a file name and modification time.
+    /// The extra flags of the header are derived from the level.
+    pub fn with_header(inner: W, level: Level, header: MemberHeader) -> Self {
+        let mut encoder = Self::new(inner, level);
+        encoder.inner.header = member_header(header, level);
+        encoder
+    }
+
+    /// Like `new`, but letting matches refer to the last 32 KiB of `dictionary`.
+    /// Unlike zlib, gzip has no way to tell that a dictionary was used, so the member
+    /// can only be decompressed by `GzDecoder::with_dictionary` with the same dictionary.
+    pub fn with_dictionary(inner: W, level: Level, dictionary: &[u8]) -> Self {
+        Self {
+            inner: Encoder::with_dictionary(inner, level, Format::Gzip, dictionary),
+        }
+    }
+
+    /// Compress the input in chunks cut where a rolling hash of the input matches,
+    /// each one starting a new block at a byte boundary, like `gzip --rsyncable`.
+    /// As matches only reach 32 KiB back, a local change of the input only changes
+    /// the output of the chunks it touches and of those starting within 32 KiB after it,
+    /// which lets rsync and deduplicating storage transfer the rest as is.
+    pub fn with_rsyncable(mut self, rsyncable: bool) -> Self {
+        self.inner.chunker = rsyncable.then(Chunker::new);
+        self
+    }
+
+    pub fn get_ref(&self) -> &W {
+        self.inner.get_ref()
+    }
+
+    /// Write the rest of the member and return the underlying writer.
+    pub fn finish(self) -> io::Result<W> {
+        self.inner.finish()
+    }
+}
+
+impl<W: Write> Write for GzEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// A zlib (RFC 1950) writer.
+///
+/// The stream is complete only after `finish` is called.
+pub struct ZlibEncoder<W: Write> {
+    inner: Encoder<W>,
+}
+
+impl<W: Write> ZlibEncoder<W> {
+    pub fn new(inner: W, level: Level) -> Self {
+        Self {
+            inner: Encoder::new(inner, level, Format::Zlib),
+        }
+    }
+
+    /// Like `new`, but priming the compressor with the last 32 KiB of `dictionary`.
+    /// The header identifies the dictionary, which must also be given to the decompressor.
+    pub fn with_dictionary(inner: W, level: Level, dictionary: &[u8]) -> Self {
+        Self {
+            inner: Encoder::with_dictionary(inner, level, Format::Zlib, dictionary),
+        }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        self.inner.get_ref()
+    }
+
+    /// Write the rest of the stream and return the underlying writer.
+    pub fn finish(self) -> io::Result<W> {
+        self.inner.finish()
+    }
+}
+
+impl<W: Write> Write for ZlibEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// A raw DEFLATE (RFC 1951) writer without any header or trailer.
+///
+/// The stream is complete only after `finish` is called.
+pub struct DeflateEncoder<W: Write> {
+    inner: Encoder<W>,
+}
+
+impl<W: Write> DeflateEncoder<W> {
+    pub fn new(inner: W, level: Level) -> Self {
+        Self {
+            inner: Encoder::new(inner, level, Format::Raw),
+        }
+    }
+
+    /// Like `new`, but letting matches refer to the last 32 KiB of `dictionary`,
+    /// which must also be given to the decompressor.
+    pub fn with_dictionary(inner: W, level: Level, dictionary: &[u8]) -> Self {
+        Self {
+            inner: Encoder::with_dictionary(inner, level, Format::Raw, dictionary),
+        }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        self.inner.get_ref()
+    }
+
+    /// Write the final block and return the underlying writer.
+    pub fn finish(self) -> io::Result<W> {
+        self.inner.finish()
+    }
+}
+
+impl<W: Write> Write for DeflateEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
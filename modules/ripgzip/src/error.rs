@@ -0,0 +1,363 @@
+use std::fmt;
+use std::io;
+
+////////////////////////////////////////////////////////////////////////////////
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Where in the compressed stream an error was detected.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Location {
+    /// Index of the gzip member or zip entry, always 0 for zlib and raw DEFLATE streams.
+    pub member: usize,
+    /// Offset from the start of the stream, in bits.
+    pub bit_offset: u64,
+}
+
+impl Location {
+    pub(crate) fn at(bit_offset: u64) -> Self {
+        Self {
+            member: 0,
+            bit_offset,
+        }
+    }
+
+    pub fn byte_offset(&self) -> u64 {
+        self.bit_offset / 8
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "member {}, byte {} (bit {})",
+            self.member,
+            self.byte_offset(),
+            self.bit_offset % 8
+        )
+    }
+}
+
+/// A resource cap set in `DecompressOptions`, along with its value.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Limit {
+    OutputSize(u64),
+    Ratio(u64),
+    MemberCount(usize),
+    HeaderFieldSize(usize),
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Limit::OutputSize(max) => write!(f, "output size of {} bytes", max),
+            Limit::Ratio(max) => write!(f, "expansion ratio of {}", max),
+            Limit::MemberCount(max) => write!(f, "member count of {}", max),
+            Limit::HeaderFieldSize(max) => write!(f, "header field size of {} bytes", max),
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    Io(io::Error),
+    /// The input ended in the middle of a member.
+    TruncatedInput {
+        location: Location,
+    },
+    /// The gzip member does not start with the `1f 8b` magic bytes.
+    BadMagic {
+        location: Location,
+    },
+    UnsupportedMethod {
+        method: u8,
+        location: Location,
+    },
+    /// A malformed zlib header or gzip extra field.
+    InvalidHeader {
+        reason: &'static str,
+        location: Location,
+    },
+    HeaderCrc {
+        stored: u16,
+        computed: u16,
+        location: Location,
+    },
+    UnsupportedBlockType {
+        location: Location,
+    },
+    /// LEN and NLEN of a stored block do not match.
+    StoredLengthMismatch {
+        location: Location,
+    },
+    /// The code lengths of a dynamic block do not form a valid Huffman code.
+    InvalidCodeLengths {
+        reason: &'static str,
+        location: Location,
+    },
+    /// A bit sequence matching no symbol, or a symbol not allowed in the data.
+    InvalidHuffmanCode {
+        location: Location,
+    },
+    DistanceTooFarBack {
+        distance: usize,
+        available: usize,
+        location: Location,
+    },
+    LengthMismatch {
+        stored: u32,
+        computed: u32,
+        location: Location,
+    },
+    CrcMismatch {
+        stored: u32,
+        computed: u32,
+        location: Location,
+    },
+    Adler32Mismatch {
+        stored: u32,
+        computed: u32,
+        location: Location,
+    },
+    /// The zlib stream was compressed with a preset dictionary, identified by its Adler-32,
+    /// that was not provided.
+    DictionaryRequired {
+        id: u32,
+        location: Location,
+    },
+    /// A malformed random access index.
+    InvalidIndex(String),
+    /// A malformed zip archive structure.
+    InvalidArchive(String),
+    /// A zip entry that is encrypted or uses a compression method other than DEFLATE.
+    UnsupportedEntry {
+        name: String,
+        reason: String,
+    },
+    /// Decompression was stopped as soon as a cap of `DecompressOptions` was crossed.
+    LimitExceeded {
+        limit: Limit,
+        location: Location,
+    },
+}
+
+impl Error {
+    pub fn location(&self) -> Option<Location> {
+        match self {
+            Error::Io(_)
+            | Error::InvalidIndex(_)
+            | Error::InvalidArchive(_)
+            | Error::UnsupportedEntry { .. } => None,
+            Error::TruncatedInput { location }
+            | Error::BadMagic { location }
+            | Error::UnsupportedMethod { location, .. }
+            | Error::InvalidHeader { location, .. }
+            | Error::HeaderCrc { location, .. }
+            | Error::UnsupportedBlockType { location }
+            | Error::StoredLengthMismatch { location }
+            | Error::InvalidCodeLengths { location, .. }
+            | Error::InvalidHuffmanCode { location }
+            | Error::DistanceTooFarBack { location, .. }
+            | Error::LengthMismatch { location, .. }
+            | Error::CrcMismatch { location, .. }
+            | Error::Adler32Mismatch { location, .. }
+            | Error::DictionaryRequired { location, .. }
+            | Error::LimitExceeded { location, .. } => Some(*location),
+        }
+    }
+
+    fn location_mut(&mut self) -> Option<&mut Location> {
+        match self {
+            Error::Io(_)
+            | Error::InvalidIndex(_)
+            | Error::InvalidArchive(_)
+            | Error::UnsupportedEntry { .. } => None,
+            Error::TruncatedInput { location }
+            | Error::BadMagic { location }
+            | Error::UnsupportedMethod { location, .. }
+            | Error::InvalidHeader { location, .. }
+            | Error::HeaderCrc { location, .. }
+            | Error::UnsupportedBlockType { location }
+            | Error::StoredLengthMismatch { location }
+            | Error::InvalidCodeLengths { location, .. }
+            | Error::InvalidHuffmanCode { location }
+            | Error::DistanceTooFarBack { location, .. }
+            | Error::LengthMismatch { location, .. }
+            | Error::CrcMismatch { location, .. }
+            | Error::Adler32Mismatch { location, .. }
+            | Error::DictionaryRequired { location, .. }
+            | Error::LimitExceeded { location, .. } => Some(location),
+        }
+    }
+
+    /// Attribute the error to the given member and, unless a more precise offset is known,
+    /// to the given bit offset. Unexpected EOF becomes `TruncatedInput`.
+    ///
+    /// Errors raised without access to the stream position carry offset 0, which can
+    /// be safely overwritten as `bit_offset` is never past the actual position.
+    pub(crate) fn locate(mut self, member: usize, bit_offset: u64) -> Self {
+        if let Error::Io(err) = &self {
+            if err.kind() == io::ErrorKind::UnexpectedEof {
+                self = Error::TruncatedInput {
+                    location: Location::default(),
+                };
+            }
+        }
+        if let Some(location) = self.location_mut() {
+            location.member = member;
+            if location.bit_offset == 0 {
+                location.bit_offset = bit_offset;
+            }
+        }
+        self
+    }
+
+    /// Make the location of an error raised while decoding a part of the stream
+    /// starting at `bit_offset` relative to the whole stream.
+    pub(crate) fn shift(mut self, member: usize, bit_offset: u64) -> Self {
+        if let Some(location) = self.location_mut() {
+            location.member = member;
+            location.bit_offset += bit_offset;
+        }
+        self
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(err) => return write!(f, "{}", err),
+            Error::InvalidIndex(reason) => return write!(f, "invalid index: {}", reason),
+            Error::InvalidArchive(reason) => return write!(f, "invalid archive: {}", reason),
+            Error::UnsupportedEntry { name, reason } => {
+                return write!(f, "{}: unsupported entry: {}", name, reason)
+            }
+            Error::TruncatedInput { .. } => write!(f, "unexpected end of input")?,
+            Error::BadMagic { .. } => write!(f, "wrong id values")?,
+            Error::UnsupportedMethod { method, .. } => {
+                write!(f, "unsupported compression method {}", method)?
+            }
+            Error::InvalidHeader { reason, .. } => write!(f, "invalid header: {}", reason)?,
+            Error::HeaderCrc {
+                stored, computed, ..
+            } => write!(
+                f,
+                "header crc16 check failed: stored {:#06x}, computed {:#06x}",
+                stored, computed
+            )?,
+            Error::UnsupportedBlockType { .. } => write!(f, "unsupported block type")?,
+            Error::StoredLengthMismatch { .. } => write!(f, "nlen check failed")?,
+            Error::InvalidCodeLengths { reason, .. } => {
+                write!(f, "invalid code lengths: {}", reason)?
+            }
+            Error::InvalidHuffmanCode { .. } => write!(f, "invalid huffman code")?,
+            Error::DistanceTooFarBack {
+                distance,
+                available,
+                ..
+            } => write!(
+                f,
+                "distance {} is too far back, only {} bytes available",
+                distance, available
+            )?,
+            Error::LengthMismatch {
+                stored, computed, ..
+            } => write!(
+                f,
+                "length check failed: stored {}, computed {}",
+                stored, computed
+            )?,
+            Error::CrcMismatch {
+                stored, computed, ..
+            } => write!(
+                f,
+                "crc32 check failed: stored {:#010x}, computed {:#010x}",
+                stored, computed
+            )?,
+            Error::Adler32Mismatch {
+                stored, computed, ..
+            } => write!(
+                f,
+                "adler32 check failed: stored {:#010x}, computed {:#010x}",
+                stored, computed
+            )?,
+            Error::DictionaryRequired { id, .. } => {
+                write!(f, "preset dictionary {:#010x} is required", id)?
+            }
+            Error::LimitExceeded { limit, .. } => write!(f, "limit exceeded: {}", limit)?,
+        }
+        if let Some(location) = self.location() {
+            write!(f, " at {}", location)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl From<Error> for io::Error {
+    fn from(err: Error) -> Self {
+        match err {
+            Error::Io(err) => err,
+            Error::TruncatedInput { .. } => io::Error::new(io::ErrorKind::UnexpectedEof, err),
+            err => io::Error::new(io::ErrorKind::InvalidData, err),
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn locate() {
+        let err = Error::from(io::Error::from(io::ErrorKind::UnexpectedEof)).locate(2, 80);
+        assert!(matches!(
+            err,
+            Error::TruncatedInput {
+                location: Location {
+                    member: 2,
+                    bit_offset: 80
+                }
+            }
+        ));
+
+        let err = Error::InvalidHuffmanCode {
+            location: Location::at(123),
+        }
+        .locate(1, 100);
+        assert_eq!(
+            err.location(),
+            Some(Location {
+                member: 1,
+                bit_offset: 123
+            })
+        );
+        assert_eq!(
+            err.to_string(),
+            "invalid huffman code at member 1, byte 15 (bit 3)"
+        );
+
+        let err = Error::from(io::Error::other("boom")).locate(1, 100);
+        assert!(matches!(err, Error::Io(_)));
+    }
+}
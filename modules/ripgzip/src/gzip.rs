@@ -1,16 +1,17 @@
-use std::io::{BufRead};
+use std::io::{self, BufRead, Write};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use anyhow::{anyhow, Result};
-use byteorder::{LittleEndian, ReadBytesExt};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 
-use crc::{crc32, Hasher32};
+use crate::crc32::Crc32;
+use crate::error::{Error, Limit, Location, Result};
 
 ////////////////////////////////////////////////////////////////////////////////
 
-const ID1: u8 = 0x1f;
-const ID2: u8 = 0x8b;
+pub(crate) const ID1: u8 = 0x1f;
+pub(crate) const ID2: u8 = 0x8b;
 
-const CM_DEFLATE: u8 = 8;
+pub(crate) const CM_DEFLATE: u8 = 8;
 
 const FTEXT_OFFSET: u8 = 0;
 const FHCRC_OFFSET: u8 = 1;
@@ -18,9 +19,29 @@ const FEXTRA_OFFSET: u8 = 2;
 const FNAME_OFFSET: u8 = 3;
 const FCOMMENT_OFFSET: u8 = 4;
 
+const OS_UNKNOWN: u8 = 255;
+
+const OS_NAMES: [&str; 14] = [
+    "FAT",
+    "Amiga",
+    "VMS",
+    "Unix",
+    "VM/CMS",
+    "Atari TOS",
+    "HPFS",
+    "Macintosh",
+    "Z-System",
+    "CP/M",
+    "TOPS-20",
+    "NTFS",
+    "QDOS",
+    "Acorn RISCOS",
+];
+
 ////////////////////////////////////////////////////////////////////////////////
 
-#[derive(Debug)]
+/// The header of a gzip member (RFC 1952, section 2.3).
+#[derive(Clone, Debug)]
 pub struct MemberHeader {
     pub compression_method: CompressionMethod,
     pub modification_time: u32,
@@ -35,7 +56,7 @@ pub struct MemberHeader {
 
 impl MemberHeader {
     pub fn crc16(&self) -> u16 {
-        let mut digest = crc32::Digest::new(crc32::IEEE);
+        let mut digest = Crc32::new();
 
         digest.write(&[ID1, ID2, self.compression_method.into(), self.flags().0]);
         digest.write(&self.modification_time.to_le_bytes());
@@ -59,6 +80,49 @@ impl MemberHeader {
         (digest.sum32() & 0xffff) as u16
     }
 
+    /// Modification time of the original file, if it was recorded.
+    pub fn mtime(&self) -> Option<SystemTime> {
+        match self.modification_time {
+            0 => None,
+            secs => Some(UNIX_EPOCH + Duration::from_secs(secs as u64)),
+        }
+    }
+
+    /// Name of the operating system the member was created on.
+    pub fn os_name(&self) -> &'static str {
+        OS_NAMES.get(self.os as usize).copied().unwrap_or("unknown")
+    }
+
+    /// Split the extra field into subfields.
+    pub fn extra_subfields(&self) -> Result<Vec<ExtraSubfield<'_>>> {
+        let mut subfields = Vec::new();
+        let mut rest = self.extra.as_deref().unwrap_or_default();
+        while !rest.is_empty() {
+            if rest.len() < 4 {
+                return Err(invalid_header("truncated extra subfield header"));
+            }
+            let len = u16::from_le_bytes([rest[2], rest[3]]) as usize;
+            if rest.len() < 4 + len {
+                return Err(invalid_header("truncated extra subfield data"));
+            }
+            subfields.push(ExtraSubfield {
+                id: [rest[0], rest[1]],
+                data: &rest[4..4 + len],
+            });
+            rest = &rest[4 + len..];
+        }
+        Ok(subfields)
+    }
+
+    /// Return the data of the first extra subfield with the given id, e.g. `*b"RA"`.
+    pub fn subfield(&self, id: [u8; 2]) -> Option<&[u8]> {
+        self.extra_subfields()
+            .ok()?
+            .into_iter()
+            .find(|subfield| subfield.id == id)
+            .map(|subfield| subfield.data)
+    }
+
     pub fn flags(&self) -> MemberFlags {
         let mut flags = MemberFlags(0);
         flags.set_is_text(self.is_text);
@@ -70,6 +134,40 @@ impl MemberHeader {
     }
 }
 
+impl Default for MemberHeader {
+    fn default() -> Self {
+        Self {
+            compression_method: CompressionMethod::Deflate,
+            modification_time: 0,
+            extra: None,
+            name: None,
+            comment: None,
+            extra_flags: 0,
+            os: OS_UNKNOWN,
+            has_crc: false,
+            is_text: false,
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// A subfield of the gzip extra field, such as `RA` (random access) or `BC` (BGZF block size).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ExtraSubfield<'a> {
+    pub id: [u8; 2],
+    pub data: &'a [u8],
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+fn invalid_header(reason: &'static str) -> Error {
+    Error::InvalidHeader {
+        reason,
+        location: Location::default(),
+    }
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 
 #[derive(Clone, Copy, Debug)]
@@ -168,17 +266,48 @@ pub struct MemberFooter {
 
 pub struct GzipReader<T> {
     reader: T,
+    max_field_size: Option<usize>,
 }
 
 impl<T: BufRead> GzipReader<T> {
     pub fn new(reader: T) -> Self {
-        Self { reader }
+        Self {
+            reader,
+            max_field_size: None,
+        }
+    }
+
+    /// Fail on FEXTRA, FNAME or FCOMMENT fields larger than `max_field_size` bytes.
+    pub fn with_max_field_size(mut self, max_field_size: Option<usize>) -> Self {
+        self.max_field_size = max_field_size;
+        self
+    }
+
+    fn check_field_size(&self, size: usize) -> Result<()> {
+        match self.max_field_size {
+            Some(max) if size > max => Err(Error::LimitExceeded {
+                limit: Limit::HeaderFieldSize(max),
+                location: Location::default(),
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    fn read_zero_terminated(&mut self) -> Result<Vec<u8>> {
+        let mut buffer = Vec::new();
+        let mut byte = self.reader.read_u8()?;
+        while byte != 0 {
+            buffer.push(byte);
+            self.check_field_size(buffer.len())?;
+            byte = self.reader.read_u8()?;
+        }
+        Ok(buffer)
     }
 
     pub fn next_member(mut self) -> Option<Result<(MemberHeader, MemberReader<T>)>> {
         match self.reader.has_data_left() {
             Ok(false) => return None,
-            Err(x) => return Some(Err(anyhow!(x))),
+            Err(x) => return Some(Err(x.into())),
             _ => (),
         }
         Some(self.inner())
@@ -187,19 +316,23 @@ impl<T: BufRead> GzipReader<T> {
     fn inner(mut self) -> Result<(MemberHeader, MemberReader<T>)> {
         let mut byte = self.reader.read_u8()?;
         if byte != ID1 {
-            return Err(anyhow!("wrong id values"));
+            return Err(Error::BadMagic {
+                location: Location::default(),
+            });
         }
         byte = self.reader.read_u8()?;
         if byte != ID2 {
-            return Err(anyhow!("wrong id values"));
+            return Err(Error::BadMagic {
+                location: Location::default(),
+            });
         }
         byte = self.reader.read_u8()?;
         let compression_method = CompressionMethod::from(byte);
-        match compression_method {
-            CompressionMethod::Unknown(_) => {
-                return Err(anyhow!("unsupported compression method"));
-            }
-            CompressionMethod::Deflate => {}
+        if let CompressionMethod::Unknown(method) = compression_method {
+            return Err(Error::UnsupportedMethod {
+                method,
+                location: Location::default(),
+            });
         }
         byte = self.reader.read_u8()?;
 
@@ -215,6 +348,7 @@ impl<T: BufRead> GzipReader<T> {
         let mut extra = None;
         if is_fextra {
             let xlen = self.reader.read_u16::<LittleEndian>()?;
+            self.check_field_size(xlen as usize)?;
             let mut temp = Vec::with_capacity(xlen as usize);
             for _ in 0..xlen {
                 let byte = self.reader.read_u8()?;
@@ -224,23 +358,19 @@ impl<T: BufRead> GzipReader<T> {
         }
         let mut name = None;
         if is_fname {
-            let mut buffer = Vec::new();
-            let mut byte = self.reader.read_u8()?;
-            while byte != 0 {
-                buffer.push(byte);
-                byte = self.reader.read_u8()?;
-            }
-            name = Some(String::from_utf8(buffer)?);
+            let buffer = self.read_zero_terminated()?;
+            name = Some(
+                String::from_utf8(buffer)
+                    .map_err(|_| invalid_header("file name is not valid UTF-8"))?,
+            );
         }
         let mut comment = None;
         if is_fcomment {
-            let mut buffer = Vec::new();
-            let mut byte = self.reader.read_u8()?;
-            while byte != 0 {
-                buffer.push(byte);
-                byte = self.reader.read_u8()?;
-            }
-            comment = Some(String::from_utf8(buffer)?);
+            let buffer = self.read_zero_terminated()?;
+            comment = Some(
+                String::from_utf8(buffer)
+                    .map_err(|_| invalid_header("comment is not valid UTF-8"))?,
+            );
         }
 
         let member_header = MemberHeader {
@@ -256,9 +386,14 @@ impl<T: BufRead> GzipReader<T> {
         };
 
         if has_crc {
-            let crc_16 = self.reader.read_u16::<LittleEndian>()?;
-            if member_header.crc16() != crc_16 {
-                return Err(anyhow!("header crc16 check failed"));
+            let stored = self.reader.read_u16::<LittleEndian>()?;
+            let computed = member_header.crc16();
+            if stored != computed {
+                return Err(Error::HeaderCrc {
+                    stored,
+                    computed,
+                    location: Location::default(),
+                });
             }
         }
         Ok((member_header, MemberReader { inner: self.reader }))
@@ -272,8 +407,8 @@ pub struct MemberReader<T> {
 }
 
 impl<T: BufRead> MemberReader<T> {
-    pub fn inner_mut(&mut self) -> &mut T {
-        &mut self.inner
+    pub fn new(inner: T) -> Self {
+        Self { inner }
     }
 
     pub fn read_footer(mut self) -> Result<(MemberFooter, GzipReader<T>)> {
@@ -287,3 +422,169 @@ impl<T: BufRead> MemberReader<T> {
         Ok((footer, gzip_reader))
     }
 }
+
+////////////////////////////////////////////////////////////////////////////////
+
+pub struct GzipWriter<T> {
+    writer: T,
+}
+
+impl<T: Write> GzipWriter<T> {
+    pub fn new(writer: T) -> Self {
+        Self { writer }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.writer
+    }
+
+    pub fn write_header(mut self, header: &MemberHeader) -> io::Result<MemberWriter<T>> {
+        self.writer
+            .write_all(&[ID1, ID2, header.compression_method.into(), header.flags().0])?;
+        self.writer
+            .write_u32::<LittleEndian>(header.modification_time)?;
+        self.writer.write_all(&[header.extra_flags, header.os])?;
+        if let Some(extra) = &header.extra {
+            self.writer.write_u16::<LittleEndian>(extra.len() as u16)?;
+            self.writer.write_all(extra)?;
+        }
+        if let Some(name) = &header.name {
+            self.writer.write_all(name.as_bytes())?;
+            self.writer.write_u8(0)?;
+        }
+        if let Some(comment) = &header.comment {
+            self.writer.write_all(comment.as_bytes())?;
+            self.writer.write_u8(0)?;
+        }
+        if header.has_crc {
+            self.writer.write_u16::<LittleEndian>(header.crc16())?;
+        }
+        Ok(MemberWriter { inner: self.writer })
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+pub struct MemberWriter<T> {
+    inner: T,
+}
+
+impl<T: Write> MemberWriter<T> {
+    pub fn new(inner: T) -> Self {
+        Self { inner }
+    }
+
+    pub fn write_footer(mut self, footer: &MemberFooter) -> io::Result<GzipWriter<T>> {
+        self.inner.write_u32::<LittleEndian>(footer.data_crc32)?;
+        self.inner.write_u32::<LittleEndian>(footer.data_size)?;
+        Ok(GzipWriter::new(self.inner))
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_read_header() -> Result<()> {
+        let header = MemberHeader {
+            modification_time: 1_600_000_000,
+            extra: Some(vec![b'A', b'B', 2, 0, 1, 2]),
+            name: Some("file.txt".to_string()),
+            comment: Some("a comment".to_string()),
+            has_crc: true,
+            is_text: true,
+            ..MemberHeader::default()
+        };
+        let mut data = Vec::new();
+        let member_writer = GzipWriter::new(&mut data).write_header(&header)?;
+        member_writer.write_footer(&MemberFooter {
+            data_crc32: 0xdeadbeef,
+            data_size: 42,
+        })?;
+
+        let (parsed, member_reader) = GzipReader::new(data.as_slice()).next_member().unwrap()?;
+        assert_eq!(parsed.modification_time, header.modification_time);
+        assert_eq!(parsed.extra, header.extra);
+        assert_eq!(parsed.name, header.name);
+        assert_eq!(parsed.comment, header.comment);
+        assert_eq!(parsed.os, OS_UNKNOWN);
+        assert!(parsed.has_crc);
+        assert!(parsed.is_text);
+
+        let (footer, gzip_reader) = member_reader.read_footer()?;
+        assert_eq!(footer.data_crc32, 0xdeadbeef);
+        assert_eq!(footer.data_size, 42);
+        assert!(gzip_reader.next_member().is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn bad_header_crc() -> Result<()> {
+        let header = MemberHeader {
+            name: Some("file.txt".to_string()),
+            has_crc: true,
+            ..MemberHeader::default()
+        };
+        let mut data = Vec::new();
+        GzipWriter::new(&mut data).write_header(&header)?;
+        *data.last_mut().unwrap() ^= 0xff;
+
+        let err = GzipReader::new(data.as_slice())
+            .next_member()
+            .unwrap()
+            .err()
+            .unwrap();
+        match err {
+            Error::HeaderCrc {
+                stored, computed, ..
+            } => {
+                assert_eq!(computed, header.crc16());
+                assert_eq!(stored, header.crc16() ^ 0xff00);
+            }
+            err => panic!("unexpected error: {}", err),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn extra_subfields() -> Result<()> {
+        let header = MemberHeader {
+            extra: Some(vec![b'B', b'C', 2, 0, 0x1b, 0x00, b'R', b'A', 1, 0, 7]),
+            modification_time: 1_600_000_000,
+            os: 3,
+            ..MemberHeader::default()
+        };
+        assert_eq!(
+            header.extra_subfields()?,
+            [
+                ExtraSubfield {
+                    id: *b"BC",
+                    data: &[0x1b, 0x00]
+                },
+                ExtraSubfield {
+                    id: *b"RA",
+                    data: &[7]
+                },
+            ]
+        );
+        assert_eq!(header.subfield(*b"RA"), Some(&[7][..]));
+        assert_eq!(header.subfield(*b"XX"), None);
+        assert_eq!(
+            header.mtime(),
+            Some(UNIX_EPOCH + Duration::from_secs(1_600_000_000))
+        );
+        assert_eq!(header.os_name(), "Unix");
+        assert_eq!(MemberHeader::default().mtime(), None);
+        assert_eq!(MemberHeader::default().os_name(), "unknown");
+
+        let truncated = MemberHeader {
+            extra: Some(vec![b'B', b'C', 2, 0, 0x1b]),
+            ..MemberHeader::default()
+        };
+        assert!(truncated.extra_subfields().is_err());
+        Ok(())
+    }
+}
@@ -1,7 +1,9 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
 use std::fmt::Debug;
-use std::{collections::HashMap, convert::TryFrom, io::BufRead};
+use std::io::{self, Write};
+use std::{convert::TryFrom, io::BufRead};
 
-use anyhow::{anyhow, Result};
 #[cfg(not(test))]
 use log::debug;
 
@@ -9,21 +11,48 @@ use log::debug;
 use std::println as debug;
 
 use crate::bit_reader::{BitReader, BitSequence};
+use crate::bit_writer::BitWriter;
+use crate::error::{Error, Location, Result};
+use crate::huffman_coding::LitLenToken::{EndOfBlock, Length, Literal};
 use crate::huffman_coding::TreeCodeToken::{CopyPrev, RepeatZero};
-use crate::LitLenToken::{EndOfBlock, Length, Literal};
 
 ////////////////////////////////////////////////////////////////////////////////
 
+/// Code lengths read from the header of a dynamic block.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CodeLengths {
+    /// Lengths of the code length alphabet, indexed by symbol rather than in transmission order.
+    pub code_length_lengths: [u8; CODE_LENGTH_CODES],
+    pub litlen_lengths: Vec<u8>,
+    pub distance_lengths: Vec<u8>,
+}
+
 pub fn decode_litlen_distance_trees<T: BufRead>(
     bit_reader: &mut BitReader<T>,
 ) -> Result<(HuffmanCoding<LitLenToken>, HuffmanCoding<DistanceToken>)> {
+    let lengths = read_code_lengths(bit_reader)?;
+    let literal_huffman_coding =
+        HuffmanCoding::<LitLenToken>::from_lengths(&lengths.litlen_lengths)?;
+    let distance_huffman_coding =
+        HuffmanCoding::<DistanceToken>::from_lengths(&lengths.distance_lengths)?;
+    Ok((literal_huffman_coding, distance_huffman_coding))
+}
+
+/// Read the code lengths of a dynamic block (RFC 1951, 3.2.7).
+pub fn read_code_lengths<T: BufRead>(bit_reader: &mut BitReader<T>) -> Result<CodeLengths> {
     let rows: usize = (bit_reader.read_bits(5)?.bits() + 257) as usize;
+    if rows > LITLEN_CODES {
+        return Err(invalid_lengths("too many literal/length codes"));
+    }
     debug!(
         "Number of literal/end-of-block/length codes (257-286) {}",
         rows
     );
 
     let distances_rows = (bit_reader.read_bits(5)?.bits() + 1) as usize;
+    if distances_rows > DISTANCE_CODES {
+        return Err(invalid_lengths("too many distance codes"));
+    }
     debug!("Number of distance codes (1-32) {}", distances_rows);
 
     let code_length_codes_number = bit_reader.read_bits(4)?.bits() + 4;
@@ -32,43 +61,36 @@ pub fn decode_litlen_distance_trees<T: BufRead>(
         code_length_codes_number
     );
 
-    let mut lengths = [0u8; 19];
+    let mut lengths = [0u8; CODE_LENGTH_CODES];
     for len in lengths.iter_mut().take(code_length_codes_number as usize) {
         *len = bit_reader.read_bits(3)?.bits() as u8;
     }
-    let mut swapped_lengths = [0u8; 19];
-    swapped_lengths[16] = lengths[0];
-    swapped_lengths[17] = lengths[1];
-    swapped_lengths[18] = lengths[2];
-    swapped_lengths[0] = lengths[3];
-    swapped_lengths[8] = lengths[4];
-    swapped_lengths[7] = lengths[5];
-    swapped_lengths[9] = lengths[6];
-    swapped_lengths[6] = lengths[7];
-    swapped_lengths[10] = lengths[8];
-    swapped_lengths[5] = lengths[9];
-    swapped_lengths[11] = lengths[10];
-    swapped_lengths[4] = lengths[11];
-    swapped_lengths[12] = lengths[12];
-    swapped_lengths[3] = lengths[13];
-    swapped_lengths[13] = lengths[14];
-    swapped_lengths[2] = lengths[15];
-    swapped_lengths[14] = lengths[16];
-    swapped_lengths[1] = lengths[17];
-    swapped_lengths[15] = lengths[18];
+    let mut swapped_lengths = [0u8; CODE_LENGTH_CODES];
+    for (len, &index) in lengths.iter().zip(CODE_LENGTH_ORDER.iter()) {
+        swapped_lengths[index] = *len;
+    }
     let huffman_coding = HuffmanCoding::<TreeCodeToken>::from_lengths(&swapped_lengths)?;
-    let mut table = [0u8; 286];
-    build_huffman_coding(&huffman_coding, &mut table, bit_reader, rows)?;
-    let literal_huffman_coding = HuffmanCoding::<LitLenToken>::from_lengths(table.as_slice())?;
-    let mut distances_table = [0u8; 30];
-    build_huffman_coding(
-        &huffman_coding,
-        &mut distances_table,
-        bit_reader,
-        distances_rows,
-    )?;
+    // Literal/length and distance code lengths form a single sequence, repeats may cross them.
+    let mut table = [0u8; LITLEN_CODES + DISTANCE_CODES];
+    let table = &mut table[..rows + distances_rows];
+    build_huffman_coding(&huffman_coding, table, bit_reader)?;
+    let (litlen_lengths, distance_lengths) = table.split_at(rows);
+    Ok(CodeLengths {
+        code_length_lengths: swapped_lengths,
+        litlen_lengths: litlen_lengths.to_vec(),
+        distance_lengths: distance_lengths.to_vec(),
+    })
+}
+
+/// Build the codings of a block compressed with fixed Huffman codes (RFC 1951, 3.2.6).
+pub fn fixed_litlen_distance_trees(
+) -> Result<(HuffmanCoding<LitLenToken>, HuffmanCoding<DistanceToken>)> {
+    // Literal/length codes 286-287 take part in the code construction,
+    // but never occur in the data.
+    let literal_huffman_coding =
+        HuffmanCoding::<LitLenToken>::from_partial_lengths(&FIXED_LITLEN_LENGTHS, LITLEN_CODES)?;
     let distance_huffman_coding =
-        HuffmanCoding::<DistanceToken>::from_lengths(distances_table.as_slice())?;
+        HuffmanCoding::<DistanceToken>::from_lengths(&FIXED_DISTANCE_LENGTHS)?;
     Ok((literal_huffman_coding, distance_huffman_coding))
 }
 
@@ -76,33 +98,38 @@ fn build_huffman_coding<T: BufRead>(
     tree_code_coding: &HuffmanCoding<TreeCodeToken>,
     table: &mut [u8],
     reader: &mut BitReader<T>,
-    rows: usize,
 ) -> Result<()> {
+    let rows = table.len();
     let mut index: usize = 0;
-    let mut prev: u8 = 0;
+    let mut prev = None;
     while index < rows {
-        match tree_code_coding.read_symbol(reader)? {
-            TreeCodeToken::Length(len) => {
-                table[index] = len;
-                prev = len;
-                index += 1;
-            }
+        let (len, repeats) = match tree_code_coding.read_symbol(reader)? {
+            TreeCodeToken::Length(len) => (len, 1),
             CopyPrev => {
-                let repeats = reader.read_bits(2)?.bits() + 3;
-                for _ in 0..repeats {
-                    table[index] = prev;
-                    index += 1;
-                }
+                let len = prev.ok_or_else(|| invalid_lengths("repeat with no previous length"))?;
+                (len, (reader.read_bits(2)?.bits() + 3) as usize)
             }
             RepeatZero { base, extra_bits } => {
-                let number_of_zero_rows = (base + reader.read_bits(extra_bits)?.bits()) as usize;
-                index += number_of_zero_rows;
+                (0, (base + reader.read_bits(extra_bits)?.bits()) as usize)
             }
+        };
+        if index + repeats > rows {
+            return Err(invalid_lengths("too many code lengths"));
         }
+        table[index..index + repeats].fill(len);
+        prev = Some(len);
+        index += repeats;
     }
     Ok(())
 }
 
+fn invalid_lengths(reason: &'static str) -> Error {
+    Error::InvalidCodeLengths {
+        reason,
+        location: Location::default(),
+    }
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 
 #[derive(Clone, Copy, Debug)]
@@ -113,7 +140,7 @@ pub enum TreeCodeToken {
 }
 
 impl TryFrom<HuffmanCodeWord> for TreeCodeToken {
-    type Error = anyhow::Error;
+    type Error = Error;
 
     fn try_from(value: HuffmanCodeWord) -> Result<Self> {
         if value.0 <= 15 {
@@ -131,7 +158,9 @@ impl TryFrom<HuffmanCodeWord> for TreeCodeToken {
                 extra_bits: 7,
             })
         } else {
-            Err(anyhow!("Unknown value for TreeCodeToken: {}", value.0))
+            Err(Error::InvalidHuffmanCode {
+                location: Location::default(),
+            })
         }
     }
 }
@@ -146,7 +175,7 @@ pub enum LitLenToken {
 }
 
 impl TryFrom<HuffmanCodeWord> for LitLenToken {
-    type Error = anyhow::Error;
+    type Error = Error;
 
     fn try_from(value: HuffmanCodeWord) -> Result<Self> {
         assert!(value.0 <= 285);
@@ -207,7 +236,7 @@ pub struct DistanceToken {
 }
 
 impl TryFrom<HuffmanCodeWord> for DistanceToken {
-    type Error = anyhow::Error;
+    type Error = Error;
 
     // todo: make a table on a compile time
     fn try_from(value: HuffmanCodeWord) -> Result<Self> {
@@ -303,59 +332,436 @@ impl TryFrom<HuffmanCodeWord> for DistanceToken {
 
 const MAX_BITS: usize = 15;
 
+/// Number of bits resolved by the primary lookup table; longer codes go to sub-tables.
+const PRIMARY_BITS: u8 = 9;
+
 pub struct HuffmanCodeWord(pub u16);
 
+#[derive(Clone, Copy, Debug)]
+enum TableEntry<T> {
+    /// No code starts with these bits.
+    Invalid,
+    /// A code of `len` bits for `token`.
+    Symbol { token: T, len: u8 },
+    /// The code is longer than the table, continue in the sub-table at `offset`
+    /// indexed by the next `bits` bits.
+    SubTable { offset: u16, bits: u8 },
+}
+
+/// A canonical Huffman code decoded with a lookup table indexed by the next
+/// `PRIMARY_BITS` bits of the stream, with sub-tables for longer codes.
 #[derive(Debug)]
 pub struct HuffmanCoding<T> {
-    map: HashMap<BitSequence, T>,
+    table: Vec<TableEntry<T>>,
 }
 
 impl<T> HuffmanCoding<T>
 where
-    T: Copy + TryFrom<HuffmanCodeWord, Error = anyhow::Error> + Debug,
+    T: Copy + TryFrom<HuffmanCodeWord, Error = Error> + Debug,
 {
-    pub fn decode_symbol(&self, seq: BitSequence) -> Option<T> {
-        self.map.get(&seq).cloned()
+    /// Decode a single code given MSB-first, as in RFC 1951. The decoder uses `read_symbol`,
+    /// this one checks the table in the tests.
+    #[cfg(test)]
+    fn decode_symbol(&self, seq: BitSequence) -> Option<T> {
+        let len = seq.len();
+        let bits = reverse_bits(seq.bits(), len) as usize;
+        let entry = match self.table[bits & mask(PRIMARY_BITS)] {
+            TableEntry::SubTable {
+                offset,
+                bits: sub_bits,
+            } if len > PRIMARY_BITS => {
+                self.table[offset as usize + (bits >> PRIMARY_BITS & mask(sub_bits))]
+            }
+            entry => entry,
+        };
+        match entry {
+            TableEntry::Symbol {
+                token,
+                len: code_len,
+            } if code_len == len => Some(token),
+            _ => None,
+        }
     }
 
     pub fn read_symbol<U: BufRead>(&self, bit_reader: &mut BitReader<U>) -> Result<T> {
-        let mut seq = bit_reader.read_bits(1)?;
-        loop {
-            if self.map.contains_key(&seq) {
-                return Ok(*self.map.get(&seq).unwrap());
+        let position = bit_reader.position();
+        let bits = bit_reader.peek_bits(MAX_BITS as u8)? as usize;
+        let mut entry = self.table[bits & mask(PRIMARY_BITS)];
+        if let TableEntry::SubTable {
+            offset,
+            bits: sub_bits,
+        } = entry
+        {
+            entry = self.table[offset as usize + (bits >> PRIMARY_BITS & mask(sub_bits))];
+        }
+        match entry {
+            TableEntry::Symbol { token, len } => {
+                bit_reader.consume(len)?;
+                Ok(token)
+            }
+            _ => {
+                // Tell a truncated stream from a bad code.
+                bit_reader.consume(MAX_BITS as u8)?;
+                Err(Error::InvalidHuffmanCode {
+                    location: Location::at(position),
+                })
             }
-            let temp = bit_reader.read_bits(1)?;
-            seq = seq.concat(temp);
         }
     }
 
     pub fn from_lengths(code_lengths: &[u8]) -> Result<Self> {
-        let mut bl_count: HashMap<u8, u16> = HashMap::new();
-        for e in code_lengths {
-            let key = *e;
-            if key > 0 {
-                let lower_border_for_len = *bl_count.entry(key).or_insert(0) + 1;
-                bl_count.insert(key, lower_border_for_len);
+        Self::from_partial_lengths(code_lengths, code_lengths.len())
+    }
+
+    /// Build the coding from all of the `code_lengths`, but keep only the first `symbols` symbols.
+    pub fn from_partial_lengths(code_lengths: &[u8], symbols: usize) -> Result<Self> {
+        let mut bl_count = [0u16; MAX_BITS + 1];
+        for &len in code_lengths {
+            if len as usize > MAX_BITS {
+                return Err(invalid_lengths("code length is too large"));
             }
+            bl_count[len as usize] += 1;
+        }
+        bl_count[0] = 0;
+        let kraft: u32 = (1..=MAX_BITS)
+            .map(|bits| (bl_count[bits] as u32) << (MAX_BITS - bits))
+            .sum();
+        if kraft > 1 << MAX_BITS {
+            return Err(invalid_lengths("oversubscribed huffman code"));
         }
         let mut next_code = [0u16; MAX_BITS + 1];
         let mut code = 0;
         for bits in 1..=MAX_BITS {
-            code = (code + bl_count.get(&(bits as u8 - 1)).unwrap_or(&0)) << 1;
+            code = (code + bl_count[bits - 1]) << 1;
             next_code[bits] = code;
         }
-        let mut result = HashMap::new();
-        for (i, len) in code_lengths.iter().enumerate() {
-            let len = *len as usize;
+
+        // Reversed codes, since the stream is read starting from the first bit of a code.
+        let mut codes = Vec::new();
+        for (i, &len) in code_lengths.iter().enumerate() {
             if len > 0 {
-                let seq = BitSequence::new(next_code[len], len as u8);
-                let elem = T::try_from(HuffmanCodeWord(i as u16))?;
-                result.insert(seq, elem);
+                let code = reverse_bits(next_code[len as usize], len);
                 next_code[len as usize] += 1;
+                if i < symbols {
+                    codes.push((T::try_from(HuffmanCodeWord(i as u16))?, code, len));
+                }
+            }
+        }
+
+        let mut table = vec![TableEntry::Invalid; 1 << PRIMARY_BITS];
+        let mut sub_table_bits = vec![0u8; 1 << PRIMARY_BITS];
+        for &(_, code, len) in &codes {
+            if len > PRIMARY_BITS {
+                let prefix = code as usize & mask(PRIMARY_BITS);
+                sub_table_bits[prefix] = sub_table_bits[prefix].max(len - PRIMARY_BITS);
+            }
+        }
+        for (prefix, &bits) in sub_table_bits.iter().enumerate() {
+            if bits > 0 {
+                table[prefix] = TableEntry::SubTable {
+                    offset: table.len() as u16,
+                    bits,
+                };
+                table.resize(table.len() + (1 << bits), TableEntry::Invalid);
+            }
+        }
+
+        for (token, code, len) in codes {
+            let entry = TableEntry::Symbol { token, len };
+            let (start, code, len, bits) = if len <= PRIMARY_BITS {
+                (0, code as usize, len, PRIMARY_BITS)
+            } else {
+                let prefix = code as usize & mask(PRIMARY_BITS);
+                let offset = match table[prefix] {
+                    TableEntry::SubTable { offset, .. } => offset as usize,
+                    _ => unreachable!(),
+                };
+                let sub_code = (code >> PRIMARY_BITS) as usize;
+                (offset, sub_code, len - PRIMARY_BITS, sub_table_bits[prefix])
+            };
+            // Fill every entry whose low `len` bits are the code.
+            for high in 0..1usize << (bits - len) {
+                table[start + (high << len | code)] = entry;
+            }
+        }
+        Ok(Self { table })
+    }
+}
+
+fn reverse_bits(bits: u16, len: u8) -> u16 {
+    (bits.reverse_bits() as u32 >> (16 - len)) as u16
+}
+
+fn mask(bits: u8) -> usize {
+    (1 << bits) - 1
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+pub const LITLEN_CODES: usize = 286;
+pub const DISTANCE_CODES: usize = 30;
+pub const LENGTH_CODES: usize = 29;
+pub const END_OF_BLOCK: usize = 256;
+
+pub const CODE_LENGTH_CODES: usize = 19;
+const MAX_CODE_LENGTH_BITS: u8 = 7;
+
+pub const CODE_LENGTH_ORDER: [usize; CODE_LENGTH_CODES] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+pub const LENGTH_BASES: [u16; LENGTH_CODES] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA_BITS: [u8; LENGTH_CODES] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+
+pub const DISTANCE_BASES: [u16; DISTANCE_CODES] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA_BITS: [u8; DISTANCE_CODES] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+/// Code lengths of the fixed literal/length alphabet (RFC 1951, 3.2.6).
+pub const FIXED_LITLEN_LENGTHS: [u8; 288] = {
+    let mut lengths = [8u8; 288];
+    let mut i = 144;
+    while i < 256 {
+        lengths[i] = 9;
+        i += 1;
+    }
+    while i < 280 {
+        lengths[i] = 7;
+        i += 1;
+    }
+    lengths
+};
+
+/// Code lengths of the fixed distance alphabet (RFC 1951, 3.2.6).
+pub const FIXED_DISTANCE_LENGTHS: [u8; DISTANCE_CODES] = [5; DISTANCE_CODES];
+
+/// Return the literal/length symbol for a match length together with its extra bits.
+pub fn length_code(length: u16) -> (usize, BitSequence) {
+    let index = LENGTH_BASES.partition_point(|&base| base <= length) - 1;
+    let extra = BitSequence::new(length - LENGTH_BASES[index], LENGTH_EXTRA_BITS[index]);
+    (257 + index, extra)
+}
+
+/// Return the distance symbol for a match distance together with its extra bits.
+pub fn distance_code(distance: u16) -> (usize, BitSequence) {
+    let index = DISTANCE_BASES.partition_point(|&base| base <= distance) - 1;
+    let extra = BitSequence::new(distance - DISTANCE_BASES[index], DISTANCE_EXTRA_BITS[index]);
+    (index, extra)
+}
+
+/// Build length-limited Huffman code lengths for the given symbol frequencies.
+///
+/// At least two symbols always get a code, so the resulting code is complete.
+pub fn build_code_lengths(frequencies: &[u32], max_bits: u8) -> Vec<u8> {
+    let mut symbols: Vec<usize> = (0..frequencies.len())
+        .filter(|&i| frequencies[i] > 0)
+        .collect();
+    let mut unused = (0..frequencies.len()).filter(|&i| frequencies[i] == 0);
+    while symbols.len() < 2 {
+        symbols.push(unused.next().expect("at least two symbols are required"));
+    }
+    let weight = |symbol: usize| frequencies[symbol].max(1) as u64;
+
+    // Plain Huffman tree: leaves are 0..n, inner nodes are appended after them.
+    let n = symbols.len();
+    let mut parent = vec![0usize; 2 * n - 1];
+    let mut heap: BinaryHeap<Reverse<(u64, usize)>> = symbols
+        .iter()
+        .enumerate()
+        .map(|(node, &symbol)| Reverse((weight(symbol), node)))
+        .collect();
+    let mut next_node = n;
+    while heap.len() > 1 {
+        let Reverse((first_weight, first)) = heap.pop().unwrap();
+        let Reverse((second_weight, second)) = heap.pop().unwrap();
+        parent[first] = next_node;
+        parent[second] = next_node;
+        heap.push(Reverse((first_weight + second_weight, next_node)));
+        next_node += 1;
+    }
+    let root = next_node - 1;
+    let mut depth = vec![0usize; 2 * n - 1];
+    for node in (0..root).rev() {
+        depth[node] = depth[parent[node]] + 1;
+    }
+
+    // Clamp the tree to `max_bits` and restore the Kraft equality by
+    // splitting shorter codes.
+    let max_bits = max_bits as usize;
+    let mut bl_count = vec![0u32; max_bits + 1];
+    for &d in &depth[..n] {
+        bl_count[d.min(max_bits)] += 1;
+    }
+    let mut total: u64 = (1..=max_bits)
+        .map(|bits| (bl_count[bits] as u64) << (max_bits - bits))
+        .sum();
+    while total != 1 << max_bits {
+        bl_count[max_bits] -= 1;
+        for bits in (1..max_bits).rev() {
+            if bl_count[bits] > 0 {
+                bl_count[bits] -= 1;
+                bl_count[bits + 1] += 2;
+                break;
+            }
+        }
+        total -= 1;
+    }
+
+    // The most frequent symbols get the shortest codes.
+    symbols.sort_by_key(|&symbol| (Reverse(weight(symbol)), symbol));
+    let mut lengths = vec![0u8; frequencies.len()];
+    let mut sorted = symbols.into_iter();
+    for (bits, &count) in bl_count.iter().enumerate().skip(1) {
+        for symbol in sorted.by_ref().take(count as usize) {
+            lengths[symbol] = bits as u8;
+        }
+    }
+    lengths
+}
+
+/// Build canonical codes for the given code lengths. Every code is bit-reversed,
+/// so it can be passed to `BitWriter::write_bits` as is.
+pub fn build_codes(code_lengths: &[u8]) -> Vec<BitSequence> {
+    let mut bl_count = [0u16; MAX_BITS + 1];
+    for &len in code_lengths {
+        bl_count[len as usize] += 1;
+    }
+    bl_count[0] = 0;
+    let mut next_code = [0u16; MAX_BITS + 1];
+    let mut code = 0;
+    for bits in 1..=MAX_BITS {
+        code = (code + bl_count[bits - 1]) << 1;
+        next_code[bits] = code;
+    }
+    code_lengths
+        .iter()
+        .map(|&len| {
+            if len == 0 {
+                return BitSequence::new(0, 0);
+            }
+            let code = next_code[len as usize];
+            next_code[len as usize] += 1;
+            BitSequence::new(code.reverse_bits() >> (16 - len), len)
+        })
+        .collect()
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Code lengths of a dynamic block together with their run-length encoded form.
+pub struct DynamicTrees {
+    pub litlen_lengths: Vec<u8>,
+    pub distance_lengths: Vec<u8>,
+    code_length_lengths: Vec<u8>,
+    code_length_codes: Vec<BitSequence>,
+    tokens: Vec<(usize, BitSequence)>,
+    code_length_count: usize,
+}
+
+impl DynamicTrees {
+    pub fn new(litlen_frequencies: &[u32], distance_frequencies: &[u32]) -> Self {
+        let litlen_lengths = build_code_lengths(litlen_frequencies, MAX_BITS as u8);
+        let distance_lengths = build_code_lengths(distance_frequencies, MAX_BITS as u8);
+
+        let litlen_count = trimmed_len(&litlen_lengths, 257);
+        let distance_count = trimmed_len(&distance_lengths, 1);
+        let mut tokens = run_length_encode(&litlen_lengths[..litlen_count]);
+        tokens.extend(run_length_encode(&distance_lengths[..distance_count]));
+
+        let mut frequencies = [0u32; CODE_LENGTH_CODES];
+        for (symbol, _) in &tokens {
+            frequencies[*symbol] += 1;
+        }
+        let code_length_lengths = build_code_lengths(&frequencies, MAX_CODE_LENGTH_BITS);
+        let code_length_codes = build_codes(&code_length_lengths);
+        let code_length_count = CODE_LENGTH_ORDER
+            .iter()
+            .rposition(|&i| code_length_lengths[i] != 0)
+            .map_or(4, |i| (i + 1).max(4));
+
+        Self {
+            litlen_lengths: litlen_lengths[..litlen_count].to_vec(),
+            distance_lengths: distance_lengths[..distance_count].to_vec(),
+            code_length_lengths,
+            code_length_codes,
+            tokens,
+            code_length_count,
+        }
+    }
+
+    /// Size of the encoded trees in bits.
+    pub fn cost(&self) -> u64 {
+        let tokens: u64 = self
+            .tokens
+            .iter()
+            .map(|(symbol, extra)| (self.code_length_lengths[*symbol] + extra.len()) as u64)
+            .sum();
+        5 + 5 + 4 + 3 * self.code_length_count as u64 + tokens
+    }
+
+    pub fn write<T: Write>(&self, writer: &mut BitWriter<T>) -> io::Result<()> {
+        writer.write_bits(BitSequence::new(self.litlen_lengths.len() as u16 - 257, 5))?;
+        writer.write_bits(BitSequence::new(self.distance_lengths.len() as u16 - 1, 5))?;
+        writer.write_bits(BitSequence::new(self.code_length_count as u16 - 4, 4))?;
+        for &i in CODE_LENGTH_ORDER.iter().take(self.code_length_count) {
+            writer.write_bits(BitSequence::new(self.code_length_lengths[i] as u16, 3))?;
+        }
+        for (symbol, extra) in &self.tokens {
+            writer.write_bits(self.code_length_codes[*symbol])?;
+            writer.write_bits(*extra)?;
+        }
+        Ok(())
+    }
+}
+
+fn trimmed_len(lengths: &[u8], min: usize) -> usize {
+    lengths
+        .iter()
+        .rposition(|&len| len != 0)
+        .map_or(min, |i| (i + 1).max(min))
+}
+
+fn run_length_encode(lengths: &[u8]) -> Vec<(usize, BitSequence)> {
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < lengths.len() {
+        let len = lengths[i];
+        let mut run = lengths[i..].iter().take_while(|&&x| x == len).count();
+        i += run;
+        if len == 0 {
+            while run >= 11 {
+                let repeats = run.min(138);
+                tokens.push((18, BitSequence::new((repeats - 11) as u16, 7)));
+                run -= repeats;
+            }
+            if run >= 3 {
+                tokens.push((17, BitSequence::new((run - 3) as u16, 3)));
+                run = 0;
+            }
+        } else {
+            tokens.push((len as usize, BitSequence::new(0, 0)));
+            run -= 1;
+            while run >= 3 {
+                let repeats = run.min(6);
+                tokens.push((16, BitSequence::new((repeats - 3) as u16, 2)));
+                run -= repeats;
             }
         }
-        Ok(Self { map: result })
+        for _ in 0..run {
+            tokens.push((len as usize, BitSequence::new(0, 0)));
+        }
     }
+    tokens
 }
 
 ////////////////////////////////////////////////////////////////////////////////
@@ -368,7 +774,7 @@ mod tests {
     struct Value(u16);
 
     impl TryFrom<HuffmanCodeWord> for Value {
-        type Error = anyhow::Error;
+        type Error = Error;
 
         fn try_from(x: HuffmanCodeWord) -> Result<Self> {
             Ok(Self(x.0))
@@ -432,4 +838,140 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn fixed_trees() -> Result<()> {
+        let (litlen_coding, distance_coding) = fixed_litlen_distance_trees()?;
+        let decode_litlen = |bits, len| litlen_coding.decode_symbol(BitSequence::new(bits, len));
+        assert!(matches!(decode_litlen(0b00110000, 8), Some(Literal(0))));
+        assert!(matches!(decode_litlen(0b10111111, 8), Some(Literal(143))));
+        assert!(matches!(decode_litlen(0b110010000, 9), Some(Literal(144))));
+        assert!(matches!(decode_litlen(0b111111111, 9), Some(Literal(255))));
+        assert!(matches!(decode_litlen(0b0000000, 7), Some(EndOfBlock)));
+        assert!(matches!(
+            decode_litlen(0b0010111, 7),
+            Some(Length {
+                base: 99,
+                extra_bits: 4
+            })
+        ));
+        assert!(matches!(
+            decode_litlen(0b11000101, 8),
+            Some(Length {
+                base: 258,
+                extra_bits: 0
+            })
+        ));
+        assert!(decode_litlen(0b11000110, 8).is_none());
+        assert!(matches!(
+            distance_coding.decode_symbol(BitSequence::new(0b11101, 5)),
+            Some(DistanceToken {
+                base: 24577,
+                extra_bits: 13
+            })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn build_code_lengths_limited() {
+        let frequencies: Vec<u32> = (0..30).map(|i| 1 << i).collect();
+        let lengths = build_code_lengths(&frequencies, 15);
+        assert!(lengths.iter().all(|&len| (1..=15).contains(&len)));
+        let kraft: u32 = lengths.iter().map(|&len| 1 << (15 - len)).sum();
+        assert_eq!(kraft, 1 << 15);
+        assert!(lengths.windows(2).all(|w| w[0] >= w[1]));
+
+        assert_eq!(build_code_lengths(&[0, 0, 7, 0], 7), [1, 0, 1, 0]);
+        assert_eq!(build_code_lengths(&[0, 0, 0], 7), [1, 1, 0]);
+    }
+
+    #[test]
+    fn build_codes_roundtrip() -> Result<()> {
+        let lengths = [2, 3, 4, 3, 3, 4, 2];
+        let codes = build_codes(&lengths);
+        let mut data = Vec::new();
+        let mut writer = BitWriter::new(&mut data);
+        for symbol in [1, 2, 3, 6, 0, 2, 4, 5] {
+            writer.write_bits(codes[symbol])?;
+        }
+        writer.borrow_writer_from_boundary()?;
+
+        let coding = HuffmanCoding::<Value>::from_lengths(&lengths)?;
+        let mut reader = BitReader::new(data.as_slice());
+        for symbol in [1, 2, 3, 6, 0, 2, 4, 5] {
+            assert_eq!(coding.read_symbol(&mut reader)?, Value(symbol));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn long_codes_roundtrip() -> Result<()> {
+        let frequencies: Vec<u32> = (0..30).map(|i| 1 << i).collect();
+        let lengths = build_code_lengths(&frequencies, 15);
+        assert!(lengths.iter().any(|&len| len as usize == MAX_BITS));
+        let codes = build_codes(&lengths);
+        let symbols: Vec<usize> = (0..30).chain((0..30).rev()).collect();
+        let mut data = Vec::new();
+        let mut writer = BitWriter::new(&mut data);
+        for &symbol in &symbols {
+            writer.write_bits(codes[symbol])?;
+        }
+        writer.borrow_writer_from_boundary()?;
+
+        let coding = HuffmanCoding::<Value>::from_lengths(&lengths)?;
+        let mut reader = BitReader::new(data.as_slice());
+        for &symbol in &symbols {
+            assert_eq!(coding.read_symbol(&mut reader)?, Value(symbol as u16));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn oversubscribed() {
+        assert!(HuffmanCoding::<Value>::from_lengths(&[1, 1, 1]).is_err());
+    }
+
+    #[test]
+    fn dynamic_trees_roundtrip() -> Result<()> {
+        let mut litlen_frequencies = [0u32; LITLEN_CODES];
+        for (i, freq) in litlen_frequencies.iter_mut().enumerate().take(200) {
+            *freq = (i as u32 * 7919) % 61;
+        }
+        litlen_frequencies[END_OF_BLOCK] = 1;
+        litlen_frequencies[270] = 5;
+        let mut distance_frequencies = [0u32; DISTANCE_CODES];
+        distance_frequencies[3] = 10;
+        distance_frequencies[17] = 2;
+
+        let trees = DynamicTrees::new(&litlen_frequencies, &distance_frequencies);
+        let mut header = Vec::new();
+        let mut writer = BitWriter::new(&mut header);
+        trees.write(&mut writer)?;
+        let pending = writer.pending_bits() as u64;
+        assert_eq!(trees.cost(), header.len() as u64 * 8 + pending);
+
+        let mut data = Vec::new();
+        let mut writer = BitWriter::new(&mut data);
+        trees.write(&mut writer)?;
+        let codes = build_codes(&trees.litlen_lengths);
+        writer.write_bits(codes[270])?;
+        writer.write_bits(codes[END_OF_BLOCK])?;
+        writer.borrow_writer_from_boundary()?;
+
+        let mut reader = BitReader::new(data.as_slice());
+        let (litlen_coding, _) = decode_litlen_distance_trees(&mut reader)?;
+        assert!(matches!(
+            litlen_coding.read_symbol(&mut reader)?,
+            Length {
+                base: 23,
+                extra_bits: 2
+            }
+        ));
+        assert!(matches!(
+            litlen_coding.read_symbol(&mut reader)?,
+            EndOfBlock
+        ));
+        Ok(())
+    }
 }
@@ -0,0 +1,298 @@
+use std::cmp::min;
+use std::io::{self, BufRead, Read, Seek, SeekFrom, Write};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::decoder::{into_io_error, Decoder};
+use crate::error::{Error, Result};
+use crate::Format;
+
+////////////////////////////////////////////////////////////////////////////////
+
+const MAGIC: &[u8; 4] = b"RGZI";
+const VERSION: u8 = 1;
+
+const MAX_WINDOW_SIZE: usize = 32768;
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// A position in a gzip stream where decompression can be resumed.
+///
+/// Access points are located at DEFLATE block boundaries, so along with
+/// the offsets only the preceding 32 KiB of output have to be kept.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AccessPoint {
+    pub output_offset: u64,
+    pub input_bit_offset: u64,
+    pub window: Vec<u8>,
+}
+
+/// A random access index of a gzip stream, in the spirit of zlib's `zran.c`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Index {
+    span: u64,
+    total_size: u64,
+    points: Vec<AccessPoint>,
+}
+
+impl Index {
+    /// Decompress the whole `input`, recording an access point after every `span` bytes of output.
+    pub fn build<R: BufRead>(input: R, span: u64) -> Result<Self> {
+        let mut decoder = Decoder::new(input, Format::Gzip);
+        let mut output_offset = 0;
+        let mut points = Vec::new();
+        let mut last_offset = 0;
+        while !decoder.is_done() {
+            if decoder.is_at_block_boundary() && output_offset >= last_offset + span {
+                points.push(AccessPoint {
+                    output_offset,
+                    input_bit_offset: decoder.input_position(),
+                    window: decoder.window(),
+                });
+                last_offset = output_offset;
+            }
+            decoder.step()?;
+            output_offset += decoder.discard_output() as u64;
+        }
+        Ok(Self {
+            span,
+            total_size: output_offset,
+            points,
+        })
+    }
+
+    pub fn span(&self) -> u64 {
+        self.span
+    }
+
+    /// Size of the decompressed data.
+    pub fn total_size(&self) -> u64 {
+        self.total_size
+    }
+
+    /// Access points in the increasing order of offsets. The start of the stream is not included.
+    pub fn points(&self) -> &[AccessPoint] {
+        &self.points
+    }
+
+    /// The last access point at or before `offset` of the output.
+    fn point_before(&self, offset: u64) -> Option<&AccessPoint> {
+        let count = self
+            .points
+            .partition_point(|point| point.output_offset <= offset);
+        count.checked_sub(1).map(|i| &self.points[i])
+    }
+
+    pub fn write_to<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_all(MAGIC)?;
+        writer.write_u8(VERSION)?;
+        writer.write_u64::<LittleEndian>(self.span)?;
+        writer.write_u64::<LittleEndian>(self.total_size)?;
+        writer.write_u64::<LittleEndian>(self.points.len() as u64)?;
+        for point in &self.points {
+            writer.write_u64::<LittleEndian>(point.output_offset)?;
+            writer.write_u64::<LittleEndian>(point.input_bit_offset)?;
+            writer.write_u32::<LittleEndian>(point.window.len() as u32)?;
+            writer.write_all(&point.window)?;
+        }
+        Ok(())
+    }
+
+    pub fn read_from<R: Read>(mut reader: R) -> Result<Self> {
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(Error::InvalidIndex("not a ripgzip index".into()));
+        }
+        let version = reader.read_u8()?;
+        if version != VERSION {
+            return Err(Error::InvalidIndex(format!(
+                "unsupported version {}",
+                version
+            )));
+        }
+        let span = reader.read_u64::<LittleEndian>()?;
+        let total_size = reader.read_u64::<LittleEndian>()?;
+        let count = reader.read_u64::<LittleEndian>()?;
+        let mut points: Vec<AccessPoint> = Vec::new();
+        for _ in 0..count {
+            let output_offset = reader.read_u64::<LittleEndian>()?;
+            let input_bit_offset = reader.read_u64::<LittleEndian>()?;
+            let window_size = reader.read_u32::<LittleEndian>()? as usize;
+            if window_size > MAX_WINDOW_SIZE {
+                return Err(Error::InvalidIndex("window is too large".into()));
+            }
+            if points
+                .last()
+                .is_some_and(|last| last.output_offset >= output_offset)
+            {
+                return Err(Error::InvalidIndex("access points are not sorted".into()));
+            }
+            let mut window = vec![0; window_size];
+            reader.read_exact(&mut window)?;
+            points.push(AccessPoint {
+                output_offset,
+                input_bit_offset,
+                window,
+            });
+        }
+        Ok(Self {
+            span,
+            total_size,
+            points,
+        })
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// A gzip reader supporting `Seek` within the decompressed data with the help of an `Index`.
+pub struct SeekableGzReader<R> {
+    index: Index,
+    /// The decoder along with the output offset of its next byte.
+    /// It is only missing if resuming decompression failed.
+    decoder: Option<(Decoder<R>, u64)>,
+    position: u64,
+}
+
+impl<R: BufRead + Seek> SeekableGzReader<R> {
+    /// `input` must be the gzip stream the `index` was built for, starting at offset 0.
+    pub fn new(input: R, index: Index) -> Self {
+        Self {
+            index,
+            decoder: Some((Decoder::new(input, Format::Gzip), 0)),
+            position: 0,
+        }
+    }
+
+    pub fn index(&self) -> &Index {
+        &self.index
+    }
+
+    /// Make the decoder produce the output starting at `position`.
+    fn prepare_decoder(&mut self) -> Result<&mut Decoder<R>> {
+        let point = self.index.point_before(self.position);
+        let point_offset = point.map_or(0, |point| point.output_offset);
+        let is_reusable = matches!(
+            &self.decoder,
+            Some((_, offset)) if *offset <= self.position && *offset >= point_offset
+        );
+        if !is_reusable {
+            let mut input = match self.decoder.take() {
+                Some((decoder, _)) => decoder.into_inner(),
+                None => {
+                    return Err(io::Error::other("the reader is broken by a previous error").into())
+                }
+            };
+            let decoder = match point {
+                None => {
+                    input.seek(SeekFrom::Start(0))?;
+                    Decoder::new(input, Format::Gzip)
+                }
+                Some(point) => {
+                    input.seek(SeekFrom::Start(point.input_bit_offset / 8))?;
+                    let skip_bits = (point.input_bit_offset % 8) as u8;
+                    Decoder::resume(input, skip_bits, &point.window)?
+                }
+            };
+            self.decoder = Some((decoder, point_offset));
+        }
+
+        let (decoder, offset) = self.decoder.as_mut().unwrap();
+        while *offset < self.position {
+            let data = decoder.decode()?;
+            if data.is_empty() {
+                break;
+            }
+            let len = min(data.len() as u64, self.position - *offset) as usize;
+            decoder.consume(len);
+            *offset += len as u64;
+        }
+        Ok(decoder)
+    }
+}
+
+impl<R: BufRead + Seek> Read for SeekableGzReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let decoder = self.prepare_decoder().map_err(into_io_error)?;
+        let data = decoder.decode().map_err(into_io_error)?;
+        let len = min(data.len(), buf.len());
+        buf[..len].copy_from_slice(&data[..len]);
+        decoder.consume(len);
+        self.position += len as u64;
+        if let Some((_, offset)) = &mut self.decoder {
+            *offset += len as u64;
+        }
+        Ok(len)
+    }
+}
+
+impl<R: BufRead + Seek> Seek for SeekableGzReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(delta) => self.index.total_size.checked_add_signed(delta),
+            SeekFrom::Current(delta) => self.position.checked_add_signed(delta),
+        };
+        self.position = position.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )
+        })?;
+        Ok(self.position)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serialization() -> Result<()> {
+        let index = Index {
+            span: 1 << 20,
+            total_size: 3 << 20,
+            points: vec![
+                AccessPoint {
+                    output_offset: 1 << 20,
+                    input_bit_offset: 123_456,
+                    window: vec![1, 2, 3],
+                },
+                AccessPoint {
+                    output_offset: 2 << 20,
+                    input_bit_offset: 234_567,
+                    window: vec![4; MAX_WINDOW_SIZE],
+                },
+            ],
+        };
+        let mut data = Vec::new();
+        index.write_to(&mut data)?;
+        assert_eq!(Index::read_from(data.as_slice())?, index);
+
+        data[0] = b'X';
+        assert!(Index::read_from(data.as_slice()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn point_before() {
+        let point = |output_offset| AccessPoint {
+            output_offset,
+            input_bit_offset: 0,
+            window: vec![],
+        };
+        let index = Index {
+            span: 10,
+            total_size: 100,
+            points: vec![point(10), point(25)],
+        };
+        assert_eq!(index.point_before(0), None);
+        assert_eq!(index.point_before(9), None);
+        assert_eq!(index.point_before(10), Some(&point(10)));
+        assert_eq!(index.point_before(24), Some(&point(10)));
+        assert_eq!(index.point_before(1000), Some(&point(25)));
+    }
+}
@@ -1,93 +1,118 @@
 #![feature(buf_read_has_data_left)]
-#![forbid(unsafe_code)]
+#![deny(unsafe_code)]
 
 extern crate core;
 
-use std::io::{BufRead, Write};
+use std::io::{self, BufRead, Write};
 
-use anyhow::{anyhow, Result};
-
-use bit_reader::BitReader;
-use deflate::{CompressionType, DeflateReader};
-use gzip::GzipReader;
-use huffman_coding::{decode_litlen_distance_trees, LitLenToken};
-use log::*;
-use tracking_writer::TrackingWriter;
-
-#[cfg(test)]
-use std::println as debug;
+pub use analyze::{Analysis, BlockInfo, BlockType};
+#[cfg(feature = "tokio")]
+pub use async_io::{AsyncGzDecoder, AsyncGzEncoder, AsyncZlibDecoder, AsyncZlibEncoder};
+pub use bgzf::{BgzfReader, BgzfWriter, VirtualOffset};
+pub use crc32::{crc32_combine, Crc32};
+pub use decoder::{
+    DecompressOptions, DeflateDecoder, GzDecoder, MemberHeaders, MemberInfo, Members, ZlibDecoder,
+};
+pub use encoder::{DeflateEncoder, GzEncoder, ZlibEncoder};
+pub use error::{Error, Limit, Location, Result};
+pub use gzip::{CompressionMethod, ExtraSubfield, MemberFlags, MemberHeader};
+pub use huffman_coding::CodeLengths;
+pub use index::{AccessPoint, Index, SeekableGzReader};
+pub use lz77::Level;
+pub use parallel::{compress_parallel, compress_parallel_with_header, decompress_parallel};
+pub use recovery::{decompress_lenient, LostRange, RecoveredRange, RecoveryReport};
+pub use zip::{ZipArchive, ZipEntry, ZipEntryReader, ZipMethod};
 
+mod adler32;
+mod analyze;
+#[cfg(feature = "tokio")]
+mod async_io;
+mod bgzf;
 mod bit_reader;
+mod bit_writer;
+mod chunker;
+mod crc32;
+mod decoder;
 mod deflate;
+mod encoder;
+mod error;
 mod gzip;
 mod huffman_coding;
+mod index;
+mod lz77;
+mod optimal;
+mod parallel;
+mod recovery;
 mod tracking_writer;
+mod zip;
+mod zlib;
 
 ////////////////////////////////////////////////////////////////////////////////
 
-pub fn compress<R: BufRead, W: Write>(_input: R, _output: W) -> Result<()> {
-    // todo: tba in future
-    unimplemented!()
+/// Container format around a DEFLATE stream.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Format {
+    Gzip,
+    Zlib,
+    Raw,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+pub fn compress<R: BufRead, W: Write>(mut input: R, output: W) -> Result<()> {
+    let mut encoder = GzEncoder::new(output, Level::default());
+    io::copy(&mut input, &mut encoder)?;
+    encoder.finish()?.flush()?;
+    Ok(())
+}
+
+/// Like `compress`, but letting matches refer to the last 32 KiB of `dictionary`,
+/// which must be given to `decompress_with_dictionary` as well.
+pub fn compress_with_dictionary<R: BufRead, W: Write>(
+    mut input: R,
+    output: W,
+    dictionary: &[u8],
+) -> Result<()> {
+    let mut encoder = GzEncoder::with_dictionary(output, Level::default(), dictionary);
+    io::copy(&mut input, &mut encoder)?;
+    encoder.finish()?.flush()?;
+    Ok(())
 }
 
 ////////////////////////////////////////////////////////////////////////////////
 
 pub fn decompress<R: BufRead, W: Write>(input: R, output: W) -> Result<()> {
-    let mut gzip_reader = GzipReader::new(input);
-    let mut tracking_writer = TrackingWriter::new(output);
-    while let Some(member) = gzip_reader.next_member() {
-        let (_, mut member_reader) = member?;
-        let mut reader = DeflateReader::new(BitReader::new(member_reader.inner_mut()));
-        while let Some(block) = reader.next_block() {
-            let (header, r) = block?;
-            match header.compression_type {
-                CompressionType::Uncompressed => {
-                    let _ = r.borrow_reader_from_boundary();
-                    let len = r.read_bits(16)?.bits();
-                    let nlen = r.read_bits(16)?.bits();
-                    if len != !nlen {
-                        return Err(anyhow!("nlen check failed"));
-                    }
-                    for _ in 0..len {
-                        let _ = tracking_writer.write(&[r.read_bits(8)?.bits() as u8])?;
-                    }
-                }
-                CompressionType::DynamicTree => {
-                    let (litlen_coding, distance_coding) = decode_litlen_distance_trees(r)?;
-                    while let token = litlen_coding.read_symbol(r)? {
-                        match token {
-                            LitLenToken::Literal(lit) => {
-                                let _ = tracking_writer.write(&[lit])?;
-                            }
-                            LitLenToken::EndOfBlock => break,
-                            LitLenToken::Length { base, extra_bits } => {
-                                let len = (base + r.read_bits(extra_bits)?.bits()) as usize;
-                                let distance_token = distance_coding.read_symbol(r)?;
-                                let distance = (distance_token.base
-                                    + r.read_bits(distance_token.extra_bits)?.bits())
-                                    as usize;
-                                tracking_writer.write_previous(distance, len)?;
-                            }
-                        }
-                    }
-                }
-                _ => {
-                    return Err(anyhow!("unsupported block type"));
-                }
-            }
-            if header.is_final {
-                break;
-            }
-        }
-        let (footer, new_gzip_reader) = member_reader.read_footer()?;
-        if footer.data_size as usize != tracking_writer.byte_count() {
-            return Err(anyhow!("length check failed"));
-        }
-        if footer.data_crc32 != tracking_writer.crc32() {
-            return Err(anyhow!("crc32 check failed"));
+    decompress_with_options(input, output, DecompressOptions::default())
+}
+
+/// Like `decompress`, but enforcing the resource caps of `options`.
+pub fn decompress_with_options<R: BufRead, W: Write>(
+    input: R,
+    output: W,
+    options: DecompressOptions,
+) -> Result<()> {
+    write_decoded(GzDecoder::with_options(input, options), output)
+}
+
+/// Like `decompress`, for streams written by `compress_with_dictionary`.
+pub fn decompress_with_dictionary<R: BufRead, W: Write>(
+    input: R,
+    output: W,
+    dictionary: &[u8],
+) -> Result<()> {
+    write_decoded(GzDecoder::with_dictionary(input, dictionary), output)
+}
+
+fn write_decoded<R: BufRead, W: Write>(mut decoder: GzDecoder<R>, mut output: W) -> Result<()> {
+    loop {
+        let data = decoder.decode()?;
+        if data.is_empty() {
+            break;
         }
-        gzip_reader = new_gzip_reader;
-        tracking_writer.flush()?;
+        output.write_all(data)?;
+        let len = data.len();
+        decoder.consume(len);
     }
+    output.flush()?;
     Ok(())
 }
@@ -0,0 +1,614 @@
+use std::cmp::min;
+
+use crate::optimal::{self, Matches};
+
+////////////////////////////////////////////////////////////////////////////////
+
+pub const WINDOW_SIZE: usize = 32768;
+pub const MIN_MATCH: usize = 3;
+pub const MAX_MATCH: usize = 258;
+
+const HASH_BITS: usize = 15;
+const HASH_SIZE: usize = 1 << HASH_BITS;
+const WINDOW_MASK: usize = WINDOW_SIZE - 1;
+const NIL: usize = usize::MAX;
+
+const BLOCK_TOKENS: usize = 16384;
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Compression level from 0 (no compression) to 9 (best compression) as in zlib,
+/// or 10 for the much slower optimal parsing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Level(u8);
+
+impl Level {
+    pub fn new(level: u8) -> Self {
+        assert!(level <= 10, "compression level must be in 0..=10");
+        Self(level)
+    }
+
+    pub fn none() -> Self {
+        Self(0)
+    }
+
+    pub fn fast() -> Self {
+        Self(1)
+    }
+
+    pub fn best() -> Self {
+        Self(9)
+    }
+
+    /// Choose the matches of every block by iterative cost-based optimal parsing, like zopfli.
+    /// Meant for data compressed once and decompressed many times.
+    pub fn optimal() -> Self {
+        Self(10)
+    }
+
+    pub fn level(&self) -> u8 {
+        self.0
+    }
+}
+
+impl Default for Level {
+    fn default() -> Self {
+        Self(6)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Strategy {
+    /// Take the longest match at every position.
+    Greedy,
+    /// Emit a match only if the next position does not start a longer one.
+    Lazy,
+    /// Find the cheapest sequence of tokens for the whole block.
+    Optimal,
+}
+
+struct Config {
+    /// Search only a quarter of the chain for a lazy match when the current match is that long.
+    good_length: usize,
+    /// Do not look for a lazy match when the current match is that long.
+    /// Greedy levels do not index the positions inside matches longer than that.
+    max_lazy: usize,
+    nice_length: usize,
+    max_chain: usize,
+    strategy: Strategy,
+}
+
+const fn config(
+    good_length: usize,
+    max_lazy: usize,
+    nice_length: usize,
+    max_chain: usize,
+    strategy: Strategy,
+) -> Config {
+    Config {
+        good_length,
+        max_lazy,
+        nice_length,
+        max_chain,
+        strategy,
+    }
+}
+
+/// Matching parameters for every level, as in zlib.
+const CONFIGS: [Config; 11] = [
+    config(0, 0, 0, 0, Strategy::Greedy),
+    config(4, 4, 8, 4, Strategy::Greedy),
+    config(4, 5, 16, 8, Strategy::Greedy),
+    config(4, 6, 32, 32, Strategy::Greedy),
+    config(4, 4, 16, 16, Strategy::Lazy),
+    config(8, 16, 32, 32, Strategy::Lazy),
+    config(8, 16, 128, 128, Strategy::Lazy),
+    config(8, 32, 128, 256, Strategy::Lazy),
+    config(32, 128, 258, 1024, Strategy::Lazy),
+    config(32, 258, 258, 4096, Strategy::Lazy),
+    config(258, 258, 258, 8192, Strategy::Optimal),
+];
+
+/// Lazy levels drop matches of the minimal length farther than that, as they cost more than literals.
+const TOO_FAR: usize = 4096;
+
+/// Amount of input parsed at once by the optimal level.
+const OPTIMAL_CHUNK_SIZE: usize = 65536;
+
+/// The optimal level parses chunks into the same block until it has that many tokens.
+const OPTIMAL_BLOCK_TOKENS: usize = 8192;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Flush {
+    /// Keep the input buffered until a block is complete.
+    None,
+    /// Emit all the buffered input as a non-final block.
+    Sync,
+    /// Emit all the buffered input, the last block being final.
+    Finish,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Token {
+    Literal(u8),
+    Match { length: u16, distance: u16 },
+}
+
+pub struct Block<'a> {
+    pub tokens: &'a [Token],
+    pub data: &'a [u8],
+    pub is_final: bool,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Splits the input into blocks of LZ77 tokens.
+///
+/// All positions are absolute offsets in the input stream; `window` holds the
+/// bytes starting from `base`: at least `WINDOW_SIZE` bytes of history before
+/// the current block, the block itself and the pending input.
+pub struct Lz77Encoder {
+    config: &'static Config,
+    window: Vec<u8>,
+    base: usize,
+    block_start: usize,
+    cursor: usize,
+    tokens: Vec<Token>,
+    head: Vec<usize>,
+    prev: Vec<usize>,
+    block_emitted: bool,
+    finished: bool,
+}
+
+impl Lz77Encoder {
+    pub fn new(level: Level) -> Self {
+        Self {
+            config: &CONFIGS[level.0 as usize],
+            window: Vec::new(),
+            base: 0,
+            block_start: 0,
+            cursor: 0,
+            tokens: Vec::with_capacity(BLOCK_TOKENS),
+            head: vec![NIL; HASH_SIZE],
+            prev: vec![NIL; WINDOW_SIZE],
+            block_emitted: false,
+            finished: false,
+        }
+    }
+
+    /// Make the last `WINDOW_SIZE` bytes of `dictionary` available for matches,
+    /// as if they were compressed before. Must be called before any input is pushed.
+    pub fn set_dictionary(&mut self, dictionary: &[u8]) {
+        assert!(self.window.is_empty(), "the dictionary must be set first");
+        let dictionary = &dictionary[dictionary.len().saturating_sub(WINDOW_SIZE)..];
+        self.window.extend_from_slice(dictionary);
+        for pos in 0..dictionary.len() {
+            self.insert(pos);
+        }
+        self.block_start = dictionary.len();
+        self.cursor = dictionary.len();
+    }
+
+    pub fn push(&mut self, data: &[u8]) {
+        self.window.extend_from_slice(data);
+    }
+
+    /// Tokenize the pushed input and return the next complete block, if any.
+    pub fn next_block(&mut self, flush: Flush) -> Option<Block<'_>> {
+        if self.finished {
+            return None;
+        }
+        if self.block_emitted {
+            self.start_block();
+        }
+        let end = self.end();
+        // Without flushing, the input of every match search must be complete, including
+        // the lazy lookahead and the positions inside matches which are indexed, so that
+        // the output does not depend on how the input is pushed.
+        let limit = match flush {
+            Flush::None => end.saturating_sub(MAX_MATCH + MIN_MATCH),
+            Flush::Sync | Flush::Finish => end,
+        };
+        match self.config.strategy {
+            Strategy::Greedy => self.tokenize_greedy(limit),
+            Strategy::Lazy => self.tokenize_lazy(limit),
+            Strategy::Optimal => self.tokenize_optimal(limit, flush),
+        }
+
+        let consumed = self.cursor == end;
+        let is_ready = match flush {
+            Flush::None => false,
+            Flush::Sync => consumed && !self.tokens.is_empty(),
+            Flush::Finish => consumed,
+        };
+        let block_tokens = match self.config.strategy {
+            Strategy::Optimal => OPTIMAL_BLOCK_TOKENS,
+            _ => BLOCK_TOKENS,
+        };
+        if self.tokens.len() < block_tokens && !is_ready {
+            return None;
+        }
+        let is_final = flush == Flush::Finish && consumed;
+        self.block_emitted = true;
+        self.finished = is_final;
+        Some(Block {
+            tokens: &self.tokens,
+            data: &self.window[self.block_start - self.base..self.cursor - self.base],
+            is_final,
+        })
+    }
+
+    fn end(&self) -> usize {
+        self.base + self.window.len()
+    }
+
+    fn start_block(&mut self) {
+        self.tokens.clear();
+        self.block_start = self.cursor;
+        self.block_emitted = false;
+
+        let keep_from = self.block_start.saturating_sub(WINDOW_SIZE);
+        if keep_from - self.base >= WINDOW_SIZE {
+            self.window.drain(..keep_from - self.base);
+            self.base = keep_from;
+        }
+    }
+
+    fn tokenize_greedy(&mut self, limit: usize) {
+        while self.cursor < limit && self.tokens.len() < BLOCK_TOKENS {
+            let (length, distance) = self.find_match(self.cursor, self.config.max_chain);
+            self.insert(self.cursor);
+            if length >= MIN_MATCH {
+                self.push_match(length, distance);
+                if length <= self.config.max_lazy {
+                    for pos in self.cursor + 1..self.cursor + length {
+                        self.insert(pos);
+                    }
+                }
+                self.cursor += length;
+            } else {
+                self.push_literal();
+                self.cursor += 1;
+            }
+        }
+    }
+
+    fn tokenize_lazy(&mut self, limit: usize) {
+        // The match found at the cursor while looking ahead from the previous position.
+        let mut next_match = None;
+        while self.cursor < limit && self.tokens.len() < BLOCK_TOKENS {
+            let (length, distance) = next_match
+                .take()
+                .unwrap_or_else(|| self.find_match(self.cursor, self.config.max_chain));
+            self.insert(self.cursor);
+            if length < MIN_MATCH {
+                self.push_literal();
+                self.cursor += 1;
+                continue;
+            }
+            if length < self.config.max_lazy && self.cursor + 1 < self.end() {
+                let max_chain = if length >= self.config.good_length {
+                    self.config.max_chain >> 2
+                } else {
+                    self.config.max_chain
+                };
+                let next = self.find_match(self.cursor + 1, max_chain);
+                if next.0 > length {
+                    self.push_literal();
+                    self.cursor += 1;
+                    next_match = Some(next);
+                    continue;
+                }
+            }
+            self.push_match(length, distance);
+            for pos in self.cursor + 1..self.cursor + length {
+                self.insert(pos);
+            }
+            self.cursor += length;
+        }
+    }
+
+    /// Parse the input by chunks of `OPTIMAL_CHUNK_SIZE` bytes, the last one being shorter
+    /// only when flushing.
+    fn tokenize_optimal(&mut self, limit: usize, flush: Flush) {
+        while self.tokens.len() < OPTIMAL_BLOCK_TOKENS {
+            let available = limit.saturating_sub(self.cursor);
+            if available == 0 || flush == Flush::None && available < OPTIMAL_CHUNK_SIZE {
+                return;
+            }
+            let chunk_end = self.cursor + min(available, OPTIMAL_CHUNK_SIZE);
+            let mut matches = Matches::with_capacity(chunk_end - self.cursor);
+            // Positions inside a match of the maximal length are not searched, which keeps
+            // long runs fast to parse: taking the whole match is as good as it gets.
+            let mut skip_until = self.cursor;
+            for pos in self.cursor..chunk_end {
+                matches.next_position();
+                if pos >= skip_until {
+                    let max_length = min(MAX_MATCH, chunk_end - pos);
+                    let (length, _) = self.longest_match(
+                        pos,
+                        max_length,
+                        self.config.max_chain,
+                        Some(&mut matches),
+                    );
+                    if length == MAX_MATCH {
+                        skip_until = pos + length;
+                    }
+                }
+                self.insert(pos);
+            }
+            let data = &self.window[self.cursor - self.base..chunk_end - self.base];
+            self.tokens.extend(optimal::parse(data, &matches));
+            self.cursor = chunk_end;
+        }
+    }
+
+    fn push_literal(&mut self) {
+        let byte = self.window[self.cursor - self.base];
+        self.tokens.push(Token::Literal(byte));
+    }
+
+    fn push_match(&mut self, length: usize, distance: usize) {
+        self.tokens.push(Token::Match {
+            length: length as u16,
+            distance: distance as u16,
+        });
+    }
+
+    fn hash(&self, pos: usize) -> usize {
+        let i = pos - self.base;
+        let bytes = &self.window[i..i + MIN_MATCH];
+        ((bytes[0] as usize) << 10 ^ (bytes[1] as usize) << 5 ^ bytes[2] as usize) & (HASH_SIZE - 1)
+    }
+
+    fn insert(&mut self, pos: usize) {
+        if pos + MIN_MATCH > self.end() {
+            return;
+        }
+        let hash = self.hash(pos);
+        self.prev[pos & WINDOW_MASK] = self.head[hash];
+        self.head[hash] = pos;
+    }
+
+    /// Find the longest match for `pos` in the window, returning its length and distance.
+    fn find_match(&self, pos: usize, max_chain: usize) -> (usize, usize) {
+        let max_length = min(MAX_MATCH, self.end() - pos);
+        let (length, distance) = self.longest_match(pos, max_length, max_chain, None);
+        let is_too_far = length == MIN_MATCH && distance > TOO_FAR;
+        if self.config.strategy == Strategy::Lazy && is_too_far {
+            return (0, 0);
+        }
+        (length, distance)
+    }
+
+    /// Find the longest match for `pos` of at most `max_length` bytes, following up to
+    /// `max_chain` candidates. Every match longer than the ones before is added to `matches`.
+    fn longest_match(
+        &self,
+        pos: usize,
+        max_length: usize,
+        max_chain: usize,
+        mut matches: Option<&mut Matches>,
+    ) -> (usize, usize) {
+        if max_length < MIN_MATCH || pos + MIN_MATCH > self.end() {
+            return (0, 0);
+        }
+        let current = &self.window[pos - self.base..pos - self.base + max_length];
+
+        let mut best_length = MIN_MATCH - 1;
+        let mut best_distance = 0;
+        let mut candidate = self.head[self.hash(pos)];
+        let mut chain = max_chain;
+        while candidate != NIL && chain > 0 {
+            let distance = pos - candidate;
+            if distance > WINDOW_SIZE {
+                break;
+            }
+            let previous = &self.window[candidate - self.base..];
+            if previous[best_length] == current[best_length] {
+                let length = previous
+                    .iter()
+                    .zip(current)
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                if length > best_length {
+                    best_length = length;
+                    best_distance = distance;
+                    if let Some(matches) = matches.as_deref_mut() {
+                        matches.push(length, distance);
+                    }
+                    if length >= self.config.nice_length || length == max_length {
+                        break;
+                    }
+                }
+            }
+            let next = self.prev[candidate & WINDOW_MASK];
+            if next >= candidate {
+                break;
+            }
+            candidate = next;
+            chain -= 1;
+        }
+        if best_length >= MIN_MATCH {
+            (best_length, best_distance)
+        } else {
+            (0, 0)
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(blocks: &[Vec<Token>]) -> Vec<u8> {
+        let mut result = Vec::new();
+        for token in blocks.iter().flatten() {
+            match *token {
+                Token::Literal(byte) => result.push(byte),
+                Token::Match { length, distance } => {
+                    assert!((MIN_MATCH..=MAX_MATCH).contains(&(length as usize)));
+                    assert!(distance as usize <= WINDOW_SIZE);
+                    let start = result.len() - distance as usize;
+                    for i in 0..length as usize {
+                        result.push(result[start + i]);
+                    }
+                }
+            }
+        }
+        result
+    }
+
+    fn encode(chunks: &[&[u8]]) -> Vec<Vec<Token>> {
+        encode_with_level(chunks, Level::default())
+    }
+
+    fn encode_with_level(chunks: &[&[u8]], level: Level) -> Vec<Vec<Token>> {
+        let mut encoder = Lz77Encoder::new(level);
+        let mut blocks = Vec::new();
+        let mut total = Vec::new();
+        for chunk in chunks {
+            encoder.push(chunk);
+            total.extend_from_slice(chunk);
+            while let Some(block) = encoder.next_block(Flush::None) {
+                assert!(!block.is_final);
+                blocks.push((block.tokens.to_vec(), block.data.to_vec()));
+            }
+        }
+        while let Some(block) = encoder.next_block(Flush::Finish) {
+            blocks.push((block.tokens.to_vec(), block.data.to_vec()));
+        }
+        let data: Vec<u8> = blocks.iter().flat_map(|(_, data)| data.clone()).collect();
+        assert_eq!(data, total);
+        blocks.into_iter().map(|(tokens, _)| tokens).collect()
+    }
+
+    #[test]
+    fn empty() {
+        let blocks = encode(&[]);
+        assert_eq!(blocks, vec![vec![]]);
+    }
+
+    #[test]
+    fn repeats() {
+        let blocks = encode(&[b"abcabcabcabcabcX"]);
+        assert_eq!(
+            blocks,
+            vec![vec![
+                Token::Literal(b'a'),
+                Token::Literal(b'b'),
+                Token::Literal(b'c'),
+                Token::Match {
+                    length: 12,
+                    distance: 3
+                },
+                Token::Literal(b'X'),
+            ]]
+        );
+    }
+
+    #[test]
+    fn long_input() {
+        let mut state = 12345u32;
+        let mut data = Vec::new();
+        for _ in 0..300_000 {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            data.push(b"abcd"[(state >> 16) as usize % 4]);
+        }
+        let chunks: Vec<&[u8]> = data.chunks(7000).collect();
+        let blocks = encode(&chunks);
+        assert!(blocks.len() > 1);
+        assert_eq!(decode(&blocks), data);
+    }
+
+    #[test]
+    fn lazy_matching() {
+        let data: &[u8] = b"abc_bcdefg_abcdefg";
+        let tail = |level| encode_with_level(&[data], Level::new(level))[0][11..].to_vec();
+        assert_eq!(
+            tail(1),
+            [
+                Token::Match {
+                    length: 3,
+                    distance: 11
+                },
+                Token::Match {
+                    length: 4,
+                    distance: 8
+                },
+            ]
+        );
+        assert_eq!(
+            tail(6),
+            [
+                Token::Literal(b'a'),
+                Token::Match {
+                    length: 6,
+                    distance: 8
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn all_levels() {
+        let mut state = 12345u32;
+        let mut data = Vec::new();
+        for _ in 0..20_000 {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            data.push(b"abcd"[(state >> 16) as usize % 4]);
+        }
+        data.extend_from_slice(&[0; 100_000]);
+        let chunks: Vec<&[u8]> = data.chunks(7000).collect();
+        for level in 0..=10 {
+            let blocks = encode_with_level(&chunks, Level::new(level));
+            assert_eq!(decode(&blocks), data, "level {}", level);
+        }
+    }
+
+    #[test]
+    fn dictionary() {
+        let mut encoder = Lz77Encoder::new(Level::default());
+        encoder.set_dictionary(b"0123456789abcdef");
+        encoder.push(b"6789abcdX");
+        let block = encoder.next_block(Flush::Finish).unwrap();
+        assert_eq!(block.data, b"6789abcdX");
+        assert_eq!(
+            block.tokens,
+            [
+                Token::Match {
+                    length: 8,
+                    distance: 10
+                },
+                Token::Literal(b'X'),
+            ]
+        );
+    }
+
+    #[test]
+    fn sync_flush() {
+        let mut encoder = Lz77Encoder::new(Level::default());
+        encoder.push(b"abcabcabc");
+        assert!(encoder.next_block(Flush::None).is_none());
+        let block = encoder.next_block(Flush::Sync).unwrap();
+        assert!(!block.is_final);
+        assert_eq!(block.data, b"abcabcabc");
+        assert!(encoder.next_block(Flush::Sync).is_none());
+
+        encoder.push(b"abc");
+        let block = encoder.next_block(Flush::Finish).unwrap();
+        assert!(block.is_final);
+        assert_eq!(
+            block.tokens,
+            [Token::Match {
+                length: 3,
+                distance: 3
+            }]
+        );
+        assert!(encoder.next_block(Flush::Finish).is_none());
+    }
+}
@@ -1,11 +1,20 @@
 #![forbid(unsafe_code)]
 
-use std::io::{stdin, stdout};
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{self, stdin, stdout, BufRead, BufReader, BufWriter, Write};
+use std::path::{Component, Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
 
+use anyhow::{anyhow, bail, Context, Result};
 use log::*;
+use structopt::clap::{Arg, ArgMatches};
 use structopt::StructOpt;
 
-use ripgzip::{compress, decompress};
+use ripgzip::{
+    compress_parallel_with_header, decompress, decompress_lenient, decompress_parallel, Analysis,
+    GzDecoder, GzEncoder, Level, MemberHeader, Members, ZipArchive, ZipEntry, ZipMethod,
+};
 
 #[derive(StructOpt, Debug)]
 #[structopt()]
@@ -13,13 +22,497 @@ struct Opts {
     /// Decompress data
     #[structopt(short = "d", long = "decompress")]
     decompress: bool,
+    /// Write to standard output, keeping the input files
+    #[structopt(short = "c", long = "stdout")]
+    stdout: bool,
+    /// Keep the input files
+    #[structopt(short = "k", long = "keep")]
+    keep: bool,
+    /// Overwrite existing output files
+    #[structopt(short = "f", long = "force")]
+    force: bool,
+    /// Test the integrity of compressed files
+    #[structopt(short = "t", long = "test")]
+    test: bool,
+    /// List the sizes of every member of compressed files
+    #[structopt(short = "l", long = "list")]
+    list: bool,
+    /// Print the DEFLATE block structure of compressed files
+    #[structopt(long = "analyze")]
+    analyze: bool,
+    /// Print the block structure as JSON
+    #[structopt(long = "json", requires = "analyze")]
+    json: bool,
+    /// Restore the original name and modification time when decompressing
+    #[structopt(short = "N", long = "name")]
+    name: bool,
+    /// Skip damaged members when decompressing, keeping the input files
+    #[structopt(long = "recover")]
+    recover: bool,
+    /// Suffix of compressed files
+    #[structopt(short = "S", long = "suffix", default_value = ".gz")]
+    suffix: String,
+    /// Make rsync-friendly output, which only changes locally when the input does
+    #[structopt(long = "rsyncable")]
+    rsyncable: bool,
+    /// Compress, or decompress multi-member files, using this many threads
+    #[structopt(short = "p", long = "processes", default_value = "1")]
+    processes: usize,
     /// Verbose mode (-v, -vv, -vvv, etc)
     #[structopt(short = "v", long = "verbose", parse(from_occurrences))]
     verbose: usize,
+    /// List, test or extract the stored and deflated entries of a zip archive instead.
+    /// With it, -l lists, -t tests, -c extracts to standard output, -f overwrites
+    /// existing files, and the operands are the entries to process, all if none
+    #[structopt(
+        long = "unzip",
+        value_name = "archive",
+        parse(from_os_str),
+        conflicts_with_all = &[
+            "decompress", "analyze", "recover", "keep", "name", "suffix", "rsyncable",
+            "processes", "level-1", "level-2", "level-3", "level-4", "level-5", "level-6",
+            "level-7", "level-8", "level-9", "optimal",
+        ]
+    )]
+    unzip: Option<PathBuf>,
+    /// Extract the zip entries into this directory
+    #[structopt(
+        long = "exdir",
+        value_name = "dir",
+        parse(from_os_str),
+        requires = "unzip"
+    )]
+    exdir: Option<PathBuf>,
+    /// Files to process, standard input if none or `-`
+    #[structopt(parse(from_os_str))]
+    files: Vec<PathBuf>,
+}
+
+const LEVEL_ARGS: [&str; 9] = [
+    "level-1", "level-2", "level-3", "level-4", "level-5", "level-6", "level-7", "level-8",
+    "level-9",
+];
+
+/// The `-1`..`-9` and `--optimal` flags. They are not fields of `Opts`, since only their order
+/// in the argument matches tells which one applies.
+fn level_args() -> Vec<Arg<'static, 'static>> {
+    LEVEL_ARGS
+        .iter()
+        .zip(1..)
+        .map(|(name, level)| {
+            let arg = Arg::with_name(name).short(&name["level-".len()..]);
+            match level {
+                1 => arg.long("fast").help("Compress faster"),
+                9 => arg.long("best").help("Compress better"),
+                _ => arg.hidden(true),
+            }
+        })
+        .chain([Arg::with_name("optimal")
+            .long("optimal")
+            .help("Compress even better than --best with optimal parsing, many times slower")])
+        .collect()
+}
+
+/// The level given by the last of the `-1`..`-9` and `--optimal` flags, as in gzip.
+fn level_from_args(matches: &ArgMatches) -> Level {
+    LEVEL_ARGS
+        .iter()
+        .zip(1..)
+        .chain([(&"optimal", Level::optimal().level())])
+        .filter_map(|(name, level)| {
+            let index = matches.index_of(name)?;
+            Some((index, level))
+        })
+        .max()
+        .map_or_else(Level::default, |(_, level)| Level::new(level))
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+struct Gzip {
+    opts: Opts,
+    level: Level,
+}
+
+impl Gzip {
+    fn process(&self, path: &Path) -> Result<()> {
+        if path == Path::new("-") {
+            let input = stdin().lock();
+            return if self.opts.analyze {
+                self.analyze(input)
+            } else if self.opts.list {
+                self.list(input, None)
+            } else if self.opts.test {
+                test(input)
+            } else if self.opts.decompress {
+                self.decompress(input, stdout().lock())
+            } else {
+                self.compress(input, stdout().lock(), MemberHeader::default())
+            };
+        }
+
+        let metadata = fs::metadata(path)?;
+        if !metadata.is_file() {
+            bail!("not a regular file");
+        }
+        let input = BufReader::new(File::open(path)?);
+        if self.opts.analyze {
+            self.analyze(input)
+        } else if self.opts.list {
+            self.list(input, Some(path))
+        } else if self.opts.test {
+            test(input)
+        } else if self.opts.decompress {
+            self.decompress_file(path, input, &metadata)
+        } else {
+            self.compress_file(path, input, &metadata)
+        }
+    }
+
+    fn compress<R: BufRead, W: Write>(
+        &self,
+        input: R,
+        output: W,
+        header: MemberHeader,
+    ) -> Result<()> {
+        // Parallel compression ends blocks at fixed offsets rather than following the content.
+        if self.opts.processes > 1 && !self.opts.rsyncable {
+            compress_parallel_with_header(input, output, header, self.level, self.opts.processes)?;
+            return Ok(());
+        }
+        let mut input = input;
+        let mut encoder =
+            GzEncoder::with_header(output, self.level, header).with_rsyncable(self.opts.rsyncable);
+        io::copy(&mut input, &mut encoder)?;
+        encoder.finish()?.flush()?;
+        Ok(())
+    }
+
+    fn decompress<R: BufRead, W: Write>(&self, input: R, output: W) -> Result<()> {
+        if !self.opts.recover {
+            if self.opts.processes > 1 {
+                decompress_parallel(input, output, self.opts.processes)?;
+            } else {
+                decompress(input, output)?;
+            }
+            return Ok(());
+        }
+        let report = decompress_lenient(input, output)?;
+        for lost in &report.lost {
+            warn!(
+                "skipped bytes {}..{}: {}",
+                lost.input.start, lost.input.end, lost.error
+            );
+        }
+        Ok(())
+    }
+
+    fn compress_file(
+        &self,
+        path: &Path,
+        input: BufReader<File>,
+        metadata: &fs::Metadata,
+    ) -> Result<()> {
+        if path.to_string_lossy().ends_with(&self.opts.suffix) {
+            bail!("already has {} suffix -- unchanged", self.opts.suffix);
+        }
+        let modified = metadata.modified()?;
+        let header = MemberHeader {
+            name: path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned()),
+            modification_time: modified
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |time| time.as_secs() as u32),
+            ..MemberHeader::default()
+        };
+        if self.opts.stdout {
+            return self.compress(input, stdout().lock(), header);
+        }
+
+        let mut output_path = OsString::from(path);
+        output_path.push(&self.opts.suffix);
+        let output_path = PathBuf::from(output_path);
+        self.write_output(&output_path, metadata, modified, |output| {
+            self.compress(input, output, header)
+        })?;
+        self.remove_input(path)
+    }
+
+    fn decompress_file(
+        &self,
+        path: &Path,
+        input: BufReader<File>,
+        metadata: &fs::Metadata,
+    ) -> Result<()> {
+        let stem = path
+            .to_str()
+            .and_then(|path| path.strip_suffix(&self.opts.suffix))
+            .filter(|stem| !stem.is_empty() && !stem.ends_with('/'))
+            .ok_or_else(|| anyhow!("unknown suffix -- ignored"))?;
+        if self.opts.stdout {
+            return self.decompress(input, stdout().lock());
+        }
+        if self.opts.recover {
+            let output_path = Path::new(stem);
+            return self.write_output(output_path, metadata, metadata.modified()?, |output| {
+                self.decompress(input, output)
+            });
+        }
+
+        let mut decoder = GzDecoder::new(input);
+        decoder.fill_buf()?;
+        let mut output_path = PathBuf::from(stem);
+        let mut modified = metadata.modified()?;
+        if let Some(header) = decoder.header().filter(|_| self.opts.name) {
+            let name = header
+                .name
+                .as_deref()
+                .map(Path::new)
+                .and_then(Path::file_name);
+            if let Some(name) = name {
+                output_path.set_file_name(name);
+            }
+            if let Some(mtime) = header.mtime() {
+                modified = mtime;
+            }
+        }
+        self.write_output(&output_path, metadata, modified, |mut output| {
+            if self.opts.processes > 1 {
+                // The header was read by the decoder, so the file is read again from the start.
+                let input = BufReader::new(File::open(path)?);
+                return self.decompress(input, output);
+            }
+            io::copy(&mut decoder, &mut output)?;
+            output.flush()?;
+            Ok(())
+        })?;
+        self.remove_input(path)
+    }
+
+    /// Create the file at `path` and fill it with `write`, copying the permissions of the input.
+    /// The file is removed if writing fails.
+    fn write_output<F>(
+        &self,
+        path: &Path,
+        metadata: &fs::Metadata,
+        modified: SystemTime,
+        write: F,
+    ) -> Result<()>
+    where
+        F: FnOnce(&mut BufWriter<File>) -> Result<()>,
+    {
+        if path.exists() && !self.opts.force {
+            bail!("{} already exists", path.display());
+        }
+        let mut output = BufWriter::new(File::create(path)?);
+        let res = write(&mut output).and_then(|_| {
+            let file = output.into_inner().map_err(|err| err.into_error())?;
+            file.set_permissions(metadata.permissions())?;
+            file.set_modified(modified)?;
+            Ok(())
+        });
+        if res.is_err() {
+            let _ = fs::remove_file(path);
+        }
+        res.with_context(|| format!("failed to write {}", path.display()))
+    }
+
+    fn remove_input(&self, path: &Path) -> Result<()> {
+        if !self.opts.keep {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+
+    fn list<R: BufRead>(&self, input: R, path: Option<&Path>) -> Result<()> {
+        let mut stdout = stdout().lock();
+        for member in Members::new(input) {
+            let member = member?;
+            let name = match (&member.header.name, path) {
+                (Some(name), _) if self.opts.name => name.clone(),
+                (_, Some(path)) => {
+                    let path = path.to_string_lossy();
+                    path.strip_suffix(&self.opts.suffix)
+                        .unwrap_or(&path)
+                        .to_owned()
+                }
+                (_, None) => String::new(),
+            };
+            writeln!(
+                stdout,
+                "{:>19} {:>19} {:>5.1}% {}",
+                member.compressed_size,
+                member.uncompressed_size,
+                ratio(member.compressed_size, member.uncompressed_size),
+                name
+            )?;
+        }
+        Ok(())
+    }
+
+    fn analyze<R: BufRead>(&self, input: R) -> Result<()> {
+        let analysis = Analysis::new(input)?;
+        let stdout = stdout().lock();
+        if self.opts.json {
+            analysis.write_json(stdout)?;
+        } else {
+            analysis.write_text(stdout)?;
+        }
+        Ok(())
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+fn unzip(path: &Path, opts: &Opts) -> Result<()> {
+    let mut archive = ZipArchive::new(BufReader::new(File::open(path)?))?;
+    let indices = if opts.files.is_empty() {
+        (0..archive.entries().len()).collect()
+    } else {
+        opts.files
+            .iter()
+            .map(|name| {
+                let name = name.to_string_lossy();
+                archive
+                    .index_of(&name)
+                    .ok_or_else(|| anyhow!("{}: not found in archive", name))
+            })
+            .collect::<Result<Vec<_>>>()?
+    };
+
+    if opts.list {
+        list_entries(&archive, &indices)?;
+        return Ok(());
+    }
+    let mut failed = 0;
+    for index in indices {
+        let entry = archive.entries()[index].clone();
+        let res = if opts.test {
+            io::copy(&mut archive.entry_reader(index)?, &mut io::sink())
+                .map(|_| info!("{}: OK", entry.name))
+                .map_err(Into::into)
+        } else if opts.stdout {
+            io::copy(&mut archive.entry_reader(index)?, &mut stdout().lock())
+                .map(|_| ())
+                .map_err(Into::into)
+        } else {
+            extract_entry(&mut archive, index, &entry, opts)
+        };
+        if let Err(err) = res {
+            error!("{}: {:#}", entry.name, err);
+            failed += 1;
+        }
+    }
+    if failed > 0 {
+        bail!("{} entries failed", failed);
+    }
+    Ok(())
+}
+
+fn list_entries<R>(archive: &ZipArchive<R>, indices: &[usize]) -> Result<()> {
+    let mut stdout = stdout().lock();
+    writeln!(
+        stdout,
+        "{:>19} {:>19} {:>6} {:>8} {:>8} name",
+        "compressed", "uncompressed", "ratio", "method", "crc32"
+    )?;
+    for &index in indices {
+        let entry = &archive.entries()[index];
+        let method = match entry.method {
+            ZipMethod::Stored => "stored".to_owned(),
+            ZipMethod::Deflated => "deflated".to_owned(),
+            ZipMethod::Other(method) => method.to_string(),
+        };
+        writeln!(
+            stdout,
+            "{:>19} {:>19} {:>5.1}% {:>8} {:08x} {}",
+            entry.compressed_size,
+            entry.uncompressed_size,
+            ratio(entry.compressed_size, entry.uncompressed_size),
+            method,
+            entry.crc32,
+            entry.name
+        )?;
+    }
+    Ok(())
+}
+
+fn extract_entry<R: io::Read + io::Seek>(
+    archive: &mut ZipArchive<R>,
+    index: usize,
+    entry: &ZipEntry,
+    opts: &Opts,
+) -> Result<()> {
+    // Entries must not escape the output directory.
+    let relative = Path::new(&entry.name);
+    if !relative
+        .components()
+        .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+    {
+        bail!("unsafe path -- skipped");
+    }
+    let exdir = opts.exdir.as_deref().unwrap_or_else(|| Path::new("."));
+    let path = exdir.join(relative);
+    if entry.is_dir() {
+        fs::create_dir_all(&path)?;
+        return Ok(());
+    }
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    if path.exists() && !opts.force {
+        bail!("{} already exists", path.display());
+    }
+
+    let res = (|| -> Result<()> {
+        let mut output = BufWriter::new(File::create(&path)?);
+        io::copy(&mut archive.entry_reader(index)?, &mut output)?;
+        let file = output.into_inner().map_err(|err| err.into_error())?;
+        if let Some(mode) = entry.unix_mode {
+            set_mode(&file, mode)?;
+        }
+        if let Some(modified) = entry.modified {
+            file.set_modified(modified)?;
+        }
+        Ok(())
+    })();
+    if res.is_err() {
+        let _ = fs::remove_file(&path);
+    }
+    res.with_context(|| format!("failed to write {}", path.display()))
+}
+
+/// Apply the permission bits of an entry. As in Info-ZIP `unzip`, setuid, setgid and sticky bits
+/// are dropped, so that an archive cannot create setuid binaries.
+#[cfg(unix)]
+fn set_mode(file: &File, mode: u32) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    file.set_permissions(fs::Permissions::from_mode(mode & 0o777))
+}
+
+#[cfg(not(unix))]
+fn set_mode(_file: &File, _mode: u32) -> io::Result<()> {
+    Ok(())
+}
+
+fn test<R: BufRead>(input: R) -> Result<()> {
+    decompress(input, io::sink())?;
+    Ok(())
+}
+
+/// Space saved by compression in percent, as reported by gzip.
+fn ratio(compressed: u64, uncompressed: u64) -> f64 {
+    if uncompressed == 0 {
+        return 0.0;
+    }
+    (uncompressed as f64 - compressed as f64) / uncompressed as f64 * 100.0
 }
 
 fn main() {
-    let opts = Opts::from_args();
+    let matches = Opts::clap().args(&level_args()).get_matches();
+    let opts = Opts::from_clap(&matches);
+    let level = level_from_args(&matches);
 
     stderrlog::new()
         .verbosity(1 + opts.verbose)
@@ -27,14 +520,39 @@ fn main() {
         .init()
         .expect("failed to initialize logging");
 
-    let res = if opts.decompress {
-        decompress(stdin().lock(), stdout().lock())
+    if let Some(path) = &opts.unzip {
+        if let Err(err) = unzip(path, &opts) {
+            error!("{}: {:#}", path.display(), err);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let files = if opts.files.is_empty() {
+        vec![PathBuf::from("-")]
     } else {
-        compress(stdin().lock(), stdout().lock())
+        opts.files.clone()
     };
+    let gzip = Gzip { opts, level };
+    if gzip.opts.list {
+        println!(
+            "{:>19} {:>19} {:>6} uncompressed_name",
+            "compressed", "uncompressed", "ratio"
+        );
+    }
 
-    if let Err(err) = res {
-        error!("{:#}", err);
+    let mut failed = false;
+    for path in files {
+        match gzip.process(&path) {
+            Ok(()) if gzip.opts.test => info!("{}: OK", path.display()),
+            Ok(()) => {}
+            Err(err) => {
+                error!("{}: {:#}", path.display(), err);
+                failed = true;
+            }
+        }
+    }
+    if failed {
         std::process::exit(1);
     }
 }
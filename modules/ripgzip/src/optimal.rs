@@ -0,0 +1,256 @@
+use crate::huffman_coding::{
+    distance_code, length_code, DynamicTrees, DISTANCE_CODES, END_OF_BLOCK, FIXED_DISTANCE_LENGTHS,
+    FIXED_LITLEN_LENGTHS, LITLEN_CODES,
+};
+use crate::lz77::{Token, MAX_MATCH, MIN_MATCH};
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Maximum number of rounds of parsing with the statistics of the previous round.
+const ITERATIONS: usize = 15;
+
+/// The matches available at every position of a block.
+///
+/// The candidates of a position are sorted by length, a candidate being the closest
+/// match for all the lengths from the one of the previous candidate up to its own.
+pub struct Matches {
+    offsets: Vec<usize>,
+    candidates: Vec<(u16, u16)>,
+}
+
+impl Matches {
+    pub fn with_capacity(positions: usize) -> Self {
+        Self {
+            offsets: Vec::with_capacity(positions),
+            candidates: Vec::with_capacity(positions),
+        }
+    }
+
+    /// Start collecting the candidates of the next position.
+    pub fn next_position(&mut self) {
+        self.offsets.push(self.candidates.len());
+    }
+
+    pub fn push(&mut self, length: usize, distance: usize) {
+        self.candidates.push((length as u16, distance as u16));
+    }
+
+    fn at(&self, index: usize) -> &[(u16, u16)] {
+        let end = self
+            .offsets
+            .get(index + 1)
+            .copied()
+            .unwrap_or(self.candidates.len());
+        &self.candidates[self.offsets[index]..end]
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Estimated size in bits of every token, including the extra bits.
+struct CostModel {
+    literals: Vec<f64>,
+    /// Indexed by the match length.
+    lengths: Vec<f64>,
+    distance_symbols: Vec<f64>,
+}
+
+impl CostModel {
+    fn new(litlen_bits: &[f64], distance_bits: &[f64]) -> Self {
+        let lengths = (0..=MAX_MATCH)
+            .map(|length| {
+                if length < MIN_MATCH {
+                    return f64::INFINITY;
+                }
+                let (symbol, extra) = length_code(length as u16);
+                litlen_bits[symbol] + extra.len() as f64
+            })
+            .collect();
+        Self {
+            literals: litlen_bits[..256].to_vec(),
+            lengths,
+            distance_symbols: distance_bits.to_vec(),
+        }
+    }
+
+    /// The costs of the fixed Huffman codes.
+    fn fixed() -> Self {
+        let bits = |lengths: &[u8]| lengths.iter().map(|&len| len as f64).collect::<Vec<_>>();
+        Self::new(&bits(&FIXED_LITLEN_LENGTHS), &bits(&FIXED_DISTANCE_LENGTHS))
+    }
+
+    /// The costs of entropy coding the symbols of `tokens`.
+    fn from_tokens(tokens: &[Token]) -> Self {
+        let (litlen_frequencies, distance_frequencies) = frequencies(tokens);
+        Self::new(
+            &entropy(&litlen_frequencies),
+            &entropy(&distance_frequencies),
+        )
+    }
+
+    fn distance(&self, distance: u16) -> f64 {
+        let (symbol, extra) = distance_code(distance);
+        self.distance_symbols[symbol] + extra.len() as f64
+    }
+}
+
+/// Size in bits of a symbol coded with the ideal code for the given frequencies.
+/// Unused symbols are assumed to be as rare as the rarest possible one.
+fn entropy(frequencies: &[u32]) -> Vec<f64> {
+    let total: u32 = frequencies.iter().sum();
+    let log_total = if total == 0 {
+        (frequencies.len() as f64).log2()
+    } else {
+        (total as f64).log2()
+    };
+    frequencies
+        .iter()
+        .map(|&frequency| match frequency {
+            0 => log_total,
+            _ => log_total - (frequency as f64).log2(),
+        })
+        .collect()
+}
+
+fn frequencies(tokens: &[Token]) -> ([u32; LITLEN_CODES], [u32; DISTANCE_CODES]) {
+    let mut litlen = [0u32; LITLEN_CODES];
+    let mut distance = [0u32; DISTANCE_CODES];
+    for token in tokens {
+        match *token {
+            Token::Literal(byte) => litlen[byte as usize] += 1,
+            Token::Match {
+                length,
+                distance: match_distance,
+            } => {
+                litlen[length_code(length).0] += 1;
+                distance[distance_code(match_distance).0] += 1;
+            }
+        }
+    }
+    litlen[END_OF_BLOCK] += 1;
+    (litlen, distance)
+}
+
+/// Exact size in bits of `tokens` written as a dynamic block.
+fn block_size(tokens: &[Token]) -> u64 {
+    let (litlen_frequencies, distance_frequencies) = frequencies(tokens);
+    let trees = DynamicTrees::new(&litlen_frequencies, &distance_frequencies);
+    let symbols = |frequencies: &[u32], lengths: &[u8]| -> u64 {
+        frequencies
+            .iter()
+            .zip(lengths)
+            .map(|(&frequency, &len)| frequency as u64 * len as u64)
+            .sum()
+    };
+    let extra_bits: u64 = tokens
+        .iter()
+        .map(|token| match *token {
+            Token::Literal(_) => 0,
+            Token::Match { length, distance } => {
+                (length_code(length).1.len() + distance_code(distance).1.len()) as u64
+            }
+        })
+        .sum();
+    trees.cost()
+        + symbols(&litlen_frequencies, &trees.litlen_lengths)
+        + symbols(&distance_frequencies, &trees.distance_lengths)
+        + extra_bits
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Find the sequence of tokens encoding `data` with the smallest estimated size.
+///
+/// The costs of the first round are those of the fixed codes. Each next round uses
+/// the symbol statistics of the previous one, until the size of the block stops changing.
+pub fn parse(data: &[u8], matches: &Matches) -> Vec<Token> {
+    let mut model = CostModel::fixed();
+    let mut best: Option<(u64, Vec<Token>)> = None;
+    let mut last_size = None;
+    for _ in 0..ITERATIONS {
+        let tokens = shortest_path(data, matches, &model);
+        let size = block_size(&tokens);
+        if last_size == Some(size) {
+            break;
+        }
+        last_size = Some(size);
+        model = CostModel::from_tokens(&tokens);
+        if best.as_ref().is_none_or(|(best_size, _)| size < *best_size) {
+            best = Some((size, tokens));
+        }
+    }
+    best.map(|(_, tokens)| tokens).unwrap_or_default()
+}
+
+/// Find the cheapest tokens under the given model, by dynamic programming over the positions.
+fn shortest_path(data: &[u8], matches: &Matches, model: &CostModel) -> Vec<Token> {
+    let mut costs = vec![f64::INFINITY; data.len() + 1];
+    // The length and distance of the token ending at every position, distance 0 for literals.
+    let mut steps = vec![(0u16, 0u16); data.len() + 1];
+    costs[0] = 0.0;
+    for (i, &byte) in data.iter().enumerate() {
+        let cost = costs[i];
+        let literal = cost + model.literals[byte as usize];
+        if literal < costs[i + 1] {
+            costs[i + 1] = literal;
+            steps[i + 1] = (1, 0);
+        }
+        let mut min_length = MIN_MATCH;
+        for &(max_length, distance) in matches.at(i) {
+            let base = cost + model.distance(distance);
+            for length in min_length..=max_length as usize {
+                let total = base + model.lengths[length];
+                if total < costs[i + length] {
+                    costs[i + length] = total;
+                    steps[i + length] = (length as u16, distance);
+                }
+            }
+            min_length = max_length as usize + 1;
+        }
+    }
+
+    let mut tokens = Vec::new();
+    let mut end = data.len();
+    while end > 0 {
+        let (length, distance) = steps[end];
+        if distance == 0 {
+            tokens.push(Token::Literal(data[end - 1]));
+        } else {
+            tokens.push(Token::Match { length, distance });
+        }
+        end -= length as usize;
+    }
+    tokens.reverse();
+    tokens
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prefers_cheap_tokens() {
+        // Every position after the first repeats the previous byte.
+        let data = [b'a'; 20];
+        let mut matches = Matches::with_capacity(data.len());
+        for i in 0..data.len() {
+            matches.next_position();
+            if i > 0 {
+                matches.push(data.len() - i, 1);
+            }
+        }
+        let tokens = parse(&data, &matches);
+        assert_eq!(
+            tokens,
+            [
+                Token::Literal(b'a'),
+                Token::Match {
+                    length: 19,
+                    distance: 1
+                }
+            ]
+        );
+    }
+}
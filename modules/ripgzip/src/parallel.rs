@@ -0,0 +1,399 @@
+use std::collections::BTreeMap;
+use std::io::{self, BufReader, Cursor, Read, Write};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Mutex;
+use std::thread;
+
+use crate::bit_writer::BitWriter;
+use crate::crc32::{self, crc32_combine};
+use crate::decoder::Decoder;
+use crate::deflate::DeflateWriter;
+use crate::encoder::member_header;
+use crate::error::{Error, Result};
+use crate::gzip::{GzipWriter, MemberFooter, MemberHeader, MemberWriter, CM_DEFLATE, ID1, ID2};
+use crate::lz77::{Flush, Level, Lz77Encoder, WINDOW_SIZE};
+use crate::Format;
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Amount of input compressed by a single job.
+const CHUNK_SIZE: usize = 128 << 10;
+
+/// Number of jobs per thread that may be queued or awaiting output at any moment.
+const JOBS_PER_THREAD: usize = 2;
+
+struct Job {
+    index: usize,
+    dictionary: Vec<u8>,
+    data: Vec<u8>,
+}
+
+struct Compressed {
+    index: usize,
+    deflated: io::Result<Vec<u8>>,
+    crc32: u32,
+    len: usize,
+}
+
+/// Compressed input is cut into ranges at the first member boundary candidate
+/// after this many bytes.
+const RANGE_SIZE: usize = 1 << 20;
+
+/// Amount of input without a boundary candidate after which the rest of the stream
+/// is decompressed serially.
+const MAX_RANGE_SIZE: usize = 16 << 20;
+
+const READ_SIZE: u64 = 256 << 10;
+
+/// A part of the compressed input supposed to consist of whole members.
+struct Range {
+    index: usize,
+    data: Vec<u8>,
+}
+
+struct Inflated {
+    index: usize,
+    data: Vec<u8>,
+    /// The decompressed data along with the number of members.
+    output: Result<(Vec<u8>, usize)>,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Compress `input` into a single gzip member using `processes` threads, like pigz.
+///
+/// The input is split into chunks compressed independently, each one primed with
+/// the last 32 KiB of the previous chunk, and ending with an empty stored block
+/// so that the compressed chunks can be concatenated at byte boundaries.
+pub fn compress_parallel<R: Read, W: Write>(
+    input: R,
+    output: W,
+    level: Level,
+    processes: usize,
+) -> Result<()> {
+    compress_parallel_with_header(input, output, MemberHeader::default(), level, processes)
+}
+
+/// Like `compress_parallel`, but writing the given header. Its extra flags are derived from the level.
+pub fn compress_parallel_with_header<R: Read, W: Write>(
+    mut input: R,
+    mut output: W,
+    header: MemberHeader,
+    level: Level,
+    processes: usize,
+) -> Result<()> {
+    let processes = processes.max(1);
+    GzipWriter::new(&mut output).write_header(&member_header(header, level))?;
+
+    let (job_sender, job_receiver) = mpsc::channel::<Job>();
+    let job_receiver = Mutex::new(job_receiver);
+    let (data_crc32, size) = thread::scope(|scope| -> Result<(u32, u64)> {
+        // Dropping the sender on return stops the workers.
+        let job_sender = job_sender;
+        let (result_sender, result_receiver) = mpsc::channel();
+        for _ in 0..processes {
+            let job_receiver = &job_receiver;
+            let result_sender = result_sender.clone();
+            scope.spawn(move || run_worker(job_receiver, level, &result_sender));
+        }
+        drop(result_sender);
+
+        let mut next_job = 0;
+        let mut next_output = 0;
+        let mut pending = BTreeMap::new();
+        let mut dictionary = Vec::new();
+        let mut is_eof = false;
+        let mut data_crc32 = 0;
+        let mut size = 0u64;
+        loop {
+            while !is_eof && next_job - next_output < processes * JOBS_PER_THREAD {
+                let mut data = Vec::with_capacity(CHUNK_SIZE);
+                input
+                    .by_ref()
+                    .take(CHUNK_SIZE as u64)
+                    .read_to_end(&mut data)?;
+                if data.is_empty() {
+                    is_eof = true;
+                    break;
+                }
+                let next_dictionary = data[data.len().saturating_sub(WINDOW_SIZE)..].to_vec();
+                let job = Job {
+                    index: next_job,
+                    dictionary: std::mem::replace(&mut dictionary, next_dictionary),
+                    data,
+                };
+                job_sender.send(job).map_err(|_| thread_terminated())?;
+                next_job += 1;
+            }
+            if next_output == next_job {
+                break;
+            }
+
+            let result = result_receiver.recv().map_err(|_| thread_terminated())?;
+            pending.insert(result.index, result);
+            while let Some(result) = pending.remove(&next_output) {
+                output.write_all(&result.deflated?)?;
+                data_crc32 = crc32_combine(data_crc32, result.crc32, result.len as u64);
+                size += result.len as u64;
+                next_output += 1;
+            }
+        }
+        Ok((data_crc32, size))
+    })?;
+
+    let mut writer = DeflateWriter::new(BitWriter::new(&mut output));
+    writer.write_block(&[], &[], true)?;
+    writer.finish()?;
+    let footer = MemberFooter {
+        data_crc32,
+        data_size: size as u32,
+    };
+    MemberWriter::new(&mut output).write_footer(&footer)?;
+    output.flush()?;
+    Ok(())
+}
+
+fn thread_terminated() -> io::Error {
+    io::Error::other("compression thread terminated")
+}
+
+fn run_worker(jobs: &Mutex<Receiver<Job>>, level: Level, results: &mpsc::Sender<Compressed>) {
+    loop {
+        let job = match jobs.lock().unwrap().recv() {
+            Ok(job) => job,
+            Err(_) => return,
+        };
+        let result = Compressed {
+            index: job.index,
+            deflated: compress_chunk(level, &job.dictionary, &job.data),
+            crc32: crc32::checksum(&job.data),
+            len: job.data.len(),
+        };
+        if results.send(result).is_err() {
+            return;
+        }
+    }
+}
+
+/// Compress `data` into a sequence of non-final blocks ending at a byte boundary.
+fn compress_chunk(level: Level, dictionary: &[u8], data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut encoder = Lz77Encoder::new(level);
+    encoder.set_dictionary(dictionary);
+    encoder.push(data);
+    let mut writer = DeflateWriter::new(BitWriter::new(Vec::new()));
+    while let Some(block) = encoder.next_block(Flush::Sync) {
+        if level == Level::none() {
+            writer.write_stored(block.data, false)?;
+        } else {
+            writer.write_block(block.tokens, block.data, false)?;
+        }
+    }
+    writer.write_stored(&[], false)?;
+    writer.finish()?;
+    Ok(writer.into_inner())
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Decompress a multi-member gzip stream, such as a concatenation or a BGZF file,
+/// inflating its members on `processes` threads.
+///
+/// The input is cut into ranges of about 1 MiB, each one starting at gzip magic bytes,
+/// which are decompressed and verified independently. The magic bytes may also occur
+/// inside compressed data, in which case the range ending in the middle of a member is
+/// joined with the next one and decompressed again. Once no magic bytes are found in
+/// 16 MiB of input, the rest of the stream is decompressed serially.
+pub fn decompress_parallel<R: Read, W: Write>(
+    input: R,
+    mut output: W,
+    processes: usize,
+) -> Result<()> {
+    let processes = processes.max(1);
+    let (job_sender, job_receiver) = mpsc::channel::<Range>();
+    let job_receiver = Mutex::new(job_receiver);
+    thread::scope(|scope| -> Result<()> {
+        let (result_sender, result_receiver) = mpsc::channel();
+        for _ in 0..processes {
+            let job_receiver = &job_receiver;
+            let result_sender = result_sender.clone();
+            scope.spawn(move || run_decompression_worker(job_receiver, &result_sender));
+        }
+        drop(result_sender);
+
+        let mut scanner = Scanner::new(input);
+        let mut next_job = 0;
+        let mut next_output = 0;
+        let mut pending = BTreeMap::new();
+        // A range ending in the middle of a member, along with the resulting error.
+        let mut carry: Option<(Vec<u8>, Error)> = None;
+        let mut is_scan_over = false;
+        // Position of the next range to be written.
+        let mut offset = 0u64;
+        let mut member_count = 0;
+        loop {
+            while !is_scan_over && next_job - next_output < processes * JOBS_PER_THREAD {
+                let Some(mut data) = scanner.next_range()? else {
+                    is_scan_over = true;
+                    break;
+                };
+                if next_output == next_job {
+                    if let Some((carried, _)) = carry.take() {
+                        data = [carried, data].concat();
+                    }
+                }
+                let job = Range {
+                    index: next_job,
+                    data,
+                };
+                job_sender.send(job).map_err(|_| thread_terminated())?;
+                next_job += 1;
+            }
+            if next_output == next_job {
+                break;
+            }
+
+            let result = result_receiver.recv().map_err(|_| thread_terminated())?;
+            pending.insert(result.index, result);
+            while let Some(result) = pending.remove(&next_output) {
+                if let Some((carried, _)) = carry.take() {
+                    // The range started in the middle of a member, so it is decompressed
+                    // again along with the preceding one.
+                    let job = Range {
+                        index: next_output,
+                        data: [carried, result.data].concat(),
+                    };
+                    job_sender.send(job).map_err(|_| thread_terminated())?;
+                    break;
+                }
+                match result.output {
+                    Ok((data, count)) => {
+                        output.write_all(&data)?;
+                        offset += result.data.len() as u64;
+                        member_count += count;
+                    }
+                    Err(err @ Error::TruncatedInput { .. }) => carry = Some((result.data, err)),
+                    Err(err) => return Err(relocate(err, member_count, offset)),
+                }
+                next_output += 1;
+            }
+        }
+
+        drop(job_sender);
+        if scanner.is_over() {
+            if let Some((_, err)) = carry {
+                return Err(relocate(err, member_count, offset));
+            }
+        } else {
+            let mut prefix = carry.map_or_else(Vec::new, |(carried, _)| carried);
+            prefix.append(&mut scanner.buffer);
+            let input = BufReader::new(Cursor::new(prefix).chain(scanner.input));
+            let mut decoder = Decoder::new(input, Format::Gzip);
+            loop {
+                let data = decoder
+                    .decode()
+                    .map_err(|err| relocate(err, member_count, offset))?;
+                if data.is_empty() {
+                    break;
+                }
+                output.write_all(data)?;
+                let len = data.len();
+                decoder.consume(len);
+            }
+        }
+        Ok(())
+    })?;
+    output.flush()?;
+    Ok(())
+}
+
+/// Splits the input into ranges at gzip magic bytes.
+struct Scanner<R> {
+    input: R,
+    buffer: Vec<u8>,
+    is_eof: bool,
+}
+
+impl<R: Read> Scanner<R> {
+    fn new(input: R) -> Self {
+        Self {
+            input,
+            buffer: Vec::new(),
+            is_eof: false,
+        }
+    }
+
+    /// Whether all of the input was returned as ranges.
+    fn is_over(&self) -> bool {
+        self.is_eof && self.buffer.is_empty()
+    }
+
+    /// The next range, or `None` if the input is over or no magic bytes were found
+    /// within `MAX_RANGE_SIZE`, in which case the input is left in `buffer`.
+    fn next_range(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let mut search_from = RANGE_SIZE;
+        loop {
+            if let Some(pos) = find_member_start(&self.buffer, search_from) {
+                let rest = self.buffer.split_off(pos);
+                return Ok(Some(std::mem::replace(&mut self.buffer, rest)));
+            }
+            if self.is_eof {
+                return Ok(Some(std::mem::take(&mut self.buffer)).filter(|data| !data.is_empty()));
+            }
+            if self.buffer.len() >= MAX_RANGE_SIZE {
+                return Ok(None);
+            }
+            // Magic bytes may be split between reads.
+            search_from = search_from.max(self.buffer.len().saturating_sub(3));
+            let len = self
+                .input
+                .by_ref()
+                .take(READ_SIZE)
+                .read_to_end(&mut self.buffer)?;
+            self.is_eof = len == 0;
+        }
+    }
+}
+
+/// Position of the first member header candidate at or after `from`: the magic bytes,
+/// the DEFLATE method and flags with the reserved bits unset.
+fn find_member_start(data: &[u8], from: usize) -> Option<usize> {
+    data.get(from..)?
+        .windows(4)
+        .position(|header| header[..3] == [ID1, ID2, CM_DEFLATE] && header[3] & 0xe0 == 0)
+        .map(|pos| from + pos)
+}
+
+/// Make the location of an error raised in a range starting at `offset`, preceded
+/// by `member_count` members, relative to the whole stream.
+fn relocate(err: Error, member_count: usize, offset: u64) -> Error {
+    let member = member_count + err.location().map_or(0, |location| location.member);
+    err.shift(member, offset * 8)
+}
+
+fn run_decompression_worker(jobs: &Mutex<Receiver<Range>>, results: &Sender<Inflated>) {
+    loop {
+        let job = match jobs.lock().unwrap().recv() {
+            Ok(job) => job,
+            Err(_) => return,
+        };
+        let result = Inflated {
+            index: job.index,
+            output: inflate_range(&job.data),
+            data: job.data,
+        };
+        if results.send(result).is_err() {
+            return;
+        }
+    }
+}
+
+/// Decompress and verify the members of `data`, returning their output and number.
+fn inflate_range(data: &[u8]) -> Result<(Vec<u8>, usize)> {
+    let mut decoder = Decoder::new(data, Format::Gzip);
+    let mut output = Vec::new();
+    while !decoder.is_done() {
+        decoder.step()?;
+        decoder.drain_output(&mut output);
+    }
+    Ok((output, decoder.member_count()))
+}
@@ -0,0 +1,256 @@
+use std::cmp::min;
+use std::io::{self, BufRead, Read, Write};
+use std::ops::Range;
+
+use crate::decoder::Decoder;
+use crate::error::{Error, Result};
+use crate::gzip::{GzipReader, CM_DEFLATE, ID1, ID2};
+use crate::Format;
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// A member decoded and verified successfully.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RecoveredRange {
+    /// Byte range of the member in the input.
+    pub input: Range<u64>,
+    /// Byte range of its data in the output.
+    pub output: Range<u64>,
+}
+
+/// Damaged input skipped up to the next member header that parses cleanly.
+#[derive(Debug)]
+pub struct LostRange {
+    /// Byte range in the input, starting at the failed member.
+    pub input: Range<u64>,
+    /// The error that stopped the decoding of the failed member.
+    pub error: Error,
+}
+
+/// The outcome of `decompress_lenient`, both lists being sorted by input offset.
+#[derive(Debug, Default)]
+pub struct RecoveryReport {
+    pub recovered: Vec<RecoveredRange>,
+    pub lost: Vec<LostRange>,
+}
+
+impl RecoveryReport {
+    /// Whether the whole input was decoded without errors.
+    pub fn is_complete(&self) -> bool {
+        self.lost.is_empty()
+    }
+
+    /// Total size of the skipped input.
+    pub fn lost_bytes(&self) -> u64 {
+        self.lost
+            .iter()
+            .map(|lost| lost.input.end - lost.input.start)
+            .sum()
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Decompress a gzip stream, skipping damaged members instead of failing.
+///
+/// When a member fails to decode, its error is recorded and decoding resumes at the next
+/// `1f 8b 08` sequence starting a valid member header. Only the data of members passing
+/// all the checks is written, so every member is buffered in memory until its footer is read,
+/// along with its compressed bytes to scan them again if it turns out to be damaged.
+/// Memory use is thus bounded by the largest member rather than by the whole input.
+/// Errors are only returned for failures to read the input or write the output.
+pub fn decompress_lenient<R: Read, W: Write>(input: R, mut output: W) -> Result<RecoveryReport> {
+    let mut window = Window::new(input);
+    let mut report = RecoveryReport::default();
+    let mut member = Vec::new();
+    let mut output_size = 0;
+    while window.has_data_at(0)? {
+        member.clear();
+        let start = window.offset;
+        let res = decode_member(&mut window, &mut member);
+        window.take_error()?;
+        let err = match res {
+            Ok(len) => {
+                output.write_all(&member)?;
+                report.recovered.push(RecoveredRange {
+                    input: start..start + len as u64,
+                    output: output_size..output_size + member.len() as u64,
+                });
+                output_size += member.len() as u64;
+                window.discard(len);
+                continue;
+            }
+            Err(err) => err,
+        };
+
+        skip_to_next_member(&mut window)?;
+        let end = window.offset;
+        match report.lost.last_mut() {
+            // The header found while skipping the previous damage was a false positive.
+            Some(lost) if lost.input.end == start => lost.input.end = end,
+            _ => {
+                let index = report.recovered.len() + report.lost.len();
+                let error = err.shift(index, start * 8);
+                report.lost.push(LostRange {
+                    input: start..end,
+                    error,
+                });
+            }
+        }
+    }
+    output.flush()?;
+    Ok(report)
+}
+
+/// Decode the gzip member at the start of `input`, returning its compressed size.
+fn decode_member<R: BufRead>(input: R, output: &mut Vec<u8>) -> Result<usize> {
+    let mut decoder = Decoder::new(input, Format::Gzip);
+    decoder.step()?;
+    while !decoder.is_at_member_boundary() {
+        decoder.step()?;
+        decoder.drain_output(output);
+    }
+    Ok((decoder.input_position() / 8) as usize)
+}
+
+/// Drop the input up to the first member header after the start of the window that
+/// parses cleanly, or all of it if there is none.
+fn skip_to_next_member<R: Read>(window: &mut Window<R>) -> io::Result<()> {
+    const MAGIC: [u8; 3] = [ID1, ID2, CM_DEFLATE];
+
+    let mut from = 1;
+    loop {
+        if !window.has_data_at(from + MAGIC.len() - 1)? {
+            window.discard(window.len);
+            return Ok(());
+        }
+        match window.buffer[from..window.len]
+            .windows(MAGIC.len())
+            .position(|bytes| bytes == MAGIC)
+        {
+            Some(pos) => {
+                window.discard(from + pos);
+                if window.starts_with_member()? {
+                    return Ok(());
+                }
+                from = 1;
+            }
+            None => {
+                // The last bytes may start a header continued in the input yet to be read.
+                window.discard(window.len + 1 - MAGIC.len());
+                from = 0;
+            }
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+const READ_SIZE: usize = 65536;
+
+/// The input from the start of the member being decoded on, kept so that it can be
+/// scanned again for the next member header if the member turns out to be damaged.
+struct Window<R> {
+    input: R,
+    /// The input read, followed by the space for reading more.
+    buffer: Vec<u8>,
+    /// Length of the input in the buffer.
+    len: usize,
+    /// Offset of the start of the buffer in the input.
+    offset: u64,
+    /// Position of the reader in the buffer.
+    pos: usize,
+    /// The error that interrupted reading, which the decoder would report as damage.
+    error: Option<io::Error>,
+}
+
+impl<R: Read> Window<R> {
+    fn new(input: R) -> Self {
+        Self {
+            input,
+            buffer: Vec::new(),
+            len: 0,
+            offset: 0,
+            pos: 0,
+            error: None,
+        }
+    }
+
+    /// Append more input to the buffer, returning whether there was any left.
+    fn read_more(&mut self) -> io::Result<bool> {
+        if self.buffer.len() - self.len < READ_SIZE {
+            self.buffer.resize(self.len + READ_SIZE, 0);
+        }
+        let read = loop {
+            match self.input.read(&mut self.buffer[self.len..]) {
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                res => break res?,
+            }
+        };
+        self.len += read;
+        Ok(read > 0)
+    }
+
+    /// Whether the input has a byte at `pos` from the start of the buffer.
+    fn has_data_at(&mut self, pos: usize) -> io::Result<bool> {
+        while self.len <= pos {
+            if !self.read_more()? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Drop the first `len` bytes of the buffer and rewind the reader to the new start.
+    fn discard(&mut self, len: usize) {
+        self.buffer.copy_within(len..self.len, 0);
+        self.len -= len;
+        self.offset += len as u64;
+        self.pos = 0;
+    }
+
+    /// Whether a member header that parses cleanly starts the buffer.
+    fn starts_with_member(&mut self) -> io::Result<bool> {
+        self.pos = 0;
+        let res = GzipReader::new(&mut *self)
+            .next_member()
+            .is_some_and(|member| member.is_ok());
+        self.pos = 0;
+        self.take_error()?;
+        Ok(res)
+    }
+
+    fn take_error(&mut self) -> io::Result<()> {
+        match self.error.take() {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<R: Read> Read for Window<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let data = self.fill_buf()?;
+        let len = min(data.len(), buf.len());
+        buf[..len].copy_from_slice(&data[..len]);
+        self.consume(len);
+        Ok(len)
+    }
+}
+
+impl<R: Read> BufRead for Window<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.pos == self.len {
+            if let Err(err) = self.read_more() {
+                let kind = err.kind();
+                self.error = Some(err);
+                return Err(kind.into());
+            }
+        }
+        Ok(&self.buffer[self.pos..self.len])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos += amt;
+    }
+}
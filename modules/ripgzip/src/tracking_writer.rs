@@ -2,33 +2,81 @@ use std::cmp::min;
 use std::collections::VecDeque;
 use std::io::{self, Write};
 
-use anyhow::{bail, Result};
-
-use crc::crc32::Digest;
-use crc::{crc32, Hasher32};
+use crate::adler32::Adler32;
+use crate::crc32::Crc32;
+use crate::error::{Error, Location, Result};
 
 ////////////////////////////////////////////////////////////////////////////////
 
 const HISTORY_SIZE: usize = 32768;
 
+/// Longest sequence `write_previous` copies at once, the maximum DEFLATE match length.
+const MAX_COPY_LEN: usize = 258;
+
+/// The checksum kept of the data written, as required by the container format.
+#[derive(Clone, Copy, Debug)]
+pub enum Checksum {
+    Crc32(Crc32),
+    Adler32(Adler32),
+    None,
+}
+
+impl Checksum {
+    pub fn crc32() -> Self {
+        Self::Crc32(Crc32::new())
+    }
+
+    pub fn adler32() -> Self {
+        Self::Adler32(Adler32::new())
+    }
+
+    fn write(&mut self, data: &[u8]) {
+        match self {
+            Self::Crc32(digest) => digest.write(data),
+            Self::Adler32(adler) => adler.write(data),
+            Self::None => {}
+        }
+    }
+
+    fn reset(&mut self) {
+        *self = match self {
+            Self::Crc32(_) => Self::crc32(),
+            Self::Adler32(_) => Self::adler32(),
+            Self::None => Self::None,
+        };
+    }
+
+    fn sum32(&self) -> u32 {
+        match self {
+            Self::Crc32(digest) => digest.sum32(),
+            Self::Adler32(adler) => adler.sum32(),
+            Self::None => 0,
+        }
+    }
+}
+
 pub struct TrackingWriter<T> {
     inner: T,
     len: usize,
     history: VecDeque<u8>,
-    digest: Digest,
+    checksum: Checksum,
+    /// Number of bytes at the end of the history not passed to the checksum yet.
+    /// Decoders write a few bytes at a time, so the checksum is updated in larger batches.
+    unchecked: usize,
 }
 
 impl<T: Write> Write for TrackingWriter<T> {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
         let res = self.inner.write(buf)?;
         let slice_to_write = &buf[0..res];
-        for e in slice_to_write {
-            if self.history.len() == HISTORY_SIZE {
-                self.history.pop_front();
-            }
-            self.history.push_back(*e);
+        if self.unchecked + slice_to_write.len() > HISTORY_SIZE {
+            // The unchecked bytes are about to leave the history.
+            self.update_checksum();
+            let overflow = slice_to_write.len().saturating_sub(HISTORY_SIZE);
+            self.checksum.write(&slice_to_write[..overflow]);
         }
-        <Digest as Hasher32>::write(&mut self.digest, slice_to_write);
+        self.unchecked += min(slice_to_write.len(), HISTORY_SIZE);
+        self.extend_history(slice_to_write);
         self.len += res;
         Ok(res)
     }
@@ -36,43 +84,92 @@ impl<T: Write> Write for TrackingWriter<T> {
     fn flush(&mut self) -> io::Result<()> {
         self.len = 0;
         self.history = VecDeque::with_capacity(HISTORY_SIZE);
-        self.digest = Digest::new(crc32::IEEE);
+        self.checksum.reset();
+        self.unchecked = 0;
         self.inner.flush()
     }
 }
 
 impl<T: Write> TrackingWriter<T> {
-    pub fn new(inner: T) -> Self {
+    pub fn new(inner: T, checksum: Checksum) -> Self {
         Self {
             inner,
             len: 0,
             history: VecDeque::with_capacity(HISTORY_SIZE),
-            digest: Digest::new(crc32::IEEE),
+            checksum,
+            unchecked: 0,
         }
     }
 
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    /// Pretend that `data` was written before, so that it can be referenced
+    /// by `write_previous`, without passing it to the inner writer or the checksum.
+    pub fn seed_history(&mut self, data: &[u8]) {
+        self.update_checksum();
+        self.extend_history(data);
+    }
+
+    fn extend_history(&mut self, data: &[u8]) {
+        let tail = &data[data.len().saturating_sub(HISTORY_SIZE)..];
+        let overflow = (self.history.len() + tail.len()).saturating_sub(HISTORY_SIZE);
+        if overflow > 0 {
+            self.history.drain(..overflow);
+        }
+        self.history.extend(tail);
+    }
+
+    /// Pass the unchecked end of the history to the checksum.
+    fn update_checksum(&mut self) {
+        let start = self.history.len() - self.unchecked;
+        let (front, back) = self.history.as_slices();
+        if start < front.len() {
+            self.checksum.write(&front[start..]);
+            self.checksum.write(back);
+        } else {
+            self.checksum.write(&back[start - front.len()..]);
+        }
+        self.unchecked = 0;
+    }
+
+    /// The last (up to 32 KiB) bytes written.
+    pub fn history(&self) -> Vec<u8> {
+        self.history.iter().copied().collect()
+    }
+
     /// Write a sequence of `len` bytes written `dist` bytes ago.
     pub fn write_previous(&mut self, dist: usize, len: usize) -> Result<()> {
         if dist > self.history.len() {
-            bail!("History size is less than offset")
+            return Err(Error::DistanceTooFarBack {
+                distance: dist,
+                available: self.history.len(),
+                location: Location::default(),
+            });
         }
-        let start = self.history.len() - dist;
-        self.history.make_contiguous();
-        let (slice, _) = self.history.as_slices();
-        let mut repeating_vec = Vec::with_capacity(len);
-        let right_border = min(slice.len(), start + len) - 1;
-        let mut index = start;
-        while repeating_vec.len() < len {
-            repeating_vec.push(slice[index]);
-            if index == right_border {
-                index = start;
-            } else {
-                index += 1;
+        let mut buf = [0u8; MAX_COPY_LEN];
+        let mut remaining = len;
+        while remaining > 0 {
+            let chunk_len = min(remaining, MAX_COPY_LEN);
+            let start = self.history.len() - dist;
+            let copied = min(chunk_len, dist);
+            for (dst, src) in buf[..copied].iter_mut().zip(self.history.range(start..)) {
+                *dst = *src;
             }
-        }
-        let write = self.write(&repeating_vec)?;
-        if write < len {
-            bail!("Not all bytes were written")
+            // A match closer than its length repeats the bytes it has just copied.
+            for i in copied..chunk_len {
+                buf[i] = buf[i - dist];
+            }
+            let write = self.write(&buf[..chunk_len])?;
+            if write < chunk_len {
+                return Err(io::Error::from(io::ErrorKind::WriteZero).into());
+            }
+            remaining -= chunk_len;
         }
         Ok(())
     }
@@ -81,8 +178,10 @@ impl<T: Write> TrackingWriter<T> {
         self.len
     }
 
-    pub fn crc32(&self) -> u32 {
-        self.digest.sum32()
+    /// The checksum of the data written since the last flush, 0 if none is kept.
+    pub fn checksum(&mut self) -> u32 {
+        self.update_checksum();
+        self.checksum.sum32()
     }
 }
 
@@ -91,27 +190,33 @@ impl<T: Write> TrackingWriter<T> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{adler32, crc32};
+    use byteorder::WriteBytesExt;
 
     #[test]
     fn write() -> Result<()> {
         let mut buf: &mut [u8] = &mut [0u8; 10];
-        let mut writer = TrackingWriter::new(&mut buf);
+        let mut writer = TrackingWriter::new(&mut buf, Checksum::crc32());
 
         assert_eq!(writer.write(&[1, 2, 3, 4])?, 4);
         assert_eq!(writer.byte_count(), 4);
-        assert_eq!(writer.crc32(), 3057449933);
+        assert_eq!(writer.checksum(), 3057449933);
 
         assert_eq!(writer.write(&[4, 8, 15, 16, 23])?, 5);
         assert_eq!(writer.byte_count(), 9);
-        assert_eq!(writer.crc32(), 3948347807);
+        assert_eq!(writer.checksum(), 3948347807);
 
         assert_eq!(writer.write(&[0, 0, 123])?, 1);
         assert_eq!(writer.byte_count(), 10);
-        assert_eq!(writer.crc32(), 2992191065);
+        assert_eq!(writer.checksum(), 2992191065);
 
         assert_eq!(writer.write(&[42, 124, 234, 27])?, 0);
         assert_eq!(writer.byte_count(), 10);
-        assert_eq!(writer.crc32(), 2992191065);
+        assert_eq!(writer.checksum(), 2992191065);
+
+        let mut writer = TrackingWriter::new(Vec::new(), Checksum::adler32());
+        writer.write_all(&[1, 2, 3, 4])?;
+        assert_eq!(writer.checksum(), 1572875);
 
         Ok(())
     }
@@ -119,7 +224,7 @@ mod tests {
     #[test]
     fn write_previous() -> Result<()> {
         let mut buf: &mut [u8] = &mut [0u8; 512];
-        let mut writer = TrackingWriter::new(&mut buf);
+        let mut writer = TrackingWriter::new(&mut buf, Checksum::crc32());
 
         for i in 0..=255 {
             writer.write_u8(i)?;
@@ -127,20 +232,76 @@ mod tests {
 
         writer.write_previous(192, 128)?;
         assert_eq!(writer.byte_count(), 384);
-        assert_eq!(writer.crc32(), 2611529849);
+        assert_eq!(writer.checksum(), 2611529849);
 
         assert!(writer.write_previous(10000, 20).is_err());
         assert_eq!(writer.byte_count(), 384);
-        assert_eq!(writer.crc32(), 2611529849);
+        assert_eq!(writer.checksum(), 2611529849);
 
         assert!(writer.write_previous(256, 256).is_err());
         assert_eq!(writer.byte_count(), 512);
-        assert_eq!(writer.crc32(), 2733545866);
+        assert_eq!(writer.checksum(), 2733545866);
 
         assert!(writer.write_previous(1, 1).is_err());
         assert_eq!(writer.byte_count(), 512);
-        assert_eq!(writer.crc32(), 2733545866);
+        assert_eq!(writer.checksum(), 2733545866);
+
+        Ok(())
+    }
 
+    #[test]
+    fn overlapping_copy() -> Result<()> {
+        let mut writer = TrackingWriter::new(Vec::new(), Checksum::None);
+        writer.write_all(b"xabc")?;
+        writer.write_previous(3, 1000)?;
+        let expected = [b"x".as_slice(), &b"abc".repeat(335)[..1003]].concat();
+        assert_eq!(writer.get_ref(), &expected);
+        Ok(())
+    }
+
+    #[test]
+    fn batched_checksum() -> Result<()> {
+        let data: Vec<u8> = (0..200_000u32)
+            .map(|i| (i.wrapping_mul(i) >> 7) as u8)
+            .collect();
+        for checksum in [Checksum::crc32(), Checksum::adler32()] {
+            let expected = match checksum {
+                Checksum::Crc32(_) => crc32::checksum(&data),
+                _ => adler32::checksum(&data),
+            };
+            let mut writer = TrackingWriter::new(Vec::new(), checksum);
+            // Writes both smaller and larger than the history.
+            let (small, large) = data.split_at(50_000);
+            for byte in small {
+                writer.write_u8(*byte)?;
+            }
+            writer.write_all(&large[..HISTORY_SIZE + 1])?;
+            writer.write_all(&large[HISTORY_SIZE + 1..])?;
+            assert_eq!(writer.checksum(), expected);
+            assert_eq!(writer.history(), &data[data.len() - HISTORY_SIZE..]);
+
+            writer.flush()?;
+            writer.write_all(&data[..10])?;
+            assert_eq!(
+                writer.checksum(),
+                match checksum {
+                    Checksum::Crc32(_) => crc32::checksum(&data[..10]),
+                    _ => adler32::checksum(&data[..10]),
+                }
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn seed_history() -> Result<()> {
+        let mut writer = TrackingWriter::new(Vec::new(), Checksum::crc32());
+        writer.seed_history(b"abcdef");
+        assert_eq!(writer.byte_count(), 0);
+        writer.write_previous(4, 6)?;
+        assert_eq!(writer.get_ref(), b"cdefcd");
+        assert_eq!(writer.history(), b"abcdefcdefcd");
+        assert_eq!(writer.checksum(), crc32::checksum(b"cdefcd"));
         Ok(())
     }
 }
@@ -0,0 +1,448 @@
+use std::cmp::min;
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Take};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use byteorder::{LittleEndian, ReadBytesExt};
+
+use crate::crc32::Crc32;
+use crate::decoder::{into_io_error, Decoder};
+use crate::error::{Error, Location, Result};
+use crate::Format;
+
+////////////////////////////////////////////////////////////////////////////////
+
+const LOCAL_HEADER_SIGNATURE: u32 = 0x04034b50;
+const CENTRAL_HEADER_SIGNATURE: u32 = 0x02014b50;
+const END_SIGNATURE: u32 = 0x06054b50;
+const ZIP64_END_SIGNATURE: u32 = 0x06064b50;
+const ZIP64_LOCATOR_SIGNATURE: u32 = 0x07064b50;
+
+const LOCAL_HEADER_SIZE: u64 = 30;
+const END_SIZE: u64 = 22;
+const ZIP64_LOCATOR_SIZE: u64 = 20;
+const MAX_COMMENT_SIZE: u64 = 65535;
+
+const ZIP64_EXTRA_ID: u16 = 0x0001;
+const EXTENDED_TIMESTAMP_ID: u16 = 0x5455;
+
+const FLAG_ENCRYPTED: u16 = 1;
+const FLAG_UTF8: u16 = 1 << 11;
+
+/// The "version made by" host system whose external attributes hold Unix permissions.
+const HOST_UNIX: u16 = 3;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ZipMethod {
+    Stored,
+    Deflated,
+    Other(u16),
+}
+
+impl From<u16> for ZipMethod {
+    fn from(method: u16) -> Self {
+        match method {
+            0 => ZipMethod::Stored,
+            8 => ZipMethod::Deflated,
+            method => ZipMethod::Other(method),
+        }
+    }
+}
+
+/// An entry of the central directory.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ZipEntry {
+    /// Path of the entry, ending with `/` for directories.
+    pub name: String,
+    pub method: ZipMethod,
+    pub crc32: u32,
+    pub compressed_size: u64,
+    pub uncompressed_size: u64,
+    /// The extended timestamp if present, otherwise the DOS date and time taken as UTC.
+    pub modified: Option<SystemTime>,
+    pub is_encrypted: bool,
+    /// Permission bits of entries created on Unix.
+    pub unix_mode: Option<u32>,
+    header_offset: u64,
+}
+
+impl ZipEntry {
+    pub fn is_dir(&self) -> bool {
+        self.name.ends_with('/')
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// A zip archive, read through its central directory.
+pub struct ZipArchive<R> {
+    reader: R,
+    entries: Vec<ZipEntry>,
+}
+
+impl<R> ZipArchive<R> {
+    pub fn entries(&self) -> &[ZipEntry] {
+        &self.entries
+    }
+
+    pub fn index_of(&self, name: &str) -> Option<usize> {
+        self.entries.iter().position(|entry| entry.name == name)
+    }
+}
+
+impl<R: Read + Seek> ZipArchive<R> {
+    pub fn new(mut reader: R) -> Result<Self> {
+        let end = find_end(&mut reader)?;
+        reader.seek(SeekFrom::Start(end.directory_offset))?;
+        let mut directory = Vec::new();
+        (&mut reader)
+            .take(end.directory_size)
+            .read_to_end(&mut directory)?;
+        if directory.len() as u64 != end.directory_size {
+            return Err(invalid_archive("central directory is truncated"));
+        }
+
+        let mut data = directory.as_slice();
+        let mut entries = Vec::new();
+        while !data.is_empty() {
+            entries.push(read_central_header(&mut data)?);
+        }
+        if entries.len() as u64 != end.entry_count {
+            return Err(Error::InvalidArchive(format!(
+                "central directory has {} entries, expected {}",
+                entries.len(),
+                end.entry_count
+            )));
+        }
+        Ok(Self { reader, entries })
+    }
+
+    /// A reader of the uncompressed data of the entry at `index`, which checks
+    /// its size and CRC32 once all of it is read.
+    pub fn entry_reader(&mut self, index: usize) -> Result<ZipEntryReader<'_, R>> {
+        let entry = &self.entries[index];
+        let unsupported = |reason: String| Error::UnsupportedEntry {
+            name: entry.name.clone(),
+            reason,
+        };
+        if entry.is_encrypted {
+            return Err(unsupported("encrypted".to_owned()));
+        }
+        if let ZipMethod::Other(method) = entry.method {
+            return Err(unsupported(format!("compression method {}", method)));
+        }
+
+        self.reader.seek(SeekFrom::Start(entry.header_offset))?;
+        if self.reader.read_u32::<LittleEndian>()? != LOCAL_HEADER_SIGNATURE {
+            return Err(invalid_archive("bad local header signature"));
+        }
+        self.reader.seek(SeekFrom::Current(22))?;
+        let name_len = self.reader.read_u16::<LittleEndian>()?;
+        let extra_len = self.reader.read_u16::<LittleEndian>()?;
+        let data_offset =
+            entry.header_offset + LOCAL_HEADER_SIZE + name_len as u64 + extra_len as u64;
+        self.reader.seek(SeekFrom::Start(data_offset))?;
+
+        let data = (&mut self.reader).take(entry.compressed_size);
+        let data = match entry.method {
+            ZipMethod::Stored => EntryData::Stored(data),
+            _ => EntryData::Deflated(Box::new(Decoder::new(BufReader::new(data), Format::Raw))),
+        };
+        Ok(ZipEntryReader {
+            data,
+            index,
+            data_offset,
+            crc32: Crc32::new(),
+            size: 0,
+            expected_crc32: entry.crc32,
+            expected_size: entry.uncompressed_size,
+        })
+    }
+}
+
+enum EntryData<'a, R> {
+    Stored(Take<&'a mut R>),
+    Deflated(Box<Decoder<BufReader<Take<&'a mut R>>>>),
+}
+
+/// The uncompressed data of a zip entry.
+///
+/// Errors are reported with the index of the entry as the member of their location.
+pub struct ZipEntryReader<'a, R> {
+    data: EntryData<'a, R>,
+    index: usize,
+    /// Offset of the compressed data in the archive.
+    data_offset: u64,
+    crc32: Crc32,
+    size: u64,
+    expected_crc32: u32,
+    expected_size: u64,
+}
+
+impl<R: Read> ZipEntryReader<'_, R> {
+    fn verify(&self) -> Result<()> {
+        if self.size != self.expected_size {
+            return Err(Error::InvalidArchive(format!(
+                "entry {} has {} bytes, expected {}",
+                self.index, self.size, self.expected_size
+            )));
+        }
+        if self.crc32.sum32() != self.expected_crc32 {
+            return Err(Error::CrcMismatch {
+                stored: self.expected_crc32,
+                computed: self.crc32.sum32(),
+                location: Location {
+                    member: self.index,
+                    bit_offset: self.data_offset * 8,
+                },
+            });
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for ZipEntryReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = match &mut self.data {
+            EntryData::Stored(data) => data.read(buf)?,
+            EntryData::Deflated(decoder) => {
+                let data = decoder
+                    .decode()
+                    .map_err(|err| into_io_error(err.shift(self.index, self.data_offset * 8)))?;
+                let len = min(data.len(), buf.len());
+                buf[..len].copy_from_slice(&data[..len]);
+                decoder.consume(len);
+                len
+            }
+        };
+        if len == 0 && !buf.is_empty() {
+            self.verify().map_err(into_io_error)?;
+        }
+        self.crc32.write(&buf[..len]);
+        self.size += len as u64;
+        Ok(len)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// The fields of the end of central directory record, from its ZIP64 version if present.
+struct EndRecord {
+    entry_count: u64,
+    directory_size: u64,
+    directory_offset: u64,
+}
+
+fn invalid_archive(reason: &str) -> Error {
+    Error::InvalidArchive(reason.to_owned())
+}
+
+/// Find the end of central directory record, which is followed by a comment of up to 64 KiB.
+fn find_end<R: Read + Seek>(reader: &mut R) -> Result<EndRecord> {
+    let len = reader.seek(SeekFrom::End(0))?;
+    let tail_len = min(len, END_SIZE + MAX_COMMENT_SIZE);
+    let tail_offset = len - tail_len;
+    reader.seek(SeekFrom::Start(tail_offset))?;
+    let mut tail = vec![0; tail_len as usize];
+    reader.read_exact(&mut tail)?;
+
+    let end_pos = (0..tail.len().saturating_sub(END_SIZE as usize - 1))
+        .rev()
+        .find(|&pos| {
+            let comment_len = u16::from_le_bytes([tail[pos + 20], tail[pos + 21]]) as usize;
+            tail[pos..pos + 4] == END_SIGNATURE.to_le_bytes()
+                && pos + END_SIZE as usize + comment_len <= tail.len()
+        })
+        .ok_or_else(|| invalid_archive("end of central directory not found"))?;
+    let mut data = &tail[end_pos + 4..];
+    let _disk = data.read_u16::<LittleEndian>()?;
+    let _directory_disk = data.read_u16::<LittleEndian>()?;
+    let _disk_entry_count = data.read_u16::<LittleEndian>()?;
+    let entry_count = data.read_u16::<LittleEndian>()?;
+    let directory_size = data.read_u32::<LittleEndian>()?;
+    let directory_offset = data.read_u32::<LittleEndian>()?;
+
+    let end_offset = tail_offset + end_pos as u64;
+    if let Some(locator_offset) = end_offset.checked_sub(ZIP64_LOCATOR_SIZE) {
+        reader.seek(SeekFrom::Start(locator_offset))?;
+        if reader.read_u32::<LittleEndian>()? == ZIP64_LOCATOR_SIGNATURE {
+            let _disk = reader.read_u32::<LittleEndian>()?;
+            let zip64_end_offset = reader.read_u64::<LittleEndian>()?;
+            return read_zip64_end(reader, zip64_end_offset);
+        }
+    }
+
+    let end = EndRecord {
+        entry_count: entry_count as u64,
+        directory_size: directory_size as u64,
+        directory_offset: directory_offset as u64,
+    };
+    if end.directory_offset + end.directory_size > end_offset {
+        return Err(invalid_archive("central directory is out of bounds"));
+    }
+    Ok(end)
+}
+
+fn read_zip64_end<R: Read + Seek>(reader: &mut R, offset: u64) -> Result<EndRecord> {
+    reader.seek(SeekFrom::Start(offset))?;
+    if reader.read_u32::<LittleEndian>()? != ZIP64_END_SIGNATURE {
+        return Err(invalid_archive(
+            "bad ZIP64 end of central directory signature",
+        ));
+    }
+    let _record_size = reader.read_u64::<LittleEndian>()?;
+    let _version_made_by = reader.read_u16::<LittleEndian>()?;
+    let _version_needed = reader.read_u16::<LittleEndian>()?;
+    let _disk = reader.read_u32::<LittleEndian>()?;
+    let _directory_disk = reader.read_u32::<LittleEndian>()?;
+    let _disk_entry_count = reader.read_u64::<LittleEndian>()?;
+    let entry_count = reader.read_u64::<LittleEndian>()?;
+    let directory_size = reader.read_u64::<LittleEndian>()?;
+    let directory_offset = reader.read_u64::<LittleEndian>()?;
+    if directory_offset
+        .checked_add(directory_size)
+        .is_none_or(|end| end > offset)
+    {
+        return Err(invalid_archive("central directory is out of bounds"));
+    }
+    Ok(EndRecord {
+        entry_count,
+        directory_size,
+        directory_offset,
+    })
+}
+
+fn read_central_header(data: &mut &[u8]) -> Result<ZipEntry> {
+    let truncated = |_| invalid_archive("central directory entry is truncated");
+    if data.read_u32::<LittleEndian>().map_err(truncated)? != CENTRAL_HEADER_SIGNATURE {
+        return Err(invalid_archive("bad central directory entry signature"));
+    }
+    let mut fields = [0u16; 6];
+    data.read_u16_into::<LittleEndian>(&mut fields)
+        .map_err(truncated)?;
+    let [version_made_by, _version_needed, flags, method, time, date] = fields;
+    let crc32 = data.read_u32::<LittleEndian>().map_err(truncated)?;
+    let compressed_size = data.read_u32::<LittleEndian>().map_err(truncated)?;
+    let uncompressed_size = data.read_u32::<LittleEndian>().map_err(truncated)?;
+    let mut lengths = [0u16; 5];
+    data.read_u16_into::<LittleEndian>(&mut lengths)
+        .map_err(truncated)?;
+    let [name_len, extra_len, comment_len, _disk, _internal_attributes] = lengths;
+    let external_attributes = data.read_u32::<LittleEndian>().map_err(truncated)?;
+    let header_offset = data.read_u32::<LittleEndian>().map_err(truncated)?;
+
+    let mut take = |len: u16| -> Result<&[u8]> {
+        if data.len() < len as usize {
+            return Err(invalid_archive("central directory entry is truncated"));
+        }
+        let (field, rest) = data.split_at(len as usize);
+        *data = rest;
+        Ok(field)
+    };
+    let name = take(name_len)?;
+    let name = if flags & FLAG_UTF8 != 0 {
+        String::from_utf8(name.to_vec())
+            .map_err(|_| invalid_archive("entry name is not valid UTF-8"))?
+    } else {
+        String::from_utf8_lossy(name).into_owned()
+    };
+    let extra = take(extra_len)?;
+    take(comment_len)?;
+
+    let mut entry = ZipEntry {
+        name,
+        method: method.into(),
+        crc32,
+        compressed_size: compressed_size as u64,
+        uncompressed_size: uncompressed_size as u64,
+        modified: dos_time(date, time),
+        is_encrypted: flags & FLAG_ENCRYPTED != 0,
+        unix_mode: Some(external_attributes >> 16)
+            .filter(|&mode| version_made_by >> 8 == HOST_UNIX && mode != 0),
+        header_offset: header_offset as u64,
+    };
+    read_extra_fields(&mut entry, extra)?;
+    Ok(entry)
+}
+
+/// Apply the ZIP64 sizes and offset and the extended timestamp from the extra fields.
+fn read_extra_fields(entry: &mut ZipEntry, mut extra: &[u8]) -> Result<()> {
+    while extra.len() >= 4 {
+        let id = extra.read_u16::<LittleEndian>()?;
+        let size = extra.read_u16::<LittleEndian>()? as usize;
+        if extra.len() < size {
+            return Err(invalid_archive("extra field is truncated"));
+        }
+        let (mut field, rest) = extra.split_at(size);
+        extra = rest;
+        match id {
+            ZIP64_EXTRA_ID => {
+                // Only the values saturated in the header are present, in this order.
+                for value in [
+                    &mut entry.uncompressed_size,
+                    &mut entry.compressed_size,
+                    &mut entry.header_offset,
+                ] {
+                    if *value == u32::MAX as u64 {
+                        *value = field
+                            .read_u64::<LittleEndian>()
+                            .map_err(|_| invalid_archive("ZIP64 extra field is truncated"))?;
+                    }
+                }
+            }
+            EXTENDED_TIMESTAMP_ID if size >= 5 && field[0] & 1 != 0 => {
+                field = &field[1..];
+                let mtime = field.read_i32::<LittleEndian>()?;
+                entry.modified = u64::try_from(mtime)
+                    .ok()
+                    .map(|secs| UNIX_EPOCH + Duration::from_secs(secs));
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Convert an MS-DOS date and time to a point in time, assuming UTC.
+fn dos_time(date: u16, time: u16) -> Option<SystemTime> {
+    let year = 1980 + (date >> 9) as i64;
+    let month = (date >> 5 & 0xf) as i64;
+    let day = (date & 0x1f) as i64;
+    if !(1..=12).contains(&month) || day == 0 {
+        return None;
+    }
+    let seconds =
+        (time >> 11) as u64 * 3600 + (time >> 5 & 0x3f) as u64 * 60 + (time & 0x1f) as u64 * 2;
+    let days = days_from_civil(year, month, day);
+    Some(UNIX_EPOCH + Duration::from_secs(days as u64 * 86400 + seconds))
+}
+
+/// Number of days from 1970-01-01 to the given date of the proleptic Gregorian calendar.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dos_time() {
+        // 2024-03-01 12:30:00.
+        let date = (2024 - 1980) << 9 | 3 << 5 | 1;
+        let time = 12 << 11 | 30 << 5;
+        assert_eq!(
+            super::dos_time(date, time),
+            Some(UNIX_EPOCH + Duration::from_secs(1709296200))
+        );
+        assert_eq!(super::dos_time(0, 0), None);
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(days_from_civil(2000, 3, 1), 11017);
+    }
+}
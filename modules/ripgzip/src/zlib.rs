@@ -0,0 +1,156 @@
+use std::io::{self, BufRead, Write};
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::error::{Error, Location, Result};
+
+////////////////////////////////////////////////////////////////////////////////
+
+const CM_DEFLATE: u8 = 8;
+const MAX_CINFO: u8 = 7;
+
+const FDICT_OFFSET: u8 = 5;
+const FLEVEL_OFFSET: u8 = 6;
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug)]
+pub struct StreamHeader {
+    pub window_size: usize,
+    /// Compression level hint from 0 (fastest) to 3 (maximum compression).
+    pub level: u8,
+    pub dictionary_id: Option<u32>,
+}
+
+#[derive(Debug)]
+pub struct StreamFooter {
+    pub data_adler32: u32,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+pub struct ZlibReader<T> {
+    reader: T,
+}
+
+impl<T: BufRead> ZlibReader<T> {
+    pub fn new(reader: T) -> Self {
+        Self { reader }
+    }
+
+    pub fn read_header(&mut self) -> Result<StreamHeader> {
+        let cmf = self.reader.read_u8()?;
+        let flg = self.reader.read_u8()?;
+        if cmf & 0x0f != CM_DEFLATE {
+            return Err(Error::UnsupportedMethod {
+                method: cmf & 0x0f,
+                location: Location::default(),
+            });
+        }
+        let cinfo = cmf >> 4;
+        if cinfo > MAX_CINFO {
+            return Err(invalid_header("invalid window size"));
+        }
+        if !(cmf as u16 * 256 + flg as u16).is_multiple_of(31) {
+            return Err(invalid_header("header check failed"));
+        }
+        let dictionary_id = if flg >> FDICT_OFFSET & 1 == 1 {
+            Some(self.reader.read_u32::<BigEndian>()?)
+        } else {
+            None
+        };
+        Ok(StreamHeader {
+            window_size: 1 << (cinfo + 8),
+            level: flg >> FLEVEL_OFFSET,
+            dictionary_id,
+        })
+    }
+
+    pub fn read_footer(&mut self) -> Result<StreamFooter> {
+        let data_adler32 = self.reader.read_u32::<BigEndian>()?;
+        Ok(StreamFooter { data_adler32 })
+    }
+}
+
+fn invalid_header(reason: &'static str) -> Error {
+    Error::InvalidHeader {
+        reason,
+        location: Location::default(),
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+pub struct ZlibWriter<T> {
+    writer: T,
+}
+
+impl<T: Write> ZlibWriter<T> {
+    pub fn new(writer: T) -> Self {
+        Self { writer }
+    }
+
+    pub fn write_header(&mut self, header: &StreamHeader) -> io::Result<()> {
+        let cinfo = header.window_size.trailing_zeros() as u8 - 8;
+        let cmf = cinfo << 4 | CM_DEFLATE;
+        let mut flg = header.level << FLEVEL_OFFSET;
+        if header.dictionary_id.is_some() {
+            flg |= 1 << FDICT_OFFSET;
+        }
+        flg += ((31 - (cmf as u16 * 256 + flg as u16) % 31) % 31) as u8;
+        self.writer.write_all(&[cmf, flg])?;
+        if let Some(dictionary_id) = header.dictionary_id {
+            self.writer.write_u32::<BigEndian>(dictionary_id)?;
+        }
+        Ok(())
+    }
+
+    pub fn write_footer(&mut self, footer: &StreamFooter) -> io::Result<()> {
+        self.writer.write_u32::<BigEndian>(footer.data_adler32)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_read_header() -> Result<()> {
+        for level in 0..=3 {
+            let mut data = Vec::new();
+            ZlibWriter::new(&mut data).write_header(&StreamHeader {
+                window_size: 32768,
+                level,
+                dictionary_id: None,
+            })?;
+            assert_eq!(data[0], 0x78);
+            assert!((data[0] as u16 * 256 + data[1] as u16).is_multiple_of(31));
+
+            let header = ZlibReader::new(data.as_slice()).read_header()?;
+            assert_eq!(header.window_size, 32768);
+            assert_eq!(header.level, level);
+            assert!(header.dictionary_id.is_none());
+        }
+        // The header zlib writes by default.
+        assert_eq!(ZlibReader::new(&[0x78, 0x9c][..]).read_header()?.level, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn bad_header() {
+        let err = ZlibReader::new(&[0x79, 0x9c][..])
+            .read_header()
+            .unwrap_err();
+        assert!(err.to_string().contains("unsupported compression method"));
+        let err = ZlibReader::new(&[0x88, 0x9c][..])
+            .read_header()
+            .unwrap_err();
+        assert!(err.to_string().contains("invalid window size"));
+        let err = ZlibReader::new(&[0x78, 0x9d][..])
+            .read_header()
+            .unwrap_err();
+        assert!(err.to_string().contains("header check failed"));
+    }
+}
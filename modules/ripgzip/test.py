@@ -21,6 +21,12 @@ def decompress_file_ripgzip(data, debug=True):
   return proc.stdout
 
 
+def compress_file_ripgzip(data, debug=True):
+  path = DEBUG_BINARY_PATH if debug else RELEASE_BINARY_PATH
+  proc = subprocess.run([path], input=data, capture_output=True, check=True)
+  return proc.stdout
+
+
 def test_static_cases():
   for file_path in sorted(OK_TESTS_PATH.iterdir()):
     print(f"checking file '{file_path}'")
@@ -72,11 +78,33 @@ def test_big_random_cases():
       raise
 
 
+def test_compress_cases():
+  random.seed(7345623462)
+
+  cases = [gzip.decompress(path.read_bytes())
+           for path in sorted(OK_TESTS_PATH.iterdir())]
+  cases += [bytes(random.randrange(4) for _ in range(10000)) * 10
+            for _ in range(10)]
+
+  for i, data in enumerate(cases):
+    print(f"testing compression, case #{i}")
+
+    compressed = compress_file_ripgzip(data, debug=False)
+    try:
+      assert gzip.decompress(compressed) == data
+    except Exception:
+      with open(DUMP_PATH, "wb") as f:
+        f.write(compressed)
+      print(f"check failed, wrote problematic data to {DUMP_PATH}")
+      raise
+
+
 def main():
   bundles = [
       test_static_cases,
       test_small_random_cases,
       test_big_random_cases,
+      test_compress_cases,
   ]
 
   if len(sys.argv) > 1:
@@ -0,0 +1,99 @@
+mod common;
+
+use common::decompressed;
+
+use std::io::Write;
+
+use ripgzip::{Analysis, BlockType, Error, GzEncoder, Level};
+
+fn compressed(data: &[u8], level: Level) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::new(), level);
+    encoder.write_all(data).unwrap();
+    encoder.finish().unwrap()
+}
+
+#[cfg(test)]
+mod analysis {
+    use super::*;
+
+    #[test]
+    fn sizes() {
+        for data in [
+            &include_bytes!("../data/ok/06-war-and-peace.txt.gz")[..],
+            &include_bytes!("../data/ok/09-concat.gz")[..],
+        ] {
+            let analysis = Analysis::new(data).unwrap();
+            let size: u64 = analysis.blocks.iter().map(|b| b.uncompressed_size).sum();
+            assert_eq!(size, decompressed(data).len() as u64);
+            let bits: u64 = analysis.blocks.iter().map(|b| b.compressed_bits).sum();
+            assert!(bits <= data.len() as u64 * 8);
+
+            for block in &analysis.blocks {
+                assert_eq!(block.length_histogram.iter().sum::<u64>(), block.matches);
+                assert_eq!(block.distance_histogram.iter().sum::<u64>(), block.matches);
+                assert_eq!(
+                    block.code_lengths.is_some(),
+                    block.block_type == BlockType::Dynamic
+                );
+            }
+            for pair in analysis.blocks.windows(2) {
+                assert!(pair[0].bit_offset + pair[0].compressed_bits <= pair[1].bit_offset);
+            }
+        }
+    }
+
+    #[test]
+    fn block_types() {
+        let text = decompressed(include_bytes!("../data/ok/01-page.gz"));
+        let analysis = Analysis::new(compressed(&text, Level::none()).as_slice()).unwrap();
+        assert!(analysis
+            .blocks
+            .iter()
+            .all(|b| b.block_type == BlockType::Stored && b.matches == 0));
+        assert!(analysis.blocks.last().unwrap().is_final);
+
+        let analysis = Analysis::new(&include_bytes!("../data/ok/12-to-be.txt.gz")[..]).unwrap();
+        let block = &analysis.blocks[0];
+        assert_eq!(block.block_type, BlockType::Fixed);
+        assert_eq!((block.literals, block.matches), (35, 4));
+        assert_eq!(block.uncompressed_size, 61);
+        // A match of length 13-14, and one of distance 33-48.
+        assert_eq!(block.length_histogram[9], 1);
+        assert_eq!(block.distance_histogram[10], 1);
+    }
+
+    #[test]
+    fn output() {
+        let data: &[u8] = include_bytes!("../data/ok/13-fixed-concat.gz");
+        let analysis = Analysis::new(data).unwrap();
+        assert_eq!(analysis.blocks.len(), 2);
+
+        let mut text = Vec::new();
+        analysis.write_text(&mut text).unwrap();
+        let text = String::from_utf8(text).unwrap();
+        assert!(text.contains("block 1 at member 1"));
+        assert!(text.contains("fixed, final"));
+
+        let mut json = Vec::new();
+        analysis.write_json(&mut json).unwrap();
+        let json = String::from_utf8(json).unwrap();
+        assert!(json.starts_with("{\"blocks\": ["));
+        assert_eq!(json.matches("\"type\": \"fixed\"").count(), 2);
+        assert!(json.contains("\"code_lengths\": null"));
+        assert!(json.trim_end().ends_with("]}"));
+    }
+
+    #[test]
+    fn corrupted() {
+        let data: &[u8] = include_bytes!("../data/corrupted/06-invalid-btype.gz");
+        assert!(matches!(
+            Analysis::new(data),
+            Err(Error::UnsupportedBlockType { .. })
+        ));
+        let data: &[u8] = include_bytes!("../data/corrupted/00-bad-length.gz");
+        assert!(matches!(
+            Analysis::new(data),
+            Err(Error::LengthMismatch { .. })
+        ));
+    }
+}
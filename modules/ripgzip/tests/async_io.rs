@@ -0,0 +1,169 @@
+#![cfg(feature = "tokio")]
+
+mod common;
+
+use common::decompressed;
+
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use tokio::io::{AsyncBufRead, AsyncRead, AsyncReadExt, AsyncWriteExt, ReadBuf};
+
+use ripgzip::{AsyncGzDecoder, AsyncGzEncoder, AsyncZlibDecoder, AsyncZlibEncoder, Level};
+
+/// An input arriving in chunks of at most `chunk_size` bytes, every other poll being pending.
+struct Trickle<'a> {
+    data: &'a [u8],
+    chunk_size: usize,
+    is_ready: bool,
+}
+
+impl<'a> Trickle<'a> {
+    fn new(data: &'a [u8], chunk_size: usize) -> Self {
+        Self {
+            data,
+            chunk_size,
+            is_ready: false,
+        }
+    }
+}
+
+impl AsyncRead for Trickle<'_> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let data = match self.as_mut().poll_fill_buf(cx) {
+            Poll::Ready(data) => data?,
+            Poll::Pending => return Poll::Pending,
+        };
+        let len = data.len().min(buf.remaining());
+        buf.put_slice(&data[..len]);
+        self.consume(len);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncBufRead for Trickle<'_> {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        let this = self.get_mut();
+        this.is_ready = !this.is_ready;
+        if !this.is_ready {
+            cx.waker().wake_by_ref();
+            return Poll::Pending;
+        }
+        let len = this.data.len().min(this.chunk_size);
+        Poll::Ready(Ok(&this.data[..len]))
+    }
+
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        let this = self.get_mut();
+        this.data = &this.data[amt..];
+    }
+}
+
+fn block_on<F: std::future::Future>(future: F) -> F::Output {
+    tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap()
+        .block_on(future)
+}
+
+async fn decompress_async(data: &[u8], chunk_size: usize) -> io::Result<Vec<u8>> {
+    let mut output = Vec::new();
+    AsyncGzDecoder::new(Trickle::new(data, chunk_size))
+        .read_to_end(&mut output)
+        .await?;
+    Ok(output)
+}
+
+#[test]
+fn decode_in_chunks() {
+    let inputs: [&[u8]; 4] = [
+        include_bytes!("../data/ok/00-Cargo.toml.gz"),
+        include_bytes!("../data/ok/06-war-and-peace.txt.gz"),
+        include_bytes!("../data/ok/09-concat.gz"),
+        include_bytes!("../data/ok/10-header-crc16.gz"),
+    ];
+    for data in inputs {
+        let expected = decompressed(data);
+        for chunk_size in [1, 7, 4096, usize::MAX] {
+            let output = block_on(decompress_async(data, chunk_size)).unwrap();
+            assert!(output == expected, "chunks of {} bytes", chunk_size);
+        }
+    }
+}
+
+#[test]
+fn header() {
+    let data = include_bytes!("../data/ok/11-hello.txt.gz");
+    block_on(async {
+        let mut decoder = AsyncGzDecoder::new(Trickle::new(data, 3));
+        assert!(decoder.header().is_none());
+        let mut output = Vec::new();
+        decoder.read_to_end(&mut output).await.unwrap();
+        assert_eq!(output, b"Hello, world!\n");
+        assert_eq!(decoder.header().unwrap().name.as_deref(), Some("hello.txt"));
+    });
+}
+
+#[test]
+fn corrupted() {
+    let cases: [(&[u8], &str); 3] = [
+        (
+            include_bytes!("../data/corrupted/01-bad-crc32.gz"),
+            "crc32 check failed",
+        ),
+        (
+            include_bytes!("../data/corrupted/02-unexpected-eof.gz"),
+            "unexpected end of input",
+        ),
+        (
+            include_bytes!("../data/corrupted/04-header-eof.gz"),
+            "unexpected end of input",
+        ),
+    ];
+    for (data, message) in cases {
+        let err = block_on(decompress_async(data, 5)).unwrap_err();
+        assert!(err.to_string().contains(message), "{}", err);
+    }
+}
+
+#[test]
+fn gzip_roundtrip() {
+    let data = decompressed(include_bytes!("../data/ok/06-war-and-peace.txt.gz"));
+    let compressed = block_on(async {
+        let mut encoder = AsyncGzEncoder::new(Vec::new(), Level::default());
+        for chunk in data.chunks(10000) {
+            encoder.write_all(chunk).await.unwrap();
+        }
+        encoder.shutdown().await.unwrap();
+        encoder.into_inner()
+    });
+    assert!(compressed.len() < data.len() / 2);
+    assert_eq!(decompressed(&compressed), data);
+}
+
+#[test]
+fn zlib_roundtrip() {
+    let data = b"to be or not to be, that is the question\n".repeat(1000);
+    let output = block_on(async {
+        let mut encoder = AsyncZlibEncoder::new(Vec::new(), Level::best());
+        encoder.write_all(&data).await.unwrap();
+        // A flush makes everything written so far decodable.
+        encoder.flush().await.unwrap();
+        assert!(!encoder.get_ref().is_empty());
+        encoder.shutdown().await.unwrap();
+        let compressed = encoder.into_inner();
+
+        let mut output = Vec::new();
+        AsyncZlibDecoder::new(Trickle::new(&compressed, 100))
+            .read_to_end(&mut output)
+            .await
+            .unwrap();
+        output
+    });
+    assert_eq!(output, data);
+}
@@ -0,0 +1,126 @@
+mod common;
+
+use common::{decompressed, text};
+
+use std::io::{BufRead, Cursor, Read, Write};
+
+use ripgzip::{BgzfReader, BgzfWriter, Level, Members, VirtualOffset};
+
+fn war_and_peace() -> Vec<u8> {
+    text("06-war-and-peace.txt.gz")
+}
+
+/// Compress `data` line by line, returning the file and the virtual offset of every line.
+fn bgzf_lines(data: &[u8]) -> (Vec<u8>, Vec<VirtualOffset>) {
+    let mut writer = BgzfWriter::new(Vec::new(), Level::default());
+    let mut offsets = Vec::new();
+    for line in data.split_inclusive(|&byte| byte == b'\n') {
+        offsets.push(writer.virtual_offset());
+        writer.write_all(line).unwrap();
+    }
+    (writer.finish().unwrap(), offsets)
+}
+
+#[test]
+fn blocks() {
+    let data = war_and_peace();
+    let mut writer = BgzfWriter::new(Vec::new(), Level::default());
+    writer.write_all(&data).unwrap();
+    let file = writer.finish().unwrap();
+    assert_eq!(decompressed(&file), data);
+
+    let members: Vec<_> = Members::new(file.as_slice())
+        .collect::<ripgzip::Result<_>>()
+        .unwrap();
+    assert_eq!(members.len(), data.len().div_ceil(0xff00) + 1);
+    for member in &members {
+        assert!(member.compressed_size <= 65536);
+        let bsize = member.header.subfield(*b"BC").unwrap();
+        assert_eq!(
+            u16::from_le_bytes([bsize[0], bsize[1]]) as u64 + 1,
+            member.compressed_size
+        );
+    }
+    // The end-of-file marker.
+    let last = members.last().unwrap();
+    assert_eq!((last.compressed_size, last.uncompressed_size), (28, 0));
+
+    let mut output = Vec::new();
+    BgzfReader::new(file.as_slice())
+        .read_to_end(&mut output)
+        .unwrap();
+    assert!(output == data);
+}
+
+#[test]
+fn seek_to_lines() {
+    let data = war_and_peace();
+    let (file, offsets) = bgzf_lines(&data);
+    let lines: Vec<_> = data.split_inclusive(|&byte| byte == b'\n').collect();
+
+    let mut reader = BgzfReader::new(Cursor::new(&file));
+    for (i, line) in lines.iter().enumerate() {
+        assert_eq!(reader.virtual_offset(), offsets[i]);
+        let mut buffer = Vec::new();
+        reader.read_until(b'\n', &mut buffer).unwrap();
+        assert_eq!(&buffer, line);
+    }
+
+    for i in (0..lines.len()).rev().step_by(997) {
+        reader.seek_virtual(offsets[i]).unwrap();
+        let mut buffer = Vec::new();
+        reader.read_until(b'\n', &mut buffer).unwrap();
+        assert_eq!(buffer, lines[i], "line {}", i);
+    }
+
+    let end = VirtualOffset::new(file.len() as u64 - 28, 0);
+    reader.seek_virtual(end).unwrap();
+    assert_eq!(reader.fill_buf().unwrap(), b"");
+    let past_end = VirtualOffset::new(offsets[1].block_offset(), 0xfff0);
+    assert!(reader.seek_virtual(past_end).is_err());
+}
+
+#[test]
+fn flush() {
+    let mut writer = BgzfWriter::new(Vec::new(), Level::fast());
+    writer.write_all(b"first\n").unwrap();
+    writer.flush().unwrap();
+    let second = writer.virtual_offset();
+    assert_eq!(second.block_offset(), writer.get_ref().len() as u64);
+    assert_eq!(second.in_block_offset(), 0);
+    writer.write_all(b"second\n").unwrap();
+    let file = writer.finish().unwrap();
+
+    let mut reader = BgzfReader::new(Cursor::new(&file));
+    reader.seek_virtual(second).unwrap();
+    let mut output = String::new();
+    reader.read_to_string(&mut output).unwrap();
+    assert_eq!(output, "second\n");
+}
+
+#[test]
+fn corrupted() {
+    let (mut file, _) = bgzf_lines(b"hello\nworld\n");
+    let mut output = Vec::new();
+
+    let err = BgzfReader::new(&file[..file.len() - 30])
+        .read_to_end(&mut output)
+        .unwrap_err();
+    assert!(
+        err.to_string().contains("unexpected end of input"),
+        "{}",
+        err
+    );
+
+    // The CRC-32 of the first block.
+    let footer = file.len() - 28 - 8;
+    file[footer] ^= 1;
+    let err = BgzfReader::new(file.as_slice())
+        .read_to_end(&mut output)
+        .unwrap_err();
+    assert!(err.to_string().contains("crc32 check failed"), "{}", err);
+
+    let plain: &[u8] = include_bytes!("../data/ok/11-hello.txt.gz");
+    let err = BgzfReader::new(plain).read_to_end(&mut output).unwrap_err();
+    assert!(err.to_string().contains("no extra field"), "{}", err);
+}
@@ -0,0 +1,306 @@
+mod common;
+
+use common::{decompressed, text};
+
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use std::time::{Duration, SystemTime};
+
+use ripgzip::Members;
+
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new(name: &str) -> Self {
+        let path =
+            std::env::temp_dir().join(format!("ripgzip-cli-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        self.0.join(name)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+fn ripgzip(dir: &TempDir, args: &[&str], stdin: &[u8]) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_ripgzip"))
+        .args(args)
+        .current_dir(&dir.0)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(stdin).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+fn set_mtime(path: &Path, time: SystemTime) {
+    fs::File::options()
+        .write(true)
+        .open(path)
+        .unwrap()
+        .set_modified(time)
+        .unwrap();
+}
+
+#[test]
+fn compress_and_decompress_in_place() {
+    let dir = TempDir::new("in-place");
+    let data = text("01-page.gz");
+    let mtime = SystemTime::UNIX_EPOCH + Duration::from_secs(1_500_000_000);
+    fs::write(dir.path("page.html"), &data).unwrap();
+    set_mtime(&dir.path("page.html"), mtime);
+
+    assert!(ripgzip(&dir, &["page.html"], b"").status.success());
+    assert!(!dir.path("page.html").exists());
+    let compressed = fs::read(dir.path("page.html.gz")).unwrap();
+    assert_eq!(decompressed(&compressed), data);
+    let header = Members::new(compressed.as_slice())
+        .next()
+        .unwrap()
+        .unwrap()
+        .header;
+    assert_eq!(header.name.as_deref(), Some("page.html"));
+    assert_eq!(header.mtime(), Some(mtime));
+    let metadata = fs::metadata(dir.path("page.html.gz")).unwrap();
+    assert_eq!(metadata.modified().unwrap(), mtime);
+
+    assert!(ripgzip(&dir, &["-d", "page.html.gz"], b"").status.success());
+    assert!(!dir.path("page.html.gz").exists());
+    assert_eq!(fs::read(dir.path("page.html")).unwrap(), data);
+}
+
+#[test]
+fn keep_force_and_suffix() {
+    let dir = TempDir::new("keep");
+    let data = text("01-page.gz");
+    fs::write(dir.path("page"), &data).unwrap();
+
+    assert!(ripgzip(&dir, &["-k", "-S", ".z", "page"], b"")
+        .status
+        .success());
+    assert_eq!(fs::read(dir.path("page")).unwrap(), data);
+    assert!(dir.path("page.z").exists());
+
+    let output = ripgzip(&dir, &["-k", "-S", ".z", "page"], b"");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("already exists"));
+    assert!(ripgzip(&dir, &["-kf", "-S", ".z", "page"], b"")
+        .status
+        .success());
+
+    let output = ripgzip(&dir, &["-d", "page"], b"");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("unknown suffix"));
+
+    fs::remove_file(dir.path("page")).unwrap();
+    assert!(ripgzip(&dir, &["-d", "-S", ".z", "page.z"], b"")
+        .status
+        .success());
+    assert_eq!(fs::read(dir.path("page")).unwrap(), data);
+}
+
+#[test]
+fn restore_name() {
+    let dir = TempDir::new("name");
+    fs::write(dir.path("original.txt"), b"hello").unwrap();
+    assert!(ripgzip(&dir, &["original.txt"], b"").status.success());
+    fs::rename(dir.path("original.txt.gz"), dir.path("renamed.gz")).unwrap();
+
+    assert!(ripgzip(&dir, &["-dN", "renamed.gz"], b"").status.success());
+    assert_eq!(fs::read(dir.path("original.txt")).unwrap(), b"hello");
+}
+
+#[test]
+fn stdout_and_levels() {
+    let dir = TempDir::new("stdout");
+    let data = text("01-page.gz");
+    fs::write(dir.path("page"), &data).unwrap();
+
+    let fast = ripgzip(&dir, &["-c1", "page"], b"").stdout;
+    let best = ripgzip(&dir, &["-1", "-9", "-c", "page"], b"").stdout;
+    assert!(dir.path("page").exists());
+    assert_eq!(decompressed(&fast), data);
+    assert_eq!(decompressed(&best), data);
+    assert!(best.len() < fast.len());
+
+    let output = ripgzip(&dir, &["-d"], &best);
+    assert_eq!(output.stdout, data);
+    let output = ripgzip(&dir, &["-p", "4"], &data);
+    assert_eq!(decompressed(&output.stdout), data);
+    let output = ripgzip(&dir, &["--rsyncable", "-p", "4"], &data);
+    assert_eq!(decompressed(&output.stdout), data);
+}
+
+#[test]
+fn parallel_decompression() {
+    let dir = TempDir::new("parallel");
+    let concat: &[u8] = include_bytes!("../data/ok/09-concat.gz");
+    let page: &[u8] = include_bytes!("../data/ok/01-page.gz");
+    let data = [concat, page].concat().repeat(2);
+    fs::write(dir.path("members.gz"), &data).unwrap();
+
+    let output = ripgzip(&dir, &["-dc", "-p", "3"], &data);
+    assert!(output.status.success());
+    assert!(output.stdout == decompressed(&data));
+
+    assert!(ripgzip(&dir, &["-d", "-p", "3", "members.gz"], b"")
+        .status
+        .success());
+    assert!(fs::read(dir.path("members")).unwrap() == decompressed(&data));
+}
+
+#[test]
+fn test_and_list() {
+    let dir = TempDir::new("list");
+    let page: &[u8] = include_bytes!("../data/ok/01-page.gz");
+    let concat: &[u8] = include_bytes!("../data/ok/09-concat.gz");
+    fs::write(dir.path("page.gz"), page).unwrap();
+    fs::write(dir.path("concat.gz"), concat).unwrap();
+    fs::write(
+        dir.path("bad.gz"),
+        include_bytes!("../data/corrupted/01-bad-crc32.gz"),
+    )
+    .unwrap();
+
+    assert!(ripgzip(&dir, &["-t", "page.gz", "concat.gz"], b"")
+        .status
+        .success());
+    assert!(!ripgzip(&dir, &["-t", "page.gz", "bad.gz"], b"")
+        .status
+        .success());
+    assert!(dir.path("page.gz").exists());
+
+    let output = ripgzip(&dir, &["-l", "page.gz", "concat.gz"], b"");
+    assert!(output.status.success());
+    let listing = String::from_utf8(output.stdout).unwrap();
+    let lines: Vec<_> = listing.lines().collect();
+    let members = Members::new(concat).count();
+    assert_eq!(lines.len(), 2 + members);
+    assert!(lines[0].contains("uncompressed_name"));
+    let columns: Vec<_> = lines[1].split_whitespace().collect();
+    assert_eq!(columns[0], page.len().to_string());
+    assert_eq!(columns[1], decompressed(page).len().to_string());
+    assert_eq!(columns[3], "page");
+}
+
+#[test]
+fn recover() {
+    let dir = TempDir::new("recover");
+    let hello: &[u8] = include_bytes!("../data/ok/11-hello.txt.gz");
+    let page: &[u8] = include_bytes!("../data/ok/01-page.gz");
+    let mut data = [page, hello].concat();
+    data[page.len() / 2] ^= 0xff;
+    fs::write(dir.path("logs.gz"), &data).unwrap();
+
+    assert!(!ripgzip(&dir, &["-dk", "logs.gz"], b"").status.success());
+    assert!(!dir.path("logs").exists());
+
+    let output = ripgzip(&dir, &["-d", "--recover", "logs.gz"], b"");
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("skipped bytes 0.."));
+    assert_eq!(fs::read(dir.path("logs")).unwrap(), b"Hello, world!\n");
+    assert!(dir.path("logs.gz").exists());
+}
+
+#[test]
+fn unzip() {
+    let dir = TempDir::new("unzip");
+    fs::write(
+        dir.path("archive.zip"),
+        include_bytes!("../data/zip/archive.zip"),
+    )
+    .unwrap();
+
+    let output = ripgzip(&dir, &["--unzip", "archive.zip", "-l"], b"");
+    assert!(output.status.success());
+    let listing = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(listing.lines().count(), 6);
+    assert!(listing.contains("deflated a188320a docs/page.html"));
+
+    let output = ripgzip(&dir, &["-c", "--unzip", "archive.zip", "hello.txt"], b"");
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"Hello, world!\n");
+
+    let output = ripgzip(&dir, &["--unzip", "archive.zip", "--exdir", "out"], b"");
+    assert!(output.status.success());
+    assert_eq!(
+        fs::read(dir.path("out/docs/page.html")).unwrap(),
+        text("01-page.gz")
+    );
+    assert_eq!(
+        fs::read(dir.path("out/naïve.txt")).unwrap(),
+        "café\n".as_bytes()
+    );
+
+    // Existing files are kept unless -f is given.
+    fs::write(dir.path("out/hello.txt"), b"changed").unwrap();
+    let output = ripgzip(&dir, &["--unzip", "archive.zip", "--exdir", "out"], b"");
+    assert!(!output.status.success());
+    assert_eq!(fs::read(dir.path("out/hello.txt")).unwrap(), b"changed");
+    let output = ripgzip(
+        &dir,
+        &["-f", "--unzip", "archive.zip", "--exdir", "out"],
+        b"",
+    );
+    assert!(output.status.success());
+    assert_eq!(
+        fs::read(dir.path("out/hello.txt")).unwrap(),
+        b"Hello, world!\n"
+    );
+
+    // Flags that only apply to gzip files are rejected rather than ignored.
+    for flag in ["-k", "-N", "-S.z", "-p2", "-9", "--rsyncable", "--optimal"] {
+        let output = ripgzip(&dir, &["--unzip", "archive.zip", "-l", flag], b"");
+        assert!(!output.status.success(), "{} was accepted", flag);
+        assert!(String::from_utf8_lossy(&output.stderr).contains("cannot be used with"));
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn unzip_special_bits() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = TempDir::new("unzip-special-bits");
+    fs::write(
+        dir.path("setuid.zip"),
+        include_bytes!("../data/zip/setuid.zip"),
+    )
+    .unwrap();
+
+    // The entry is stored with mode 0o4755, only the permission bits are kept.
+    let output = ripgzip(&dir, &["--unzip", "setuid.zip"], b"");
+    assert!(output.status.success());
+    let mode = fs::metadata(dir.path("tool")).unwrap().permissions().mode();
+    assert_eq!(mode & 0o7777, 0o755);
+}
+
+#[test]
+fn unzip_file_name() {
+    // Files named like the option are compressed as any other.
+    let dir = TempDir::new("unzip-file-name");
+    fs::write(dir.path("unzip"), text("01-page.gz")).unwrap();
+
+    let output = ripgzip(&dir, &["unzip"], b"");
+    assert!(output.status.success());
+    assert!(!dir.path("unzip").exists());
+    assert_eq!(
+        decompressed(&fs::read(dir.path("unzip.gz")).unwrap()),
+        text("01-page.gz")
+    );
+
+    let output = ripgzip(&dir, &["--exdir", "out", "unzip.gz"], b"");
+    assert!(!output.status.success());
+}
@@ -0,0 +1,24 @@
+//! Helpers shared by the integration tests.
+
+// Every test crate uses only some of the helpers.
+#![allow(dead_code)]
+
+use std::fs;
+use std::path::Path;
+
+pub mod random;
+
+/// Decompress a whole gzip stream, which must be valid.
+pub fn decompressed(mut data: &[u8]) -> Vec<u8> {
+    let mut output = Vec::new();
+    ripgzip::decompress(&mut data, &mut output).unwrap();
+    output
+}
+
+/// Decompressed contents of the file `name` in `data/ok`.
+pub fn text(name: &str) -> Vec<u8> {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("data/ok")
+        .join(name);
+    decompressed(&fs::read(path).unwrap())
+}
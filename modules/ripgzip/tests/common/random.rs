@@ -0,0 +1,12 @@
+// Also used by the unit tests of the crate, so it must not depend on anything else.
+
+/// Bytes of a linear congruential generator, always the same for the same `seed`.
+pub fn random_bytes(len: usize, seed: u32) -> Vec<u8> {
+    let mut state = seed;
+    (0..len)
+        .map(|_| {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            (state >> 16) as u8
+        })
+        .collect()
+}
@@ -0,0 +1,300 @@
+mod common;
+
+use common::{decompressed, random::random_bytes, text};
+
+use std::io::{Read, Write};
+use std::process::{Command, Stdio};
+use std::thread;
+
+use ripgzip::{GzDecoder, GzEncoder, Level};
+
+fn gunzip(data: Vec<u8>) -> Vec<u8> {
+    let mut child = Command::new("gzip")
+        .arg("-dc")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("failed to run gzip");
+    let mut stdin = child.stdin.take().unwrap();
+    let writer = thread::spawn(move || stdin.write_all(&data));
+    let mut output = Vec::new();
+    child
+        .stdout
+        .take()
+        .unwrap()
+        .read_to_end(&mut output)
+        .unwrap();
+    writer.join().unwrap().unwrap();
+    assert!(child.wait().unwrap().success(), "gzip rejected the stream");
+    output
+}
+
+fn check_compression(data: &[u8]) -> Vec<u8> {
+    let mut compressed = Vec::new();
+    ripgzip::compress(data, &mut compressed).unwrap();
+    assert_eq!(gunzip(compressed.clone()), data);
+    assert_eq!(decompressed(&compressed), data);
+    compressed
+}
+
+#[cfg(test)]
+mod compress {
+    use super::*;
+
+    #[test]
+    fn empty() {
+        check_compression(b"");
+    }
+
+    #[test]
+    fn short() {
+        check_compression(b"a");
+        check_compression(b"hello, hello, hello world!\n");
+    }
+
+    #[test]
+    fn text() {
+        let data = decompressed(include_bytes!("../data/ok/01-page.gz"));
+        let compressed = check_compression(&data);
+        assert!(compressed.len() < data.len() / 2);
+    }
+
+    #[test]
+    fn large_text() {
+        let data = decompressed(include_bytes!("../data/ok/06-war-and-peace.txt.gz"));
+        let compressed = check_compression(&data);
+        assert!(compressed.len() < data.len() / 2);
+    }
+
+    #[test]
+    fn binary() {
+        let data = decompressed(include_bytes!("../data/ok/05-app.gz"));
+        check_compression(&data);
+    }
+
+    #[test]
+    fn long_runs() {
+        let mut data = vec![0u8; 100_000];
+        data.extend(vec![b'x'; 70_000]);
+        data.extend(b"abc".repeat(30_000));
+        let compressed = check_compression(&data);
+        assert!(compressed.len() < 1000);
+    }
+
+    #[test]
+    fn random() {
+        let data = random_bytes(200_000, 42);
+        let compressed = check_compression(&data);
+        assert!(compressed.len() < data.len() + 100);
+    }
+}
+
+#[cfg(test)]
+mod gz_encoder {
+    use super::*;
+
+    #[test]
+    fn levels() {
+        let data = decompressed(include_bytes!("../data/ok/01-page.gz"));
+        let mut sizes = Vec::new();
+        for level in 0..=10 {
+            let mut encoder = GzEncoder::new(Vec::new(), Level::new(level));
+            encoder.write_all(&data).unwrap();
+            let compressed = encoder.finish().unwrap();
+            assert_eq!(gunzip(compressed.clone()), data);
+            assert_eq!(decompressed(&compressed), data);
+            sizes.push(compressed.len());
+        }
+        assert!(sizes[0] > data.len());
+        assert!(sizes[4] < sizes[1]);
+        assert!(sizes[9] <= sizes[6]);
+        assert!(sizes[10] < sizes[9]);
+    }
+
+    #[test]
+    fn small_writes() {
+        let data = decompressed(include_bytes!("../data/ok/02-doc.pdf.gz"));
+        let mut encoder = GzEncoder::new(Vec::new(), Level::default());
+        for chunk in data.chunks(1000) {
+            encoder.write_all(chunk).unwrap();
+        }
+        let compressed = encoder.finish().unwrap();
+        assert_eq!(gunzip(compressed.clone()), data);
+    }
+
+    #[test]
+    fn flush() {
+        let mut encoder = GzEncoder::new(Vec::new(), Level::default());
+        encoder.write_all(b"first line\n").unwrap();
+        encoder.flush().unwrap();
+
+        let mut decoder = GzDecoder::new(encoder.get_ref().as_slice());
+        let mut buf = [0u8; 11];
+        decoder.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"first line\n");
+
+        encoder.write_all(b"second line\n").unwrap();
+        encoder.flush().unwrap();
+        let compressed = encoder.finish().unwrap();
+        assert_eq!(gunzip(compressed.clone()), b"first line\nsecond line\n");
+        assert_eq!(decompressed(&compressed), b"first line\nsecond line\n");
+    }
+}
+
+#[cfg(test)]
+mod dictionary {
+    use super::*;
+
+    const MESSAGE: &[u8] = br#"{"id": 1729, "method": "get_block", "params": {"hash": "00ab"}}"#;
+    const DICTIONARY: &[u8] = br#"{"id": , "method": "get_block", "params": {"hash": "#;
+
+    #[test]
+    fn roundtrip() {
+        let plain = check_compression(MESSAGE);
+        let mut compressed = Vec::new();
+        ripgzip::compress_with_dictionary(MESSAGE, &mut compressed, DICTIONARY).unwrap();
+        assert!(compressed.len() < plain.len() - DICTIONARY.len() / 2);
+
+        let mut output = Vec::new();
+        ripgzip::decompress_with_dictionary(compressed.as_slice(), &mut output, DICTIONARY)
+            .unwrap();
+        assert_eq!(output, MESSAGE);
+
+        // Without the dictionary, the matches reach before the start of the member.
+        assert!(ripgzip::decompress(compressed.as_slice(), &mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn gz_encoder() {
+        let data = [DICTIONARY, MESSAGE].concat().repeat(100);
+        let mut encoder = GzEncoder::with_dictionary(Vec::new(), Level::best(), DICTIONARY);
+        for chunk in data.chunks(100) {
+            encoder.write_all(chunk).unwrap();
+        }
+        let compressed = encoder.finish().unwrap();
+
+        let mut output = Vec::new();
+        GzDecoder::with_dictionary(compressed.as_slice(), DICTIONARY)
+            .read_to_end(&mut output)
+            .unwrap();
+        assert!(output == data);
+    }
+}
+
+#[cfg(test)]
+mod compress_parallel {
+    use super::*;
+
+    fn check_parallel(data: &[u8], level: Level, processes: usize) -> Vec<u8> {
+        let mut compressed = Vec::new();
+        ripgzip::compress_parallel(data, &mut compressed, level, processes).unwrap();
+        assert_eq!(gunzip(compressed.clone()), data);
+        assert_eq!(decompressed(&compressed), data);
+        compressed
+    }
+
+    #[test]
+    fn empty() {
+        check_parallel(b"", Level::default(), 4);
+    }
+
+    #[test]
+    fn processes() {
+        let data = decompressed(include_bytes!("../data/ok/06-war-and-peace.txt.gz"));
+        let mut sequential = Vec::new();
+        ripgzip::compress(data.as_slice(), &mut sequential).unwrap();
+        for processes in [1, 2, 3, 8] {
+            let compressed = check_parallel(&data, Level::default(), processes);
+            // Priming each chunk with the previous one keeps the ratio close to sequential.
+            assert!(compressed.len() < sequential.len() * 102 / 100);
+        }
+    }
+
+    #[test]
+    fn levels() {
+        let mut data = decompressed(include_bytes!("../data/ok/05-app.gz"));
+        data.extend(random_bytes(300_000, 7));
+        for level in [0, 1, 9] {
+            check_parallel(&data, Level::new(level), 4);
+        }
+    }
+
+    #[test]
+    fn deterministic() {
+        let data = decompressed(include_bytes!("../data/ok/02-doc.pdf.gz"));
+        assert_eq!(
+            check_parallel(&data, Level::default(), 2),
+            check_parallel(&data, Level::default(), 7)
+        );
+    }
+}
+
+#[cfg(test)]
+mod rsyncable {
+    use super::*;
+
+    fn compressed(data: &[u8], level: Level, rsyncable: bool, piece_size: usize) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), level).with_rsyncable(rsyncable);
+        for piece in data.chunks(piece_size) {
+            encoder.write_all(piece).unwrap();
+        }
+        encoder.finish().unwrap()
+    }
+
+    /// Number of bytes differing between `a` and `b`, apart from their common prefix
+    /// and their common suffix before the footer.
+    fn changed_bytes(a: &[u8], b: &[u8]) -> usize {
+        let (a, b) = (&a[..a.len() - 8], &b[..b.len() - 8]);
+        let prefix = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+        let suffix = a[prefix..]
+            .iter()
+            .rev()
+            .zip(b[prefix..].iter().rev())
+            .take_while(|(x, y)| x == y)
+            .count();
+        a.len().max(b.len()) - prefix - suffix
+    }
+
+    #[test]
+    fn roundtrip() {
+        let mut data = text("06-war-and-peace.txt.gz");
+        data.extend(random_bytes(200_000, 3));
+        data.extend(vec![0; 100_000]);
+        for level in [0, 1, 6, 9] {
+            let output = compressed(&data, Level::new(level), true, 65536);
+            assert!(decompressed(&output) == data);
+            assert!(gunzip(output) == data);
+        }
+    }
+
+    #[test]
+    fn deterministic() {
+        let data = text("06-war-and-peace.txt.gz");
+        let expected = compressed(&data, Level::default(), true, data.len());
+        assert!(compressed(&data, Level::default(), true, 1000) == expected);
+        assert!(compressed(&data, Level::default(), true, 65536) == expected);
+    }
+
+    #[test]
+    fn local_changes() {
+        let data = text("06-war-and-peace.txt.gz");
+        let mut replaced = data.clone();
+        replaced[700_000..700_010].copy_from_slice(b"0123456789");
+        let mut inserted = data.clone();
+        inserted.splice(300_000..300_000, b"an inserted sentence. ".repeat(20));
+        let mut removed = data.clone();
+        removed.drain(1_000_000..1_000_500);
+
+        let original = compressed(&data, Level::default(), true, 65536);
+        let plain = compressed(&data, Level::default(), false, 65536);
+        // The chunks cost about 1% of the compression ratio.
+        assert!(original.len() < plain.len() * 102 / 100);
+        for edited in [replaced, inserted, removed] {
+            // A few chunks of about 12 KiB of input change, the rest is kept as is.
+            let output = compressed(&edited, Level::default(), true, 65536);
+            assert!(changed_bytes(&original, &output) < 64 << 10);
+            let plain_output = compressed(&edited, Level::default(), false, 65536);
+            assert!(changed_bytes(&plain, &plain_output) > 200 << 10);
+        }
+    }
+}
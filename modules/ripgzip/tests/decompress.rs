@@ -0,0 +1,507 @@
+mod common;
+
+use common::decompressed;
+
+use std::io::{self, BufRead, Read};
+
+use std::io::Write;
+
+use ripgzip::{
+    DecompressOptions, Error, GzDecoder, GzEncoder, Level, Limit, MemberHeader, MemberHeaders,
+};
+
+fn check_decompression(data: &[u8], expected: &[u8]) {
+    assert_eq!(decompressed(data), expected);
+}
+
+#[cfg(test)]
+mod fixed_tree {
+    use super::*;
+
+    #[test]
+    fn literals_only() {
+        check_decompression(
+            include_bytes!("../data/ok/11-hello.txt.gz"),
+            b"Hello, world!\n",
+        );
+    }
+
+    #[test]
+    fn with_matches() {
+        check_decompression(
+            include_bytes!("../data/ok/12-to-be.txt.gz"),
+            b"to be or not to be, that is the question: to be or not to be\n",
+        );
+    }
+
+    #[test]
+    fn concatenated_members() {
+        check_decompression(
+            include_bytes!("../data/ok/13-fixed-concat.gz"),
+            b"Hello, world!\nto be or not to be, that is the question: to be or not to be\n",
+        );
+    }
+}
+
+#[cfg(test)]
+mod gz_decoder {
+    use super::*;
+
+    #[test]
+    fn read_to_end() {
+        let data: &[u8] = include_bytes!("../data/ok/09-concat.gz");
+        let mut output = Vec::new();
+        GzDecoder::new(data).read_to_end(&mut output).unwrap();
+        assert_eq!(output, decompressed(data));
+    }
+
+    #[test]
+    fn small_reads() {
+        let data: &[u8] = include_bytes!("../data/ok/13-fixed-concat.gz");
+        let mut decoder = GzDecoder::new(data);
+        let mut output = Vec::new();
+        let mut buf = [0u8; 3];
+        loop {
+            let len = decoder.read(&mut buf).unwrap();
+            if len == 0 {
+                break;
+            }
+            output.extend_from_slice(&buf[..len]);
+        }
+        assert_eq!(output, decompressed(data));
+    }
+
+    #[test]
+    fn split_lines() {
+        let data: &[u8] = include_bytes!("../data/ok/06-war-and-peace.txt.gz");
+        let expected = decompressed(data);
+        let lines: Vec<Vec<u8>> = GzDecoder::new(data)
+            .split(b'\n')
+            .map(Result::unwrap)
+            .collect();
+        let mut expected_lines: Vec<&[u8]> = expected.split(|&b| b == b'\n').collect();
+        if expected.ends_with(b"\n") {
+            expected_lines.pop();
+        }
+        assert_eq!(lines, expected_lines);
+    }
+
+    #[test]
+    fn empty_input() {
+        let mut output = Vec::new();
+        GzDecoder::new(&[][..]).read_to_end(&mut output).unwrap();
+        assert!(output.is_empty());
+    }
+
+    #[test]
+    fn corrupted_data() {
+        let data: &[u8] = include_bytes!("../data/corrupted/01-bad-crc32.gz");
+        let err = GzDecoder::new(data)
+            .read_to_end(&mut Vec::new())
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("crc32 check failed"));
+
+        let data: &[u8] = include_bytes!("../data/corrupted/02-unexpected-eof.gz");
+        let err = GzDecoder::new(data)
+            .read_to_end(&mut Vec::new())
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
+
+#[cfg(test)]
+mod member_headers {
+    use super::*;
+
+    #[test]
+    fn names() {
+        let data: &[u8] = include_bytes!("../data/ok/13-fixed-concat.gz");
+        let headers: Vec<_> = MemberHeaders::new(data).map(Result::unwrap).collect();
+        let names: Vec<_> = headers.iter().map(|h| h.name.as_deref()).collect();
+        assert_eq!(names, [Some("hello.txt"), Some("to-be.txt")]);
+        for header in &headers {
+            assert_eq!(header.os_name(), "Unix");
+            assert_eq!(header.modification_time, 1792219550);
+            assert!(header.comment.is_none());
+        }
+    }
+
+    #[test]
+    fn header_crc() {
+        let data: &[u8] = include_bytes!("../data/ok/10-header-crc16.gz");
+        let headers: Vec<_> = MemberHeaders::new(data).map(Result::unwrap).collect();
+        assert_eq!(headers.len(), 1);
+        assert!(headers[0].has_crc);
+        assert_eq!(headers[0].extra.as_ref().map(Vec::len), Some(93));
+    }
+
+    #[test]
+    fn decoder_header() {
+        let data: &[u8] = include_bytes!("../data/ok/13-fixed-concat.gz");
+        let mut decoder = GzDecoder::new(data);
+        assert!(decoder.header().is_none());
+        decoder.fill_buf().unwrap();
+        assert_eq!(decoder.header().unwrap().name.as_deref(), Some("hello.txt"));
+        decoder.read_to_end(&mut Vec::new()).unwrap();
+        assert_eq!(decoder.header().unwrap().name.as_deref(), Some("to-be.txt"));
+    }
+
+    #[test]
+    fn corrupted_member() {
+        let data: &[u8] = include_bytes!("../data/corrupted/01-bad-crc32.gz");
+        let mut headers = MemberHeaders::new(data);
+        assert!(headers.next().unwrap().is_ok());
+        assert!(headers.next().unwrap().is_err());
+        assert!(headers.next().is_none());
+    }
+}
+
+#[cfg(test)]
+mod decompress_options {
+    use super::*;
+
+    fn limit_error(data: &[u8], options: DecompressOptions) -> Option<Limit> {
+        let mut output = Vec::new();
+        match ripgzip::decompress_with_options(data, &mut output, options) {
+            Ok(()) => None,
+            Err(Error::LimitExceeded { limit, .. }) => Some(limit),
+            Err(err) => panic!("unexpected error: {}", err),
+        }
+    }
+
+    fn compressed(header: MemberHeader, data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::with_header(Vec::new(), Level::default(), header);
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn output_size() {
+        let data: &[u8] = include_bytes!("../data/ok/06-war-and-peace.txt.gz");
+        let size = decompressed(data).len() as u64;
+        let options = |max| DecompressOptions {
+            max_output_size: Some(max),
+            ..DecompressOptions::default()
+        };
+        assert_eq!(limit_error(data, options(size)), None);
+        assert_eq!(
+            limit_error(data, options(size - 1)),
+            Some(Limit::OutputSize(size - 1))
+        );
+        assert_eq!(
+            limit_error(data, options(1000)),
+            Some(Limit::OutputSize(1000))
+        );
+    }
+
+    #[test]
+    fn ratio() {
+        let data = compressed(MemberHeader::default(), &vec![0; 10 << 20]);
+        let options = |max| DecompressOptions {
+            max_ratio: Some(max),
+            ..DecompressOptions::default()
+        };
+        assert_eq!(limit_error(&data, options(100)), Some(Limit::Ratio(100)));
+        assert_eq!(limit_error(&data, options(2000)), None);
+
+        // The limit is hit early, without inflating the whole bomb.
+        let mut decoder = GzDecoder::with_options(data.as_slice(), options(100));
+        let mut output = Vec::new();
+        assert!(decoder.read_to_end(&mut output).is_err());
+        assert!(output.len() < 1 << 20);
+    }
+
+    #[test]
+    fn member_count() {
+        let data: &[u8] = include_bytes!("../data/ok/09-concat.gz");
+        let members = MemberHeaders::new(data).count();
+        let options = |max| DecompressOptions {
+            max_members: Some(max),
+            ..DecompressOptions::default()
+        };
+        assert_eq!(limit_error(data, options(members)), None);
+        assert_eq!(
+            limit_error(data, options(members - 1)),
+            Some(Limit::MemberCount(members - 1))
+        );
+    }
+
+    #[test]
+    fn header_fields() {
+        let options = DecompressOptions {
+            max_header_field_size: Some(100),
+            ..DecompressOptions::default()
+        };
+        let headers = [
+            MemberHeader {
+                name: Some("x".repeat(101)),
+                ..MemberHeader::default()
+            },
+            MemberHeader {
+                comment: Some("x".repeat(1000)),
+                ..MemberHeader::default()
+            },
+            MemberHeader {
+                extra: Some(vec![0; 101]),
+                ..MemberHeader::default()
+            },
+        ];
+        for header in headers {
+            let data = compressed(header, b"data");
+            assert_eq!(
+                limit_error(&data, options.clone()),
+                Some(Limit::HeaderFieldSize(100))
+            );
+            assert_eq!(limit_error(&data, DecompressOptions::default()), None);
+        }
+
+        let header = MemberHeader {
+            name: Some("x".repeat(100)),
+            extra: Some(vec![0; 100]),
+            ..MemberHeader::default()
+        };
+        assert_eq!(limit_error(&compressed(header, b"data"), options), None);
+    }
+}
+
+#[cfg(test)]
+mod decompress_lenient {
+    use super::*;
+
+    use ripgzip::RecoveryReport;
+
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    fn recovered(data: &[u8]) -> (Vec<u8>, RecoveryReport) {
+        let mut output = Vec::new();
+        let report = ripgzip::decompress_lenient(data, &mut output).unwrap();
+        (output, report)
+    }
+
+    #[test]
+    fn intact_input() {
+        let data: &[u8] = include_bytes!("../data/ok/09-concat.gz");
+        let (output, report) = recovered(data);
+        assert_eq!(output, decompressed(data));
+        assert!(report.is_complete());
+        assert_eq!(report.recovered.len(), MemberHeaders::new(data).count());
+        assert_eq!(
+            report.recovered.last().unwrap().input.end,
+            data.len() as u64
+        );
+        assert_eq!(recovered(b"").1.recovered, []);
+    }
+
+    #[test]
+    fn damaged_member() {
+        let hello: &[u8] = include_bytes!("../data/ok/11-hello.txt.gz");
+        let page: &[u8] = include_bytes!("../data/ok/01-page.gz");
+        let to_be: &[u8] = include_bytes!("../data/ok/12-to-be.txt.gz");
+        let mut data = [hello, page, to_be].concat();
+        let damaged = hello.len() + page.len() / 2;
+        data[damaged] ^= 0xff;
+
+        let (output, report) = recovered(&data);
+        assert_eq!(output, [decompressed(hello), decompressed(to_be)].concat());
+        let ranges: Vec<_> = report.recovered.iter().map(|r| r.input.clone()).collect();
+        let hello_end = hello.len() as u64;
+        let page_end = hello_end + page.len() as u64;
+        assert_eq!(ranges, [0..hello_end, page_end..data.len() as u64]);
+        assert_eq!(report.recovered[1].output.start, 14);
+
+        assert_eq!(report.lost.len(), 1);
+        let lost = &report.lost[0];
+        assert_eq!(lost.input, hello_end..page_end);
+        assert_eq!(report.lost_bytes(), page.len() as u64);
+        let location = lost.error.location().unwrap();
+        assert_eq!(location.member, 1);
+        assert!(location.byte_offset() >= damaged as u64);
+    }
+
+    #[test]
+    fn garbage() {
+        let hello: &[u8] = include_bytes!("../data/ok/11-hello.txt.gz");
+        // A false header start, then a member cut short.
+        let mut data = b"junk\x1f\x8b\x08".to_vec();
+        data.extend_from_slice(hello);
+        data.extend_from_slice(&hello[..20]);
+
+        let (output, report) = recovered(&data);
+        assert_eq!(output, b"Hello, world!\n");
+        let lost: Vec<_> = report.lost.iter().map(|l| l.input.clone()).collect();
+        let end = data.len() as u64;
+        assert_eq!(lost, [0..7, end - 20..end]);
+        assert!(matches!(report.lost[0].error, Error::BadMagic { .. }));
+        assert!(matches!(report.lost[1].error, Error::TruncatedInput { .. }));
+        assert_eq!(report.lost[1].error.location().unwrap().member, 2);
+    }
+
+    /// Reads the input a few bytes at a time, then fails.
+    struct SlowReader<'a> {
+        data: &'a [u8],
+        read: Rc<Cell<usize>>,
+    }
+
+    impl Read for SlowReader<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.data.is_empty() {
+                return Err(io::Error::other("disk failure"));
+            }
+            let len = self.data.len().min(buf.len()).min(7);
+            buf[..len].copy_from_slice(&self.data[..len]);
+            self.data = &self.data[len..];
+            self.read.set(self.read.get() + len);
+            Ok(len)
+        }
+    }
+
+    /// Records how much of the input was read before the first write.
+    struct FirstWrite {
+        read: Rc<Cell<usize>>,
+        read_before: Option<usize>,
+        data: Vec<u8>,
+    }
+
+    impl Write for FirstWrite {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.read_before.get_or_insert(self.read.get());
+            self.data.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn streaming() {
+        let page: &[u8] = include_bytes!("../data/ok/01-page.gz");
+        let mut data = page.repeat(20);
+        data[page.len() * 5 + page.len() / 2] ^= 0xff;
+        data.extend_from_slice(b"junk\x1f\x8b\x08junk");
+        let (expected, expected_report) = recovered(&data);
+        let lost = |report: &RecoveryReport| -> Vec<_> {
+            report.lost.iter().map(|l| l.input.clone()).collect()
+        };
+
+        let read = Rc::new(Cell::new(0));
+        let input = SlowReader {
+            data: &data,
+            read: read.clone(),
+        };
+        let mut output = FirstWrite {
+            read: read.clone(),
+            read_before: None,
+            data: Vec::new(),
+        };
+        let report =
+            ripgzip::decompress_lenient(input.take(data.len() as u64), &mut output).unwrap();
+        assert_eq!(output.data, expected);
+        assert_eq!(report.recovered, expected_report.recovered);
+        assert_eq!(lost(&report), lost(&expected_report));
+        // The output of a member is written before the input following it is read.
+        assert!(output.read_before.unwrap() < 2 * page.len());
+
+        // Failures to read are returned rather than taken for damage.
+        let input = SlowReader {
+            data: &data[..page.len() * 3 / 2],
+            read,
+        };
+        let err = ripgzip::decompress_lenient(input, io::sink()).unwrap_err();
+        assert!(matches!(err, Error::Io(_)));
+    }
+}
+
+#[cfg(test)]
+mod decompress_parallel {
+    use super::*;
+
+    use ripgzip::Location;
+
+    fn compressed(data: &[u8], level: Level) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), level);
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn decompressed_parallel(data: &[u8], processes: usize) -> ripgzip::Result<Vec<u8>> {
+        let mut output = Vec::new();
+        ripgzip::decompress_parallel(data, &mut output, processes)?;
+        Ok(output)
+    }
+
+    fn serial_error(data: &[u8]) -> Error {
+        ripgzip::decompress(data, io::sink()).unwrap_err()
+    }
+
+    fn error_summary(err: &Error) -> (String, Option<Location>) {
+        (err.to_string(), err.location())
+    }
+
+    fn members() -> Vec<u8> {
+        let inputs: [&[u8]; 4] = [
+            include_bytes!("../data/ok/01-page.gz"),
+            include_bytes!("../data/ok/06-war-and-peace.txt.gz"),
+            include_bytes!("../data/ok/09-concat.gz"),
+            include_bytes!("../data/ok/11-hello.txt.gz"),
+        ];
+        inputs.concat().repeat(3)
+    }
+
+    #[test]
+    fn concatenated_members() {
+        let data = members();
+        let expected = decompressed(&data);
+        for processes in [1, 2, 5] {
+            assert!(decompressed_parallel(&data, processes).unwrap() == expected);
+        }
+        assert_eq!(decompressed_parallel(b"", 4).unwrap(), b"");
+    }
+
+    #[test]
+    fn magic_bytes_inside_member() {
+        let text = decompressed(include_bytes!("../data/ok/06-war-and-peace.txt.gz"));
+        // Stored blocks keep the magic bytes as they are, so the member is cut at all of them.
+        let mut data = Vec::new();
+        for chunk in text.chunks(300_000) {
+            data.extend_from_slice(chunk);
+            data.extend_from_slice(b"\x1f\x8b\x08\x00");
+        }
+        let hello: &[u8] = include_bytes!("../data/ok/11-hello.txt.gz");
+        let input = [&compressed(&data, Level::none()), hello, &members()].concat();
+        assert!(decompressed_parallel(&input, 3).unwrap() == decompressed(&input));
+    }
+
+    #[test]
+    fn large_member() {
+        let text = decompressed(include_bytes!("../data/ok/06-war-and-peace.txt.gz"));
+        // Without magic bytes in over 16 MiB, the rest is decompressed serially.
+        let large = text.repeat(12);
+        let hello: &[u8] = include_bytes!("../data/ok/11-hello.txt.gz");
+        let input = [hello, &compressed(&large, Level::none()), hello].concat();
+        let hello = b"Hello, world!\n".as_slice();
+        let expected = [hello, &large, hello].concat();
+        assert!(decompressed_parallel(&input, 4).unwrap() == expected);
+    }
+
+    #[test]
+    fn corrupted_member() {
+        let page: &[u8] = include_bytes!("../data/ok/01-page.gz");
+        let mut data = page.repeat(60);
+        data[page.len() * 50 + page.len() / 2] ^= 0x55;
+        let err = decompressed_parallel(&data, 4).unwrap_err();
+        assert_eq!(error_summary(&err), error_summary(&serial_error(&data)));
+        assert_eq!(err.location().unwrap().member, 50);
+    }
+
+    #[test]
+    fn truncated_input() {
+        let data = members();
+        let data = &data[..data.len() - 10];
+        let err = decompressed_parallel(data, 4).unwrap_err();
+        assert!(matches!(err, Error::TruncatedInput { .. }));
+        assert_eq!(error_summary(&err), error_summary(&serial_error(data)));
+    }
+}
@@ -1,15 +1,17 @@
-fn check_decompression_error(mut data: &[u8], msg: &'static str) {
-    let res = ripgzip::decompress(&mut data, &mut std::io::sink());
-    if res.is_ok() {
-        panic!("expected Err, got Ok");
+use ripgzip::{Error, Location};
+
+fn decompression_error(mut data: &[u8]) -> Error {
+    match ripgzip::decompress(&mut data, &mut std::io::sink()) {
+        Ok(()) => panic!("expected Err, got Ok"),
+        Err(err) => err,
     }
-    for inner in res.unwrap_err().chain() {
-        println!("{}", inner.to_string());
-        if inner.to_string().contains(msg) {
-            return;
-        }
+}
+
+fn location(member: usize, byte_offset: u64) -> Location {
+    Location {
+        member,
+        bit_offset: byte_offset * 8,
     }
-    panic!("error does not contain message: {}", msg);
 }
 
 #[cfg(test)]
@@ -18,67 +20,149 @@ mod errors {
 
     #[test]
     fn length_check_error() {
-        check_decompression_error(
-            include_bytes!("../data/corrupted/00-bad-length.gz"),
-            "length check failed",
-        );
+        let data: &[u8] = include_bytes!("../data/corrupted/00-bad-length.gz");
+        let err = decompression_error(data);
+        assert!(err.to_string().contains("length check failed"));
+        match err {
+            Error::LengthMismatch {
+                stored,
+                computed,
+                location: loc,
+            } => {
+                assert_ne!(stored, computed);
+                assert_eq!(loc, location(0, data.len() as u64 - 8));
+            }
+            err => panic!("unexpected error: {}", err),
+        }
     }
 
     #[test]
     fn crc32_check_error() {
-        check_decompression_error(
-            include_bytes!("../data/corrupted/01-bad-crc32.gz"),
-            "crc32 check failed",
-        );
+        let data: &[u8] = include_bytes!("../data/corrupted/01-bad-crc32.gz");
+        let err = decompression_error(data);
+        assert!(err.to_string().contains("crc32 check failed"));
+        assert!(matches!(err, Error::CrcMismatch { stored, computed, .. } if stored != computed));
+        assert_eq!(err.location(), Some(location(0, data.len() as u64 - 8)));
     }
 
     #[test]
     fn unexpected_eof_error() {
-        check_decompression_error(include_bytes!("../data/corrupted/02-unexpected-eof.gz"), "");
+        let err = decompression_error(include_bytes!("../data/corrupted/02-unexpected-eof.gz"));
+        assert!(matches!(err, Error::TruncatedInput { location } if location.member == 0));
     }
 
     #[test]
     fn wrong_id_error() {
-        check_decompression_error(
-            include_bytes!("../data/corrupted/03-wrong-id.gz"),
-            "wrong id values",
-        );
+        let err = decompression_error(include_bytes!("../data/corrupted/03-wrong-id.gz"));
+        assert!(err.to_string().contains("wrong id values"));
+        assert!(matches!(err, Error::BadMagic { location: loc } if loc == location(0, 0)));
     }
 
     #[test]
     fn header_eof_error() {
-        check_decompression_error(include_bytes!("../data/corrupted/04-header-eof.gz"), "");
+        let data: &[u8] = include_bytes!("../data/corrupted/04-header-eof.gz");
+        let err = decompression_error(data);
+        match err {
+            Error::TruncatedInput { location } => {
+                assert_eq!(location.member, 1);
+                assert!(location.byte_offset() < data.len() as u64);
+            }
+            err => panic!("unexpected error: {}", err),
+        }
     }
 
     #[test]
     fn crc16_error() {
-        check_decompression_error(
-            include_bytes!("../data/corrupted/05-bad-header-crc16.gz"),
-            "header crc16 check failed",
-        );
+        let err = decompression_error(include_bytes!("../data/corrupted/05-bad-header-crc16.gz"));
+        assert!(err.to_string().contains("header crc16 check failed"));
+        match err {
+            Error::HeaderCrc {
+                stored,
+                computed,
+                location: loc,
+            } => {
+                assert_ne!(stored, computed);
+                assert_eq!(loc, location(0, 0));
+            }
+            err => panic!("unexpected error: {}", err),
+        }
     }
 
     #[test]
     fn unsupported_block_type_error() {
-        check_decompression_error(
-            include_bytes!("../data/corrupted/06-invalid-btype.gz"),
-            "unsupported block type",
+        let err = decompression_error(include_bytes!("../data/corrupted/06-invalid-btype.gz"));
+        assert!(err.to_string().contains("unsupported block type"));
+        assert!(
+            matches!(err, Error::UnsupportedBlockType { location: loc } if loc == location(0, 10))
         );
     }
 
     #[test]
     fn unsupported_compression_method() {
-        check_decompression_error(
-            include_bytes!("../data/corrupted/07-invalid-cm.gz"),
-            "unsupported compression method",
-        );
+        let err = decompression_error(include_bytes!("../data/corrupted/07-invalid-cm.gz"));
+        assert!(err.to_string().contains("unsupported compression method"));
+        assert!(matches!(err, Error::UnsupportedMethod { method: 128, .. }));
     }
 
     #[test]
     fn nlen_check_error() {
-        check_decompression_error(
-            include_bytes!("../data/corrupted/08-bad-nlen.gz"),
-            "nlen check failed",
+        let err = decompression_error(include_bytes!("../data/corrupted/08-bad-nlen.gz"));
+        assert!(err.to_string().contains("nlen check failed"));
+        assert!(
+            matches!(err, Error::StoredLengthMismatch { location: loc } if loc == location(0, 10))
         );
     }
+
+    #[test]
+    fn invalid_code_lengths() {
+        // A dynamic block whose first code length is a repeat of the previous one.
+        let mut data = vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 0xff];
+        data.extend([0x05, 0x00, 0x12, 0x00]);
+        let err = decompression_error(&data);
+        assert!(
+            matches!(err, Error::InvalidCodeLengths { location, .. } if location.byte_offset() == 10),
+            "unexpected error: {}",
+            err
+        );
+    }
+
+    #[test]
+    fn distance_too_far_back() {
+        // A fixed block with the single match <length 3, distance 1> and nothing before it.
+        let mut encoded = ripgzip::DeflateEncoder::new(Vec::new(), ripgzip::Level::default());
+        std::io::Write::write_all(&mut encoded, b"aaaa").unwrap();
+        let deflated = encoded.finish().unwrap();
+        // Drop the leading literal: 3 bits of header, then an 8-bit literal `a`.
+        let mut bits = 0u64;
+        for (i, byte) in deflated.iter().enumerate() {
+            bits |= (*byte as u64) << (8 * i);
+        }
+        let bits = (bits & 0b111) | (bits >> 11 << 3);
+        let mut data = vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 0xff];
+        data.extend(&bits.to_le_bytes()[..deflated.len()]);
+
+        let err = decompression_error(&data);
+        assert!(
+            matches!(
+                err,
+                Error::DistanceTooFarBack {
+                    distance: 1,
+                    available: 0,
+                    ..
+                }
+            ),
+            "unexpected error: {}",
+            err
+        );
+    }
+
+    #[test]
+    fn io_error_conversion() {
+        let mut data: &[u8] = include_bytes!("../data/corrupted/01-bad-crc32.gz");
+        let mut decoder = ripgzip::GzDecoder::new(&mut data);
+        let err = std::io::copy(&mut decoder, &mut std::io::sink()).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        let inner = err.into_inner().unwrap().downcast::<Error>().unwrap();
+        assert!(matches!(*inner, Error::CrcMismatch { .. }));
+    }
 }
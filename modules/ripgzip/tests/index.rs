@@ -0,0 +1,119 @@
+mod common;
+
+use common::decompressed;
+
+use std::io::{BufReader, Cursor, Read, Seek, SeekFrom};
+
+use ripgzip::{Index, SeekableGzReader};
+
+fn read_at<R: Read + Seek>(reader: &mut R, offset: u64, len: usize) -> Vec<u8> {
+    assert_eq!(reader.seek(SeekFrom::Start(offset)).unwrap(), offset);
+    let mut buf = Vec::new();
+    reader.take(len as u64).read_to_end(&mut buf).unwrap();
+    buf
+}
+
+#[cfg(test)]
+mod index {
+    use super::*;
+
+    #[test]
+    fn build() {
+        let data: &[u8] = include_bytes!("../data/ok/06-war-and-peace.txt.gz");
+        let expected = decompressed(data);
+        let index = Index::build(data, 128 << 10).unwrap();
+        assert_eq!(index.total_size(), expected.len() as u64);
+        assert!(index.points().len() >= 10);
+        for pair in index.points().windows(2) {
+            assert!(pair[1].output_offset - pair[0].output_offset >= 128 << 10);
+        }
+        for point in index.points() {
+            let offset = point.output_offset as usize;
+            assert_eq!(point.window, &expected[offset - point.window.len()..offset]);
+        }
+    }
+
+    #[test]
+    fn sidecar() {
+        let data: &[u8] = include_bytes!("../data/ok/09-concat.gz");
+        let index = Index::build(data, 100 << 10).unwrap();
+        let mut sidecar = Vec::new();
+        index.write_to(&mut sidecar).unwrap();
+        assert_eq!(Index::read_from(sidecar.as_slice()).unwrap(), index);
+        assert!(Index::read_from(&sidecar[..sidecar.len() - 1]).is_err());
+    }
+}
+
+#[cfg(test)]
+mod seekable_gz_reader {
+    use super::*;
+
+    fn check_random_access(data: &[u8], span: u64) {
+        let expected = decompressed(data);
+        let index = Index::build(data, span).unwrap();
+        let mut reader = SeekableGzReader::new(BufReader::new(Cursor::new(data)), index);
+
+        let len = expected.len() as u64;
+        let mut offsets = vec![0, 1, len / 2, len - 10, len / 3, span, span - 1, span + 1];
+        offsets.extend(reader.index().points().iter().map(|p| p.output_offset));
+        for offset in offsets {
+            let start = offset as usize;
+            let end = (start + 5000).min(expected.len());
+            assert_eq!(
+                read_at(&mut reader, offset, 5000),
+                &expected[start..end],
+                "offset {}",
+                offset
+            );
+        }
+    }
+
+    #[test]
+    fn single_member() {
+        check_random_access(
+            include_bytes!("../data/ok/06-war-and-peace.txt.gz"),
+            300_000,
+        );
+    }
+
+    #[test]
+    fn multiple_members() {
+        check_random_access(include_bytes!("../data/ok/09-concat.gz"), 100_000);
+    }
+
+    #[test]
+    fn sequential_read() {
+        let data: &[u8] = include_bytes!("../data/ok/09-concat.gz");
+        let index = Index::build(data, 50_000).unwrap();
+        let mut reader = SeekableGzReader::new(Cursor::new(data), index);
+        reader.seek(SeekFrom::Start(123_456)).unwrap();
+        let mut output = Vec::new();
+        reader.read_to_end(&mut output).unwrap();
+        assert_eq!(output, &decompressed(data)[123_456..]);
+    }
+
+    #[test]
+    fn seek_from_end_and_current() {
+        let data: &[u8] = include_bytes!("../data/ok/01-page.gz");
+        let expected = decompressed(data);
+        let index = Index::build(data, 4096).unwrap();
+        let mut reader = SeekableGzReader::new(Cursor::new(data), index);
+
+        assert_eq!(
+            reader.seek(SeekFrom::End(-100)).unwrap(),
+            expected.len() as u64 - 100
+        );
+        let mut tail = Vec::new();
+        reader.read_to_end(&mut tail).unwrap();
+        assert_eq!(tail, &expected[expected.len() - 100..]);
+
+        reader.seek(SeekFrom::Start(1000)).unwrap();
+        assert_eq!(reader.seek(SeekFrom::Current(-500)).unwrap(), 500);
+        let mut buf = [0; 10];
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, expected[500..510]);
+
+        assert!(reader.seek(SeekFrom::Current(-1000)).is_err());
+        assert_eq!(read_at(&mut reader, expected.len() as u64 + 10, 10), b"");
+    }
+}
@@ -0,0 +1,127 @@
+mod common;
+
+use common::text;
+
+use std::io::{Cursor, Read};
+use std::time::{Duration, SystemTime};
+
+use ripgzip::{Error, ZipArchive, ZipMethod};
+
+const ARCHIVE: &[u8] = include_bytes!("../data/zip/archive.zip");
+
+fn archive(data: &[u8]) -> ZipArchive<Cursor<&[u8]>> {
+    ZipArchive::new(Cursor::new(data)).unwrap()
+}
+
+fn extract(archive: &mut ZipArchive<Cursor<&[u8]>>, name: &str) -> std::io::Result<Vec<u8>> {
+    let index = archive.index_of(name).unwrap();
+    let mut output = Vec::new();
+    archive
+        .entry_reader(index)
+        .unwrap()
+        .read_to_end(&mut output)?;
+    Ok(output)
+}
+
+#[test]
+fn entries() {
+    let archive = archive(ARCHIVE);
+    let names: Vec<_> = archive.entries().iter().map(|e| e.name.as_str()).collect();
+    assert_eq!(
+        names,
+        [
+            "hello.txt",
+            "docs/",
+            "docs/page.html",
+            "empty.txt",
+            "naïve.txt"
+        ]
+    );
+
+    let hello = &archive.entries()[0];
+    assert_eq!(hello.method, ZipMethod::Stored);
+    assert_eq!(hello.crc32, 0x7b55a718);
+    assert_eq!(hello.uncompressed_size, 14);
+    assert!(!hello.is_dir() && !hello.is_encrypted);
+    // 2024-03-01 12:30:00 UTC.
+    let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(1709296200);
+    assert_eq!(hello.modified, Some(modified));
+
+    assert!(archive.entries()[1].is_dir());
+    let page = &archive.entries()[2];
+    assert_eq!(page.method, ZipMethod::Deflated);
+    assert_eq!(page.uncompressed_size, 153333);
+    assert!(page.compressed_size < page.uncompressed_size);
+}
+
+#[test]
+fn extract_entries() {
+    let mut archive = archive(ARCHIVE);
+    assert_eq!(
+        extract(&mut archive, "hello.txt").unwrap(),
+        b"Hello, world!\n"
+    );
+    assert_eq!(
+        extract(&mut archive, "docs/page.html").unwrap(),
+        text("01-page.gz")
+    );
+    assert_eq!(extract(&mut archive, "empty.txt").unwrap(), b"");
+    assert_eq!(
+        extract(&mut archive, "naïve.txt").unwrap(),
+        "café\n".as_bytes()
+    );
+    assert_eq!(extract(&mut archive, "docs/").unwrap(), b"");
+}
+
+#[test]
+fn zip64() {
+    let mut archive = archive(include_bytes!("../data/zip/zip64.zip"));
+    let hello = &archive.entries()[0];
+    assert_eq!(hello.name, "hello.txt");
+    assert_eq!(hello.uncompressed_size, 1400);
+    assert_eq!(
+        extract(&mut archive, "hello.txt").unwrap(),
+        b"Hello, world!\n".repeat(100)
+    );
+}
+
+#[test]
+fn crc_mismatch() {
+    let mut data = ARCHIVE.to_vec();
+    let offset = data
+        .windows(5)
+        .position(|window| window == b"Hello")
+        .unwrap();
+    data[offset] ^= 0x01;
+    let mut archive = archive(&data);
+    let err = extract(&mut archive, "hello.txt").unwrap_err();
+    assert!(err.to_string().contains("crc32 check failed"), "{}", err);
+    // The other entries are unaffected.
+    assert_eq!(
+        extract(&mut archive, "docs/page.html").unwrap(),
+        text("01-page.gz")
+    );
+}
+
+#[test]
+fn unsupported_method() {
+    let mut archive = archive(include_bytes!("../data/zip/bzip2.zip"));
+    assert_eq!(archive.entries()[0].method, ZipMethod::Other(12));
+    match archive.entry_reader(0) {
+        Err(Error::UnsupportedEntry { name, .. }) => assert_eq!(name, "hello.txt"),
+        Err(err) => panic!("unexpected error: {}", err),
+        Ok(_) => panic!("expected Err, got Ok"),
+    }
+}
+
+#[test]
+fn invalid_archive() {
+    let data: &[u8] = include_bytes!("../data/ok/01-page.gz");
+    for data in [data, &ARCHIVE[..ARCHIVE.len() - 10], b""] {
+        match ZipArchive::new(Cursor::new(data)) {
+            Err(Error::InvalidArchive(_)) => {}
+            Err(err) => panic!("unexpected error: {}", err),
+            Ok(_) => panic!("expected Err, got Ok"),
+        }
+    }
+}
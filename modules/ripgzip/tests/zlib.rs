@@ -0,0 +1,260 @@
+mod common;
+
+use common::text;
+
+use std::io::{Read, Write};
+use std::process::{Command, Stdio};
+use std::thread;
+
+use ripgzip::{DeflateDecoder, DeflateEncoder, Level, ZlibDecoder, ZlibEncoder};
+
+const TO_BE: &[u8] = b"to be or not to be, that is the question: to be or not to be\n";
+
+/// `zlib.compress(TO_BE)` as produced by Python.
+const TO_BE_ZLIB: &[u8] = &[
+    120, 156, 43, 201, 87, 72, 74, 85, 200, 47, 82, 200, 203, 47, 81, 40, 1, 113, 116, 20, 74, 50,
+    18, 75, 20, 50, 139, 129, 116, 170, 66, 97, 105, 106, 113, 73, 102, 126, 158, 21, 68, 18, 69,
+    37, 23, 0, 134, 153, 20, 163,
+];
+
+/// A JSON message, and a dictionary made of what such messages have in common.
+const MESSAGE: &[u8] = br#"{"id": 1729, "method": "get_block", "params": {"hash": "00ab"}}"#;
+const DICTIONARY: &[u8] = br#"{"id": , "method": "get_block", "params": {"hash": "#;
+
+fn python_zlib(data: Vec<u8>, wbits: i32) -> Vec<u8> {
+    python(
+        &format!("zlib.decompress(sys.stdin.buffer.read(), {})", wbits),
+        data,
+    )
+}
+
+/// Run `expression` with `sys` and `zlib` imported, writing its result to the output.
+fn python(expression: &str, data: Vec<u8>) -> Vec<u8> {
+    let script = format!("import sys, zlib; sys.stdout.buffer.write({})", expression);
+    let mut child = Command::new("python3")
+        .args(["-c", &script])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("failed to run python3");
+    let mut stdin = child.stdin.take().unwrap();
+    let writer = thread::spawn(move || stdin.write_all(&data));
+    let mut output = Vec::new();
+    child
+        .stdout
+        .take()
+        .unwrap()
+        .read_to_end(&mut output)
+        .unwrap();
+    writer.join().unwrap().unwrap();
+    assert!(child.wait().unwrap().success(), "zlib rejected the stream");
+    output
+}
+
+fn hex(data: &[u8]) -> String {
+    data.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Decompress with zlib using `DICTIONARY` as the preset dictionary.
+fn python_zlib_with_dictionary(data: Vec<u8>, wbits: i32) -> Vec<u8> {
+    let expression = format!(
+        "zlib.decompressobj({}, zdict=bytes.fromhex('{}')).decompress(sys.stdin.buffer.read())",
+        wbits,
+        hex(DICTIONARY)
+    );
+    python(&expression, data)
+}
+
+/// Compress with zlib using `DICTIONARY` as the preset dictionary.
+fn python_compress_with_dictionary(data: Vec<u8>, wbits: i32) -> Vec<u8> {
+    let expression = format!(
+        "(lambda c: c.compress(sys.stdin.buffer.read()) + c.flush())\
+         (zlib.compressobj(9, zlib.DEFLATED, {}, zdict=bytes.fromhex('{}')))",
+        wbits,
+        hex(DICTIONARY)
+    );
+    python(&expression, data)
+}
+
+#[cfg(test)]
+mod zlib {
+    use super::*;
+
+    #[test]
+    fn decode_reference() {
+        let mut output = Vec::new();
+        ZlibDecoder::new(TO_BE_ZLIB)
+            .read_to_end(&mut output)
+            .unwrap();
+        assert_eq!(output, TO_BE);
+    }
+
+    #[test]
+    fn roundtrip() {
+        let data = text("01-page.gz");
+        for level in [0, 1, 6, 9] {
+            let mut encoder = ZlibEncoder::new(Vec::new(), Level::new(level));
+            encoder.write_all(&data).unwrap();
+            let compressed = encoder.finish().unwrap();
+            assert_eq!(python_zlib(compressed.clone(), 15), data);
+
+            let mut output = Vec::new();
+            ZlibDecoder::new(compressed.as_slice())
+                .read_to_end(&mut output)
+                .unwrap();
+            assert_eq!(output, data);
+        }
+    }
+
+    #[test]
+    fn empty() {
+        let compressed = ZlibEncoder::new(Vec::new(), Level::default())
+            .finish()
+            .unwrap();
+        assert_eq!(python_zlib(compressed.clone(), 15), b"");
+        let mut output = Vec::new();
+        ZlibDecoder::new(compressed.as_slice())
+            .read_to_end(&mut output)
+            .unwrap();
+        assert!(output.is_empty());
+    }
+
+    #[test]
+    fn corrupted_checksum() {
+        let mut data = TO_BE_ZLIB.to_vec();
+        *data.last_mut().unwrap() ^= 1;
+        let err = ZlibDecoder::new(data.as_slice())
+            .read_to_end(&mut Vec::new())
+            .unwrap_err();
+        assert!(err.to_string().contains("adler32 check failed"));
+    }
+
+    #[test]
+    fn preset_dictionary() {
+        let data = [0x78, 0xbb, 0, 0, 0, 1, 3, 0];
+        let err = ZlibDecoder::new(&data[..])
+            .read_to_end(&mut Vec::new())
+            .unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("preset dictionary 0x00000001 is required"));
+    }
+
+    #[test]
+    fn dictionary_roundtrip() {
+        let mut plain = ZlibEncoder::new(Vec::new(), Level::default());
+        plain.write_all(MESSAGE).unwrap();
+        let plain = plain.finish().unwrap();
+
+        let mut encoder = ZlibEncoder::with_dictionary(Vec::new(), Level::default(), DICTIONARY);
+        encoder.write_all(MESSAGE).unwrap();
+        let compressed = encoder.finish().unwrap();
+        assert!(compressed.len() < plain.len() / 2);
+        assert_eq!(python_zlib_with_dictionary(compressed.clone(), 15), MESSAGE);
+
+        let mut output = Vec::new();
+        ZlibDecoder::with_dictionary(compressed.as_slice(), DICTIONARY)
+            .read_to_end(&mut output)
+            .unwrap();
+        assert_eq!(output, MESSAGE);
+
+        // A dictionary is ignored by streams that do not ask for one.
+        let mut output = Vec::new();
+        ZlibDecoder::with_dictionary(plain.as_slice(), DICTIONARY)
+            .read_to_end(&mut output)
+            .unwrap();
+        assert_eq!(output, MESSAGE);
+    }
+
+    #[test]
+    fn dictionary_from_zlib() {
+        let compressed = python_compress_with_dictionary(MESSAGE.to_vec(), 15);
+        let mut output = Vec::new();
+        ZlibDecoder::with_dictionary(compressed.as_slice(), DICTIONARY)
+            .read_to_end(&mut output)
+            .unwrap();
+        assert_eq!(output, MESSAGE);
+    }
+
+    #[test]
+    fn wrong_dictionary() {
+        let compressed = python_compress_with_dictionary(MESSAGE.to_vec(), 15);
+        for mut decoder in [
+            ZlibDecoder::new(compressed.as_slice()),
+            ZlibDecoder::with_dictionary(compressed.as_slice(), b"something else"),
+        ] {
+            let err = decoder.read_to_end(&mut Vec::new()).unwrap_err();
+            assert!(err.to_string().contains("is required"));
+        }
+    }
+}
+
+#[cfg(test)]
+mod deflate {
+    use super::*;
+
+    #[test]
+    fn decode_reference() {
+        let raw = &TO_BE_ZLIB[2..TO_BE_ZLIB.len() - 4];
+        let mut output = Vec::new();
+        DeflateDecoder::new(raw).read_to_end(&mut output).unwrap();
+        assert_eq!(output, TO_BE);
+    }
+
+    #[test]
+    fn roundtrip() {
+        let data = text("01-page.gz");
+        let mut encoder = DeflateEncoder::new(Vec::new(), Level::default());
+        for chunk in data.chunks(1000) {
+            encoder.write_all(chunk).unwrap();
+        }
+        let compressed = encoder.finish().unwrap();
+        assert_eq!(python_zlib(compressed.clone(), -15), data);
+
+        let mut output = Vec::new();
+        DeflateDecoder::new(compressed.as_slice())
+            .read_to_end(&mut output)
+            .unwrap();
+        assert_eq!(output, data);
+    }
+
+    #[test]
+    fn dictionary_roundtrip() {
+        let mut encoder = DeflateEncoder::with_dictionary(Vec::new(), Level::default(), DICTIONARY);
+        encoder.write_all(MESSAGE).unwrap();
+        let compressed = encoder.finish().unwrap();
+        assert_eq!(
+            python_zlib_with_dictionary(compressed.clone(), -15),
+            MESSAGE
+        );
+
+        let mut output = Vec::new();
+        DeflateDecoder::with_dictionary(compressed.as_slice(), DICTIONARY)
+            .read_to_end(&mut output)
+            .unwrap();
+        assert_eq!(output, MESSAGE);
+    }
+
+    #[test]
+    fn dictionary_from_zlib() {
+        let compressed = python_compress_with_dictionary(MESSAGE.to_vec(), -15);
+        let mut output = Vec::new();
+        DeflateDecoder::with_dictionary(compressed.as_slice(), DICTIONARY)
+            .read_to_end(&mut output)
+            .unwrap();
+        assert_eq!(output, MESSAGE);
+    }
+
+    #[test]
+    fn trailing_data_is_left() {
+        let mut input = TO_BE_ZLIB[2..TO_BE_ZLIB.len() - 4].to_vec();
+        input.extend_from_slice(b"tail");
+        let mut reader = input.as_slice();
+        let mut output = Vec::new();
+        DeflateDecoder::new(&mut reader)
+            .read_to_end(&mut output)
+            .unwrap();
+        assert_eq!(output, TO_BE);
+        assert_eq!(reader, b"tail");
+    }
+}